@@ -0,0 +1,87 @@
+// Copyright 2023 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Core-SNP-filter
+
+// This file is part of Core-SNP-filter. Core-SNP-filter is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any later version.
+// Core-SNP-filter is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU General Public License for more details. You should have received a copy of the GNU
+// General Public License along with Core-SNP-filter. If not, see <http://www.gnu.org/licenses/>.
+
+//! Benchmarks for base counting (bitvectors_and_counts) and the whole filtering pass
+//! (drop_columns) on synthetic alignments. Run with `cargo bench`.
+
+use coresnpfilter::{bitvectors_and_counts, drop_columns, Alignment, AlignmentRecord,
+                    FilterOptions, Progress};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+
+/// The alignment sizes to benchmark, as (sequences, length). The large one is slow enough that
+/// regressions at scale show up, but small enough to hold in memory.
+const SIZES: [(usize, usize); 2] = [(10, 10_000), (100, 1_000_000)];
+
+
+/// Makes a random alignment which looks a bit like a real one: mostly the same as a reference
+/// sequence, with some SNPs, gaps and Ns. The same dimensions always give the same alignment.
+fn synthetic_alignment(seq_count: usize, length: usize) -> Vec<AlignmentRecord> {
+    let mut rng = StdRng::seed_from_u64(0);
+    let reference: Vec<u8> = (0..length).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect();
+    (0..seq_count).map(|i| {
+        let seq = reference.iter().map(|&base| match rng.gen_range(0..100) {
+            0 => b"ACGT"[rng.gen_range(0..4)],
+            1 => b'-',
+            2 => b'N',
+            _ => base,
+        }).collect();
+        AlignmentRecord { header: format!("seq_{}", i + 1), seq }
+    }).collect()
+}
+
+
+fn bench_counting(c: &mut Criterion) {
+    let mut group = c.benchmark_group("bitvectors_and_counts");
+    group.sample_size(10);
+    for (seq_count, length) in SIZES {
+        let alignment = Alignment::InMemory(synthetic_alignment(seq_count, length));
+        group.throughput(Throughput::Bytes((seq_count * length) as u64));
+        group.bench_function(BenchmarkId::from_parameter(format!("{}x{}", seq_count, length)),
+                             |b| b.iter(|| {
+            bitvectors_and_counts(&alignment, length, false, None, None, false, false, "-.",
+                                  &Progress::hidden()).unwrap()
+        }));
+    }
+    group.finish();
+}
+
+
+fn bench_filtering(c: &mut Criterion) {
+    let mut group = c.benchmark_group("drop_columns");
+    group.sample_size(10);
+    let dir = tempfile::tempdir().unwrap();
+    for (seq_count, length) in SIZES {
+        let path = dir.path().join(format!("{}x{}.fasta", seq_count, length));
+        let mut file = BufWriter::new(File::create(&path).unwrap());
+        for record in synthetic_alignment(seq_count, length) {
+            writeln!(file, ">{}", record.header).unwrap();
+            file.write_all(&record.seq).unwrap();
+            writeln!(file).unwrap();
+        }
+        file.flush().unwrap();
+        let options = FilterOptions { core: 0.95, exclude_invariant: true, ..Default::default() };
+        group.throughput(Throughput::Bytes((seq_count * length) as u64));
+        group.bench_function(BenchmarkId::from_parameter(format!("{}x{}", seq_count, length)),
+                             |b| b.iter(|| {
+            drop_columns(&path, &options, &mut io::sink()).unwrap()
+        }));
+    }
+    group.finish();
+}
+
+
+criterion_group!(benches, bench_counting, bench_filtering);
+criterion_main!(benches);
@@ -0,0 +1,27 @@
+// Copyright 2023 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Core-SNP-filter
+
+// This file is part of Core-SNP-filter. Core-SNP-filter is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any later version.
+// Core-SNP-filter is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU General Public License for more details. You should have received a copy of the GNU
+// General Public License along with Core-SNP-filter. If not, see <http://www.gnu.org/licenses/>.
+
+//! Runs arbitrary bytes through the whole filtering path (from reading the file to writing the
+//! filtered alignment). Any input is fine as long as it gives a result instead of a panic.
+
+#![no_main]
+
+use coresnpfilter::{drop_columns, FilterOptions};
+use libfuzzer_sys::fuzz_target;
+use std::io;
+
+fuzz_target!(|data: &[u8]| {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("input.fasta");
+    std::fs::write(&path, data).unwrap();
+    let options = FilterOptions { core: 0.5, exclude_invariant: true, ..Default::default() };
+    let _ = drop_columns(&path, &options, &mut io::sink());
+});
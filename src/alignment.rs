@@ -0,0 +1,346 @@
+// Copyright 2023 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Core-SNP-filter
+
+// This file is part of Core-SNP-filter. Core-SNP-filter is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any later version.
+// Core-SNP-filter is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU General Public License for more details. You should have received a copy of the GNU
+// General Public License along with Core-SNP-filter. If not, see <http://www.gnu.org/licenses/>.
+
+use seq_io::fasta::{Reader, Record, RefRecord};
+use std::fs::metadata;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use crate::clustal;
+use crate::error::Error;
+use crate::filter::InputFormat;
+use crate::misc;
+use crate::phylip;
+use crate::samples::SampleSelection;
+
+
+/// A single sequence of the alignment, held in memory.
+pub struct AlignmentRecord {
+    pub header: String,
+    pub seq: Vec<u8>,
+}
+
+
+/// The input alignment. If it fits in memory, it's loaded once and kept there. Otherwise it stays
+/// on disk and is read again for each pass, which uses very little memory but means decompressing
+/// gzipped files more than once. If only some samples are selected, the others are left out of
+/// memory or skipped on each read. An alignment on disk also records whether it's memory-mapped
+/// when read (--mmap).
+pub enum Alignment {
+    InMemory(Vec<AlignmentRecord>),
+    OnDisk(PathBuf, Option<SampleSelection>, bool),
+}
+
+
+impl Alignment {
+    /// Loads the alignment into memory if it fits in max_memory (in bytes), otherwise leaves it on
+    /// disk. A max_memory of zero means the alignment always stays on disk. A filename of "-"
+    /// reads from stdin, which must fit in memory since it can only be read once. If selection is
+    /// given, only the selected sequences are used. If mmap is true, an uncompressed file is
+    /// memory-mapped when read.
+    pub fn load(filename: &Path, max_memory: usize, selection: Option<SampleSelection>,
+                mmap: bool) -> Result<Alignment, Error> {
+        if is_stdin(filename) {
+            return load_stream(io::stdin(), max_memory, selection.as_ref());
+        }
+        if max_memory == 0 {
+            return Ok(Alignment::OnDisk(filename.to_path_buf(), selection, mmap));
+        }
+        let fasta_reader = misc::open_fasta_file(filename, mmap)?;
+
+        // Uncompressed files can be checked before reading anything. Compressed files must be
+        // read to find out how big they are.
+        let file_size = metadata(filename).map(|m| m.len()).unwrap_or(0);
+        let compression = misc::detect_compression(filename)?;
+        if compression == misc::CompressionFormat::Uncompressed && file_size > max_memory as u64 {
+            return Ok(Alignment::OnDisk(filename.to_path_buf(), selection, mmap));
+        }
+        Ok(match read_records(fasta_reader, max_memory, filename, selection.as_ref())? {
+            Some(records) => Alignment::InMemory(records),
+            None => Alignment::OnDisk(filename.to_path_buf(), selection, mmap),
+        })
+    }
+
+    /// Loads the alignment with the loader for its format: load for FASTA, otherwise load_phylip
+    /// or load_clustal (which ignore max_memory and mmap).
+    pub fn load_format(filename: &Path, format: InputFormat, max_memory: usize,
+                       selection: Option<SampleSelection>, mmap: bool)
+            -> Result<Alignment, Error> {
+        match format {
+            InputFormat::Fasta => Alignment::load(filename, max_memory, selection, mmap),
+            InputFormat::Phylip => Alignment::load_phylip(filename, selection.as_ref()),
+            InputFormat::Clustal => Alignment::load_clustal(filename, selection.as_ref()),
+        }
+    }
+
+    /// Loads a relaxed PHYLIP alignment, which is always held in memory since interleaved files
+    /// can't be read one sequence at a time. A filename of "-" reads from stdin. If selection is
+    /// given, only the selected sequences are kept.
+    pub fn load_phylip(filename: &Path, selection: Option<&SampleSelection>)
+            -> Result<Alignment, Error> {
+        let (reader, name) = open_whole(filename)?;
+        Ok(selected_in_memory(phylip::read_phylip(reader, name)?, selection))
+    }
+
+    /// Loads a Clustal alignment, which is always held in memory since its sequences are split
+    /// across blocks. A filename of "-" reads from stdin. If selection is given, only the
+    /// selected sequences are kept.
+    pub fn load_clustal(filename: &Path, selection: Option<&SampleSelection>)
+            -> Result<Alignment, Error> {
+        let (reader, name) = open_whole(filename)?;
+        Ok(selected_in_memory(clustal::read_clustal(reader, name)?, selection))
+    }
+
+    /// Calls f with the header and sequence of each record, in file order, stopping at the first
+    /// error (from reading the alignment or from f).
+    pub fn for_each_record<F>(&self, mut f: F) -> Result<(), Error>
+            where F: FnMut(&str, &[u8]) -> Result<(), Error> {
+        match self {
+            Alignment::InMemory(records) => {
+                for record in records {
+                    f(&record.header, &record.seq)?;
+                }
+            }
+            Alignment::OnDisk(filename, selection, mmap) => {
+                let mut fasta_reader = misc::open_fasta_file(filename, *mmap)?;
+                while let Some(record) = fasta_reader.next() {
+                    let record = misc::check_record(record, filename)?;
+                    let header = get_fasta_header(&record, filename)?;
+                    if is_selected(selection.as_ref(), &header) {
+                        f(&header, &misc::remove_carriage_returns(record.full_seq()))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the length of the first sequence, which all other sequences must match.
+    pub fn first_seq_length(&self) -> Result<usize, Error> {
+        match self {
+            Alignment::InMemory(records) => match records.first() {
+                Some(record) => misc::check_seq_length_not_zero(record.seq.len(),
+                                                                Path::new("input")),
+                None => Err(Error::EmptyInput("empty alignment: input contains no sequences"
+                                              .to_string())),
+            },
+            Alignment::OnDisk(filename, None, _) => misc::get_first_fasta_seq_length(filename),
+            Alignment::OnDisk(filename, Some(selection), mmap) => {
+                let mut fasta_reader = misc::open_fasta_file(filename, *mmap)?;
+                while let Some(record) = fasta_reader.next() {
+                    let record = misc::check_record(record, filename)?;
+                    if selection.is_selected(&get_fasta_header(&record, filename)?) {
+                        let seq = misc::remove_carriage_returns(record.full_seq());
+                        return misc::check_seq_length_not_zero(seq.len(), filename);
+                    }
+                }
+                Err(Error::EmptyInput(format!("empty alignment: none of the sequences in {} \
+                                               were selected", filename.display())))
+            }
+        }
+    }
+}
+
+
+pub fn is_stdin(filename: &Path) -> bool {
+    filename == Path::new("-")
+}
+
+
+/// Loads an alignment from a stream which can only be read once (stdin, except in tests), so it
+/// must fit in memory. Like files, it can be gzipped or bzip2-compressed.
+fn load_stream<R: Read + 'static>(reader: R, max_memory: usize,
+                                  selection: Option<&SampleSelection>)
+        -> Result<Alignment, Error> {
+    let name = Path::new("stdin");
+    let reader = misc::decompress_stream(reader, name)?;
+    match read_records(Reader::new(reader), max_memory, name, selection)? {
+        Some(records) => Ok(Alignment::InMemory(records)),
+        None => Err(Error::BadArguments("input from stdin must fit in memory (see --max_memory)"
+                                        .to_string())),
+    }
+}
+
+
+fn is_selected(selection: Option<&SampleSelection>, header: &str) -> bool {
+    selection.map_or(true, |s| s.is_selected(header))
+}
+
+
+/// Opens a file (or stdin for "-") which will be read all at once, along with its name for error
+/// messages.
+fn open_whole(filename: &Path) -> Result<(Box<dyn BufRead>, &Path), Error> {
+    if is_stdin(filename) {
+        let name = Path::new("stdin");
+        Ok((Box::new(BufReader::new(misc::decompress_stream(io::stdin(), name)?)), name))
+    } else {
+        Ok((Box::new(BufReader::new(misc::open_decompressed(filename)?)), filename))
+    }
+}
+
+
+fn selected_in_memory(records: Vec<AlignmentRecord>, selection: Option<&SampleSelection>)
+        -> Alignment {
+    Alignment::InMemory(records.into_iter().filter(|r| is_selected(selection, &r.header))
+                        .collect())
+}
+
+
+/// Reads all selected records into memory, giving up (and returning None) if their total size
+/// exceeds max_memory bytes.
+fn read_records<R: Read>(mut fasta_reader: Reader<R>, max_memory: usize, filename: &Path,
+                         selection: Option<&SampleSelection>)
+        -> Result<Option<Vec<AlignmentRecord>>, Error> {
+    let mut records = Vec::new();
+    let mut total_size = 0;
+    while let Some(record) = fasta_reader.next() {
+        let record = misc::check_record(record, filename)?;
+        let header = get_fasta_header(&record, filename)?;
+        if !is_selected(selection, &header) {
+            continue;
+        }
+        let seq = misc::remove_carriage_returns(record.full_seq()).into_owned();
+        total_size += header.len() + seq.len();
+        if total_size > max_memory {
+            return Ok(None);
+        }
+        records.push(AlignmentRecord { header, seq });
+    }
+    Ok(Some(records))
+}
+
+
+fn get_fasta_header(record: &RefRecord, filename: &Path) -> Result<String, Error> {
+    let not_utf8 = |_| Error::MalformedFasta(format!("{} has a header which isn't valid UTF-8",
+                                                     filename.display()));
+    let mut header = String::new();
+    header += record.id().map_err(not_utf8)?;
+    if let Some(x) = record.desc() {
+        header += &format!(" {}", x.map_err(not_utf8)?);
+    }
+    if header.ends_with('\r') {
+        header.pop();
+    }
+    Ok(header)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use tempfile::{TempDir,tempdir};
+    use super::*;
+
+    fn make_test_file(contents: &str) -> (PathBuf, TempDir) {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("test.fasta");
+        let mut file = File::create(&file_path).unwrap();
+        write!(file, "{}", contents).unwrap();
+        (file_path, dir)
+    }
+
+    fn make_gzipped_test_file(contents: &str) -> (PathBuf, TempDir) {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("test.fasta.gz");
+        let mut file = File::create(&file_path).unwrap();
+        let mut e = GzEncoder::new(Vec::new(), Compression::default());
+        e.write_all(contents.as_bytes()).unwrap();
+        let _ = file.write_all(&e.finish().unwrap());
+        (file_path, dir)
+    }
+
+    fn get_records(alignment: &Alignment) -> Vec<(String, String)> {
+        let mut records = Vec::new();
+        alignment.for_each_record(|header, seq| {
+            records.push((header.to_string(), String::from_utf8(seq.to_vec()).unwrap()));
+            Ok(())
+        }).unwrap();
+        records
+    }
+
+    #[test]
+    fn test_load_1() {
+        // Fits in memory.
+        let (path, _dir) = make_test_file(">seq_1 info\nACGAT\n>seq_2\nGG\nT-A\n");
+        let alignment = Alignment::load(&path, 1000, None, false).unwrap();
+        assert!(matches!(alignment, Alignment::InMemory(_)));
+        assert_eq!(alignment.first_seq_length().unwrap(), 5);
+        assert_eq!(get_records(&alignment), vec![("seq_1 info".to_string(), "ACGAT".to_string()),
+                                                 ("seq_2".to_string(), "GGT-A".to_string())]);
+    }
+
+    #[test]
+    fn test_load_2() {
+        // Too big for memory, so it stays on disk but gives the same records.
+        let (path, _dir) = make_test_file(">seq_1 info\nACGAT\n>seq_2\nGG\nT-A\n");
+        let alignment = Alignment::load(&path, 10, None, false).unwrap();
+        assert!(matches!(alignment, Alignment::OnDisk(..)));
+        assert_eq!(alignment.first_seq_length().unwrap(), 5);
+        assert_eq!(get_records(&alignment), vec![("seq_1 info".to_string(), "ACGAT".to_string()),
+                                                 ("seq_2".to_string(), "GGT-A".to_string())]);
+    }
+
+    #[test]
+    fn test_load_3() {
+        // A gzipped file can only be sized by reading it.
+        let (path, _dir) = make_gzipped_test_file(">seq_1\nACGAT\n>seq_2\nGGT-A\n");
+        assert!(matches!(Alignment::load(&path, 1000, None, false), Ok(Alignment::InMemory(_))));
+        assert!(matches!(Alignment::load(&path, 11, None, false), Ok(Alignment::OnDisk(..))));
+    }
+
+    #[test]
+    fn test_load_4() {
+        // A max_memory of zero always leaves the alignment on disk.
+        let (path, _dir) = make_test_file(">seq_1\nACGAT\n");
+        assert!(matches!(Alignment::load(&path, 0, None, false), Ok(Alignment::OnDisk(..))));
+    }
+
+    #[test]
+    fn test_load_5() {
+        // Header-only input is an empty alignment, whether in memory or on disk.
+        let (path, _dir) = make_test_file(">seq_1\n>seq_2\n");
+        for max_memory in [0, 1000] {
+            let alignment = Alignment::load(&path, max_memory, None, false).unwrap();
+            assert!(matches!(alignment.first_seq_length(), Err(Error::EmptyInput(_))));
+        }
+    }
+
+    #[test]
+    fn test_load_stream() {
+        // Input from stdin is decompressed if necessary, like a file.
+        let contents = ">seq_1 info\nACGAT\n>seq_2\nGG\nT-A\n";
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(contents.as_bytes()).unwrap();
+        let gzipped = encoder.finish().unwrap();
+        let expected = vec![("seq_1 info".to_string(), "ACGAT".to_string()),
+                            ("seq_2".to_string(), "GGT-A".to_string())];
+        for bytes in [contents.as_bytes().to_vec(), gzipped] {
+            let alignment = load_stream(io::Cursor::new(bytes), 1000, None).unwrap();
+            assert_eq!(get_records(&alignment), expected);
+        }
+
+        // Input shorter than the magic numbers still works, and too much input is an error.
+        let alignment = load_stream(io::Cursor::new(b">a".to_vec()), 1000, None).unwrap();
+        assert!(matches!(alignment.first_seq_length(), Err(Error::EmptyInput(_))));
+        assert!(matches!(load_stream(io::Cursor::new(contents.as_bytes().to_vec()), 5, None),
+                         Err(Error::BadArguments(_))));
+    }
+
+    #[test]
+    fn test_is_stdin() {
+        assert!(is_stdin(Path::new("-")));
+        assert!(!is_stdin(Path::new("alignment.fasta")));
+    }
+}
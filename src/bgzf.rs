@@ -0,0 +1,171 @@
+// Copyright 2023 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Core-SNP-filter
+
+// This file is part of Core-SNP-filter. Core-SNP-filter is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any later version.
+// Core-SNP-filter is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU General Public License for more details. You should have received a copy of the GNU
+// General Public License along with Core-SNP-filter. If not, see <http://www.gnu.org/licenses/>.
+
+use flate2::Compression;
+use flate2::Crc;
+use flate2::write::DeflateEncoder;
+use std::io::{self, Write};
+
+
+/// The most data put in one block, the same as bgzip uses. This leaves room for the compressed
+/// data to be a little bigger than the input (e.g. for random bases) without exceeding the 64 kB
+/// block limit.
+const MAX_BLOCK_DATA: usize = 0xff00;
+
+/// The largest allowed block, including its header and footer.
+const MAX_BLOCK_SIZE: usize = 0x10000;
+
+/// An empty block which marks the end of a BGZF file.
+const EOF_BLOCK: [u8; 28] = [0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06,
+                             0x00, 0x42, 0x43, 0x02, 0x00, 0x1b, 0x00, 0x03, 0x00, 0x00, 0x00,
+                             0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+
+
+/// Writes BGZF (blocked gzip, as made by bgzip), which is a series of gzip members of up to 64 kB
+/// each, with each member's size stored in its header. Any gzip reader can decompress it, and tools
+/// like samtools faidx can index it. Data is buffered into blocks, so the underlying writer doesn't
+/// need its own buffering. finish must be called to write the last block and the end-of-file
+/// marker.
+pub struct BgzfWriter<W: Write> {
+    inner: W,
+    buffer: Vec<u8>,
+}
+
+
+impl<W: Write> BgzfWriter<W> {
+    pub fn new(inner: W) -> BgzfWriter<W> {
+        BgzfWriter { inner, buffer: Vec::with_capacity(MAX_BLOCK_DATA) }
+    }
+
+    /// Writes any buffered data and the end-of-file marker, returning the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_block()?;
+        self.inner.write_all(&EOF_BLOCK)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            write_block(&mut self.inner, &self.buffer)?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+}
+
+
+impl<W: Write> Write for BgzfWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let size = buf.len().min(MAX_BLOCK_DATA - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..size]);
+        if self.buffer.len() == MAX_BLOCK_DATA {
+            self.write_block()?;
+        }
+        Ok(size)
+    }
+
+    /// Only flushes complete blocks, since flushing a partial block would make the file bigger
+    /// without letting anything read it sooner.
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+
+/// Compresses the data into one block. Data which doesn't compress well enough to fit is split in
+/// two, though that shouldn't happen with the usual block size.
+fn write_block<W: Write>(out: &mut W, data: &[u8]) -> io::Result<()> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    let compressed = encoder.finish()?;
+    let block_size = compressed.len() + 26;
+    if block_size > MAX_BLOCK_SIZE {
+        let (first, second) = data.split_at(data.len() / 2);
+        write_block(out, first)?;
+        return write_block(out, second);
+    }
+    let mut crc = Crc::new();
+    crc.update(data);
+
+    // A gzip header with an extra field (BC) holding the block size minus one.
+    out.write_all(&[0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00,
+                    0x42, 0x43, 0x02, 0x00])?;
+    out.write_all(&((block_size - 1) as u16).to_le_bytes())?;
+    out.write_all(&compressed)?;
+    out.write_all(&crc.sum().to_le_bytes())?;
+    out.write_all(&(data.len() as u32).to_le_bytes())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use flate2::read::MultiGzDecoder;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+    use std::io::Read;
+    use super::*;
+
+    fn decompress(data: &[u8]) -> Vec<u8> {
+        let mut decompressed = Vec::new();
+        MultiGzDecoder::new(data).read_to_end(&mut decompressed).unwrap();
+        decompressed
+    }
+
+    /// Returns the size of each block, following the sizes stored in their headers.
+    fn block_sizes(data: &[u8]) -> Vec<usize> {
+        let mut sizes = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            assert_eq!(&data[pos..pos + 4], &[0x1f, 0x8b, 0x08, 0x04]);
+            assert_eq!(&data[pos + 12..pos + 14], b"BC");
+            let size = u16::from_le_bytes([data[pos + 16], data[pos + 17]]) as usize + 1;
+            sizes.push(size);
+            pos += size;
+        }
+        assert_eq!(pos, data.len());
+        sizes
+    }
+
+    #[test]
+    fn test_bgzf_writer_empty() {
+        let data = BgzfWriter::new(Vec::new()).finish().unwrap();
+        assert_eq!(data, EOF_BLOCK.to_vec());
+        assert!(decompress(&data).is_empty());
+    }
+
+    #[test]
+    fn test_bgzf_writer_small() {
+        let mut writer = BgzfWriter::new(Vec::new());
+        writer.write_all(b">seq_1\nACGT\n").unwrap();
+        writer.write_all(b">seq_2\nAC-T\n").unwrap();
+        let data = writer.finish().unwrap();
+        assert_eq!(block_sizes(&data).len(), 2);
+        assert!(data.ends_with(&EOF_BLOCK));
+        assert_eq!(decompress(&data), b">seq_1\nACGT\n>seq_2\nAC-T\n");
+    }
+
+    #[test]
+    fn test_bgzf_writer_large() {
+        // Random bases don't compress much, so this needs several full blocks.
+        let mut rng = StdRng::seed_from_u64(0);
+        let contents: Vec<u8> = (0..200_000).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect();
+        let mut writer = BgzfWriter::new(Vec::new());
+        for chunk in contents.chunks(10_000) {
+            writer.write_all(chunk).unwrap();
+        }
+        let data = writer.finish().unwrap();
+        let sizes = block_sizes(&data);
+        assert_eq!(sizes.len(), 5);
+        assert!(sizes.iter().all(|&size| size <= MAX_BLOCK_SIZE));
+        assert_eq!(decompress(&data), contents);
+    }
+}
@@ -0,0 +1,134 @@
+// Copyright 2023 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Core-SNP-filter
+
+// This file is part of Core-SNP-filter. Core-SNP-filter is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any later version.
+// Core-SNP-filter is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU General Public License for more details. You should have received a copy of the GNU
+// General Public License along with Core-SNP-filter. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::path::Path;
+
+use crate::alignment::AlignmentRecord;
+use crate::error::Error;
+
+
+/// Reads a Clustal alignment (e.g. from Clustal Omega or MUSCLE): a header line, then blocks of
+/// lines with a sequence name and a chunk of its bases, optionally followed by a running residue
+/// count. Each sequence is the concatenation of its chunks, in the order the names first appear.
+/// Blank lines and the conservation lines under each block (*, : and ., which start with
+/// whitespace) are skipped. Sequence lengths are checked later, like those of any alignment.
+pub fn read_clustal<R: BufRead>(reader: R, filename: &Path)
+        -> Result<Vec<AlignmentRecord>, Error> {
+    let malformed = |message: String| {
+        Error::MalformedFasta(format!("unable to read {} as Clustal\n{}", filename.display(),
+                                      message))
+    };
+    let mut lines = reader.lines()
+        .map(|line| line.map_err(|e| Error::Io(format!("unable to read {}\n{}",
+                                                       filename.display(), e))));
+    let first_line = match lines.next() {
+        Some(line) => line?,
+        None => return Err(Error::EmptyInput(format!("empty alignment: {} is empty",
+                                                     filename.display()))),
+    };
+    if !is_clustal_header(&first_line) {
+        return Err(malformed(format!("the first line should start with CLUSTAL: {}",
+                                     first_line.trim())));
+    }
+
+    let mut records: Vec<AlignmentRecord> = Vec::new();
+    let mut indices: HashMap<String, usize> = HashMap::new();
+    for line in lines {
+        let line = line?;
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with(char::is_whitespace) {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap();
+        let bases = match words.next() {
+            Some(bases) => bases,
+            None => return Err(malformed(format!("this line has a name but no bases: {}", line))),
+        };
+        let is_count = |word: &str| word.parse::<usize>().is_ok();
+        match (words.next(), words.next()) {
+            (None, _) => {}
+            (Some(count), None) if is_count(count) => {}
+            _ => return Err(malformed(format!("unexpected text after the bases: {}", line))),
+        }
+        let i = *indices.entry(name.to_string()).or_insert_with(|| {
+            records.push(AlignmentRecord { header: name.to_string(), seq: Vec::new() });
+            records.len() - 1
+        });
+        records[i].seq.extend_from_slice(bases.as_bytes());
+    }
+    if records.is_empty() {
+        return Err(Error::EmptyInput(format!("empty alignment: {} contains no sequences",
+                                             filename.display())));
+    }
+    Ok(records)
+}
+
+
+/// Clustal's own header starts with CLUSTAL (e.g. "CLUSTAL O(1.2.4) multiple sequence
+/// alignment"), but other aligners put their own name there (e.g. "MUSCLE (3.8) multiple
+/// sequence alignment").
+fn is_clustal_header(line: &str) -> bool {
+    line.starts_with("CLUSTAL") || line.contains("multiple sequence alignment")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(contents: &str) -> Result<Vec<(String, String)>, Error> {
+        let records = read_clustal(contents.as_bytes(), Path::new("test.aln"))?;
+        Ok(records.into_iter()
+            .map(|r| (r.header, String::from_utf8(r.seq).unwrap())).collect())
+    }
+
+    fn pairs(records: &[(&str, &str)]) -> Vec<(String, String)> {
+        records.iter().map(|(h, s)| (h.to_string(), s.to_string())).collect()
+    }
+
+    #[test]
+    fn test_read_clustal() {
+        let records = read("CLUSTAL O(1.2.4) multiple sequence alignment\n\n\n\
+                            seq_1      ACGT-A\n\
+                            seq_2      ACGTNA\n\
+                            seq_3      ACCT-A\n\
+                            \x20          ** * *\n\n\
+                            seq_1      CC\n\
+                            seq_2      CT\n\
+                            seq_3      CC\n\
+                            \x20          *\x20\n").unwrap();
+        assert_eq!(records, pairs(&[("seq_1", "ACGT-ACC"), ("seq_2", "ACGTNACT"),
+                                    ("seq_3", "ACCT-ACC")]));
+    }
+
+    #[test]
+    fn test_read_clustal_counts() {
+        // Residue counts after the bases, MUSCLE's header and Windows line endings are all okay.
+        let records = read("MUSCLE (3.8) multiple sequence alignment\r\n\r\n\
+                            a    AC-T 3\r\nb    ACGT 4\r\n\r\n\
+                            a    GG 5\r\nb    G- 5\r\n").unwrap();
+        assert_eq!(records, pairs(&[("a", "AC-TGG"), ("b", "ACGTG-")]));
+    }
+
+    #[test]
+    fn test_read_clustal_errors() {
+        assert!(matches!(read(""), Err(Error::EmptyInput(_))));
+        assert!(matches!(read("CLUSTAL W (1.83) multiple sequence alignment\n\n"),
+                         Err(Error::EmptyInput(_))));
+        assert!(matches!(read(">seq_1\nACGT\n"), Err(Error::MalformedFasta(_))));
+        assert!(matches!(read("CLUSTAL W\n\nseq_1\n"), Err(Error::MalformedFasta(_))));
+        assert!(matches!(read("CLUSTAL W\n\nseq_1 ACGT extra\n"), Err(Error::MalformedFasta(_))));
+        assert!(matches!(read("CLUSTAL W\n\nseq_1 ACGT 4 4\n"), Err(Error::MalformedFasta(_))));
+    }
+}
@@ -0,0 +1,86 @@
+// Copyright 2023 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Core-SNP-filter
+
+// This file is part of Core-SNP-filter. Core-SNP-filter is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any later version.
+// Core-SNP-filter is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU General Public License for more details. You should have received a copy of the GNU
+// General Public License along with Core-SNP-filter. If not, see <http://www.gnu.org/licenses/>.
+
+use std::fmt;
+use std::io;
+
+
+/// The ways a run can fail. Each kind of error has its own exit code (see exit_code), so scripts
+/// can tell them apart without parsing the message.
+#[derive(Debug)]
+pub enum Error {
+    /// Invalid or incompatible options, including bad files given with an option (e.g. a BED file
+    /// with a non-numeric coordinate)
+    BadArguments(String),
+    /// A file couldn't be opened, read or written
+    Io(String),
+    /// The alignment isn't valid FASTA (or whichever --input_format was given) or its sequences
+    /// aren't all the same length
+    MalformedFasta(String),
+    /// The alignment has no sequences, its sequences have no bases or it has fewer sequences than
+    /// --min_sequences
+    EmptyInput(String),
+    /// No sites passed the filters (only an error with --error_on_empty)
+    EmptyOutput(String),
+}
+
+
+impl Error {
+    /// Returns the process exit code for this kind of error. Usage errors use 2 to match clap.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::BadArguments(_) => 2,
+            Error::Io(_) => 3,
+            Error::MalformedFasta(_) => 4,
+            Error::EmptyInput(_) => 5,
+            Error::EmptyOutput(_) => 6,
+        }
+    }
+}
+
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::BadArguments(message) | Error::Io(message) |
+            Error::MalformedFasta(message) | Error::EmptyInput(message) |
+            Error::EmptyOutput(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+
+impl std::error::Error for Error {}
+
+
+/// Writes which fail after a file has been opened (e.g. a full disk or a closed stdout pipe) are
+/// reported as IO errors.
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e.to_string())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_code() {
+        let errors = [Error::BadArguments("a".to_string()), Error::Io("b".to_string()),
+                      Error::MalformedFasta("c".to_string()), Error::EmptyInput("d".to_string()),
+                      Error::EmptyOutput("e".to_string())];
+        let codes: Vec<i32> = errors.iter().map(|e| e.exit_code()).collect();
+        assert_eq!(codes, vec![2, 3, 4, 5, 6]);
+        assert_eq!(errors[2].to_string(), "c");
+    }
+}
@@ -0,0 +1,4926 @@
+// Copyright 2023 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Core-SNP-filter
+
+// This file is part of Core-SNP-filter. Core-SNP-filter is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any later version.
+// Core-SNP-filter is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU General Public License for more details. You should have received a copy of the GNU
+// General Public License along with Core-SNP-filter. If not, see <http://www.gnu.org/licenses/>.
+
+use bitvec::prelude::*;
+use clap::{Args, Command, FromArgMatches, ValueEnum};
+use log::{debug, info};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::alignment::{Alignment, AlignmentRecord};
+use crate::error::Error;
+use crate::misc;
+use crate::partitions;
+use crate::positions;
+use crate::progress::Progress;
+use crate::samples::{self, duplicate_names, seq_name, SampleSelection};
+
+
+/// The settings which control how sites are filtered and which extra files are made. These double
+/// as the command-line options, so each field's documentation is also its help text.
+#[derive(Args, Clone, Debug)]
+pub struct FilterOptions {
+    /// Restrict to core genome (0.0 to 1.0 or 0% to 100%, default = 0.0)
+    #[arg(short = 'c', long = "core", default_value = "0.0", hide_default_value = true,
+          value_parser = parse_core)]
+    pub core: f64,
+
+    /// Restrict to core genome using a number of sequences (if used with --core, sites must pass
+    /// both)
+    #[arg(long = "core_count")]
+    pub core_count: Option<usize>,
+
+    /// Only use the samples named in this file (one per line)
+    #[arg(long = "include_samples")]
+    pub include_samples: Option<PathBuf>,
+
+    /// Don't use the samples named in this file (one per line)
+    #[arg(long = "exclude_samples")]
+    pub exclude_samples: Option<PathBuf>,
+
+    /// Only use the samples whose names match this regex
+    #[arg(long = "include_regex")]
+    pub include_regex: Option<String>,
+
+    /// Don't use the samples whose names match this regex
+    #[arg(long = "exclude_regex")]
+    pub exclude_regex: Option<String>,
+
+    /// Only use this many randomly chosen sequences (after any other sample selection)
+    #[arg(long = "subsample")]
+    pub subsample: Option<usize>,
+
+    /// Random seed for --subsample (the same seed always chooses the same sequences)
+    #[arg(long = "seed", default_value = "0", requires = "subsample")]
+    pub seed: u64,
+
+    /// Leave out sequences with no bases at all, instead of quitting with an error
+    #[arg(long = "skip_empty")]
+    pub skip_empty: bool,
+
+    /// Save the header (name and description) of each sequence to this file, one per line
+    #[arg(long = "names")]
+    pub names: Option<PathBuf>,
+
+    /// Quit with an error (instead of a warning) if no sites pass the filters
+    #[arg(long = "error_on_empty")]
+    pub error_on_empty: bool,
+
+    /// Quit with an error if the alignment has fewer than this many sequences (after any sample
+    /// selection)
+    #[arg(long = "min_sequences", default_value = "1")]
+    pub min_sequences: usize,
+
+    /// Quit with an error (instead of a warning) if sequence names are repeated
+    #[arg(long = "strict_names")]
+    pub strict_names: bool,
+
+    /// Show how often each character occurs in the alignment, flagging any unexpected ones (i.e.
+    /// not a base, N or gap)
+    #[arg(long = "report_chars")]
+    pub report_chars: bool,
+
+    /// Quit with an error if the alignment contains unexpected characters (see --report_chars)
+    #[arg(long = "strict_chars")]
+    pub strict_chars: bool,
+
+    /// Exclude invariant sites
+    #[arg(short = 'e', long = "exclude_invariant")]
+    pub exclude_invariant: bool,
+
+    /// Exclude variable sites, i.e. only keep invariant sites (the opposite of -e)
+    #[arg(long = "invariant_only", conflicts_with_all = ["exclude_invariant", "pars_informative"])]
+    pub invariant_only: bool,
+
+    /// Create a table with per-site information
+    #[arg(short = 't', long = "table")]
+    pub table: Option<PathBuf>,
+
+    /// Add a column to the --table with the Shannon entropy (in bits) of each site's bases
+    #[arg(long = "with_entropy", requires = "table")]
+    pub with_entropy: bool,
+
+    /// Add a column to the --table with the number of different bases (or amino acids) at each site
+    #[arg(long = "with_allele_count", requires = "table")]
+    pub with_allele_count: bool,
+
+    /// Output invariant site counts (suitable for IQ-TREE -fconst) and nothing else
+    #[arg(short = 'C', long = "invariant_counts")]
+    pub invariant_counts: bool,
+
+    /// Save the counts of removed invariant A, C, G and T sites to this file, for RAxML's
+    /// ascertainment bias correction
+    #[arg(long = "raxml_info", requires = "exclude_invariant")]
+    pub raxml_info: Option<PathBuf>,
+
+    /// Create a table mapping output positions to input positions
+    #[arg(long = "coord_map")]
+    pub coord_map: Option<PathBuf>,
+
+    /// Partition file (RAxML-style, e.g. "DNA, gene_1 = 1-500") for the input alignment
+    #[arg(long = "partition_in", requires = "partition_out")]
+    pub partition_in: Option<PathBuf>,
+
+    /// Save the --partition_in partitions with their positions in the filtered alignment to this
+    /// file
+    #[arg(long = "partition_out", requires = "partition_in")]
+    pub partition_out: Option<PathBuf>,
+
+    /// Save a line of 1s (kept) and 0s (removed) for the input's sites to this file
+    #[arg(long = "mask_track")]
+    pub mask_track: Option<PathBuf>,
+
+    /// Remove sites which fall in the intervals of this BED file
+    #[arg(long = "mask_bed")]
+    pub mask_bed: Option<PathBuf>,
+
+    /// Only use BED intervals with this chromosome name (default: use all intervals)
+    #[arg(long = "mask_chrom", requires = "mask_bed")]
+    pub mask_chrom: Option<String>,
+
+    /// Remove the sites at the positions of this VCF's records (e.g. known homoplasic sites)
+    #[arg(long = "mask_from_vcf")]
+    pub mask_from_vcf: Option<PathBuf>,
+
+    /// Always keep the sites in this file (1-based positions, one per line), overriding all
+    /// other filters
+    #[arg(long = "keep_positions")]
+    pub keep_positions: Option<PathBuf>,
+
+    /// Only use the sites at the positions of this VCF's records (e.g. from snp-sites)
+    #[arg(long = "keep_from_vcf")]
+    pub keep_from_vcf: Option<PathBuf>,
+
+    /// Load the alignment into memory (so it's only read once) if it is no bigger than this many
+    /// gigabytes, otherwise read it from disk for each pass (0 = always read from disk)
+    #[arg(long = "max_memory", default_value = "1.0")]
+    pub max_memory: f64,
+
+    /// Memory-map uncompressed alignments which are read from disk, which can make each pass
+    /// faster
+    #[arg(long = "mmap")]
+    pub mmap: bool,
+
+    /// Show a running count of the sequences read in each pass over the alignment (only when
+    /// stderr is a terminal)
+    #[arg(long = "progress")]
+    pub progress: bool,
+
+    /// Format of the input alignment
+    #[arg(long = "input_format", value_enum, default_value = "fasta")]
+    pub input_format: InputFormat,
+
+    /// Whether the alignment is of DNA (A, C, G and T), RNA (U instead of T) or protein (the 20
+    /// amino acids)
+    #[arg(long = "alphabet", value_enum, default_value = "dna")]
+    pub alphabet: Alphabet,
+
+    /// Count IUPAC ambiguity codes (e.g. R = A or G) as partial evidence for their bases
+    #[arg(long = "iupac")]
+    pub iupac: bool,
+
+    /// Only these characters count as missing data, e.g. "-?N" (default: all non-ACGT
+    /// characters)
+    #[arg(long = "missing_chars")]
+    pub missing_chars: Option<String>,
+
+    /// Only these characters count as present, e.g. "ACGT" to make lowercase bases missing
+    /// (default: the bases and, with --iupac, ambiguity codes)
+    #[arg(long = "present_chars")]
+    pub present_chars: Option<String>,
+
+    /// Count lowercase characters (often used for masked or low-confidence bases) as missing
+    #[arg(long = "lowercase_missing")]
+    pub lowercase_missing: bool,
+
+    /// Treat N as a fifth state, so a site with both a base and an N is variable
+    #[arg(long = "n_is_allele")]
+    pub n_is_allele: bool,
+
+    /// Characters which are gaps, e.g. "-.*" (used for the gap counts, --count_gaps and
+    /// --max_gap_frac)
+    #[arg(long = "gap_chars", default_value = "-.")]
+    pub gap_chars: String,
+
+    /// Count gaps as present (not missing) for --core and --core_count, e.g. when gaps are real
+    /// indels
+    #[arg(long = "count_gaps")]
+    pub count_gaps: bool,
+
+    /// Remove sites where more than this fraction of the sequences have a gap (regardless of
+    /// --core)
+    #[arg(long = "max_gap_frac")]
+    pub max_gap_frac: Option<f64>,
+
+    /// Remove sites where more than this fraction of the sequences have an N or other ambiguous
+    /// character (not a base or gap, see --iupac)
+    #[arg(long = "max_ambiguous_frac")]
+    pub max_ambiguous_frac: Option<f64>,
+
+    /// Remove sites where no sequence has a base, e.g. all gaps and Ns (regardless of --core)
+    #[arg(long = "drop_empty_columns")]
+    pub drop_empty_columns: bool,
+
+    /// Decide which sites to keep and show the summary, but don't output the filtered alignment
+    #[arg(long = "count_only")]
+    pub count_only: bool,
+
+    /// Replace filtered sites with --mask_char instead of removing them, so the output keeps the
+    /// input's coordinates
+    #[arg(long = "soft_mask")]
+    pub soft_mask: bool,
+
+    /// Character used for filtered sites with --soft_mask
+    #[arg(long = "mask_char", default_value = "N", requires = "soft_mask")]
+    pub mask_char: char,
+
+    /// Output the kept bases in uppercase (default: keep the input's case)
+    #[arg(long = "uppercase")]
+    pub uppercase: bool,
+
+    /// Output an N in place of any kept character which isn't a base, N or gap (e.g. IUPAC codes)
+    #[arg(long = "ambiguous_to_n")]
+    pub ambiguous_to_n: bool,
+
+    /// Output an N in place of each kept gap
+    #[arg(long = "gap_to_n", conflicts_with = "n_to_gap")]
+    pub gap_to_n: bool,
+
+    /// Output a gap (the first of --gap_chars) in place of each kept N
+    #[arg(long = "n_to_gap")]
+    pub n_to_gap: bool,
+
+    /// Only keep parsimony-informative sites (two or more bases each in two or more sequences)
+    #[arg(long = "pars_informative")]
+    pub pars_informative: bool,
+
+    /// Save the majority-rule consensus of the filtered alignment to this FASTA file
+    #[arg(long = "consensus")]
+    pub consensus: Option<PathBuf>,
+
+    /// Use N in the consensus where the most common base is in less than this fraction of the
+    /// sequences
+    #[arg(long = "consensus_min_frac", default_value = "0.0", requires = "consensus")]
+    pub consensus_min_frac: f64,
+
+    /// What the consensus uses where two or more bases are tied for most common
+    #[arg(long = "consensus_tie", value_enum, default_value = "first", requires = "consensus")]
+    pub consensus_tie: ConsensusTie,
+
+    /// Save the site frequency spectrum of the kept biallelic sites to this file (folded, unless
+    /// --unfolded is used)
+    #[arg(long = "sfs")]
+    pub sfs: Option<PathBuf>,
+
+    /// Make the --sfs unfolded, i.e. count derived alleles (needs --ancestral or --ancestral_name)
+    #[arg(long = "unfolded")]
+    pub unfolded: bool,
+
+    /// FASTA file whose first sequence is the ancestral state of each site, for --unfolded
+    #[arg(long = "ancestral", requires = "sfs")]
+    pub ancestral: Option<PathBuf>,
+
+    /// Name of a sequence in the alignment (e.g. an outgroup) whose bases are the ancestral state
+    /// of each site, for --unfolded
+    #[arg(long = "ancestral_name", requires = "sfs", conflicts_with = "ancestral")]
+    pub ancestral_name: Option<String>,
+
+    /// Save the kept variable sites to this file as a sites-only VCF, with AC, AN and AF INFO
+    /// fields
+    #[arg(long = "vcf")]
+    pub vcf: Option<PathBuf>,
+
+    /// Save a pairwise SNP distance matrix (PHYLIP format) of the filtered alignment to this file
+    #[arg(long = "dist_matrix")]
+    pub dist_matrix: Option<PathBuf>,
+
+    /// Ignore sites where either sequence isn't A, C, G or T when calculating distances
+    #[arg(long = "dist_ignore_missing", requires = "dist_matrix")]
+    pub dist_ignore_missing: bool,
+
+    /// Remove leading and trailing sites which have no bases (regardless of other filters)
+    #[arg(long = "trim_ends")]
+    pub trim_ends: bool,
+
+    /// Only output the first of any sequences which are identical after filtering
+    #[arg(long = "dedup")]
+    pub dedup: bool,
+
+    /// Save a table of which sequences were collapsed into each output sequence by --dedup
+    #[arg(long = "dedup_map", requires = "dedup")]
+    pub dedup_map: Option<PathBuf>,
+
+    /// Replace each --dedup output header with the sequence's name and how many sequences it
+    /// represents, e.g. "seq_1 count=3"
+    #[arg(long = "dedup_counts", requires = "dedup")]
+    pub dedup_counts: bool,
+
+    /// Output the sequences sorted by name or by how much of the filtered sequence has bases (most
+    /// first), instead of in input order
+    #[arg(long = "sort", value_enum)]
+    pub sort: Option<SortOrder>,
+
+    /// Count the A, C, G, T, N and gap characters over the kept sites of all sequences
+    #[arg(long = "base_composition")]
+    pub base_composition: bool,
+
+    /// Save the removed sites to this FASTA file (the complement of the filtered alignment)
+    #[arg(long = "removed_output")]
+    pub removed_output: Option<PathBuf>,
+
+    /// Use 0-based positions in --table, --coord_map, --missing_report, --keep_positions and
+    /// --region (default: 1-based)
+    #[arg(long = "zero_based")]
+    pub zero_based: bool,
+
+    /// Only use the sites in this range of positions, e.g. 1001-2000 (inclusive)
+    #[arg(long = "region", value_parser = parse_region)]
+    pub region: Option<(usize, usize)>,
+
+    /// Save a table of the sequences which are missing at each kept site to this file
+    #[arg(long = "missing_report")]
+    pub missing_report: Option<PathBuf>,
+
+    /// Only include sites in the --missing_report where at least this many sequences are missing
+    #[arg(long = "missing_report_threshold", default_value = "1", requires = "missing_report")]
+    pub missing_report_threshold: usize,
+
+    /// Save a table of how many kept sites of each sequence differ from the most common base (SNPs)
+    /// or are missing
+    #[arg(long = "snp_counts")]
+    pub snp_counts: Option<PathBuf>,
+
+    /// Save the SHA-256 checksum of each output sequence to this file
+    #[arg(long = "checksums")]
+    pub checksums: Option<PathBuf>,
+
+    /// Show the SHA-256 checksum of the whole filtered alignment (as output) in the summary
+    #[arg(long = "alignment_hash")]
+    pub alignment_hash: bool,
+}
+
+
+/// The kinds of sequence which can be filtered. Only the residues of the alphabet (either case)
+/// count as present, so e.g. a protein alignment's -, X and * are all missing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Alphabet {
+    Dna,
+    /// Like DNA, but U counts as T
+    Rna,
+    Protein,
+}
+
+
+impl Alphabet {
+    /// Returns whether a character is normal for this alphabet: a base (or amino acid), a gap or
+    /// the alphabet's unknown character (N, or X and * for protein), in either case.
+    fn is_expected_char(self, c: u8) -> bool {
+        let expected: &[u8] = match self {
+            Alphabet::Dna => b"ACGTN-",
+            Alphabet::Rna => b"ACGUN-",
+            Alphabet::Protein => b"ACDEFGHIKLMNPQRSTVWYX*-",
+        };
+        expected.contains(&c.to_ascii_uppercase())
+    }
+}
+
+
+/// The alignment formats which can be read, either of which can be compressed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum InputFormat {
+    Fasta,
+    /// Relaxed PHYLIP (sequential or interleaved), which is always loaded into memory
+    Phylip,
+    /// Clustal (e.g. from Clustal Omega or MUSCLE), which is always loaded into memory
+    Clustal,
+}
+
+
+/// How --consensus resolves a site where two or more bases are tied for most common.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ConsensusTie {
+    /// The IUPAC ambiguity code for the tied bases (e.g. R for A and G)
+    Iupac,
+    /// An N
+    N,
+    /// The first of the tied bases in ACGT order
+    First,
+}
+
+
+/// The orders that --sort can put the output sequences in. Ties go to the first name
+/// (lexicographically), then to the input order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SortOrder {
+    /// By sequence name
+    Name,
+    /// By the fraction of the filtered sequence which has bases, from most to least
+    Aligned,
+}
+
+
+/// Parses a --core value, which can be a fraction (e.g. 0.95) or a percentage (e.g. 95%). Range
+/// checking is left to check_arguments.
+fn parse_core(value: &str) -> Result<f64, String> {
+    let (number, divisor) = match value.strip_suffix('%') {
+        Some(percentage) => (percentage, 100.0),
+        None => (value, 1.0),
+    };
+    match number.trim().parse::<f64>() {
+        Ok(number) => Ok(number / divisor),
+        Err(_) => Err(format!("'{}' is not a fraction or percentage", value)),
+    }
+}
+
+
+/// Parses a --region value (START-END, inclusive). Checking it against the alignment's length is
+/// left to drop_columns.
+fn parse_region(value: &str) -> Result<(usize, usize), String> {
+    let invalid = || format!("'{}' is not a range of positions like 1001-2000", value);
+    let (start, end) = value.split_once('-').ok_or_else(invalid)?;
+    let start: usize = start.trim().parse().map_err(|_| invalid())?;
+    let end: usize = end.trim().parse().map_err(|_| invalid())?;
+    if start > end {
+        return Err(format!("the start of '{}' is after its end", value));
+    }
+    Ok((start, end))
+}
+
+
+impl Default for FilterOptions {
+    /// The defaults are taken from the command-line definitions, so they can't get out of sync.
+    fn default() -> Self {
+        let matches = FilterOptions::augment_args(Command::new("coresnpfilter"))
+            .get_matches_from(["coresnpfilter"]);
+        FilterOptions::from_arg_matches(&matches).unwrap()
+    }
+}
+
+
+/// Summary statistics from a run of drop_columns. The removed-site counts are mutually exclusive,
+/// i.e. each removed site is only counted in one category.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct FilterStats {
+    /// Number of sequences in the alignment
+    pub seq_count: usize,
+    /// Length of the input alignment
+    pub alignment_length: usize,
+    /// Length of the filtered alignment (the same as the input with --soft_mask)
+    pub output_size: usize,
+    /// Sequences left out of the output by --dedup for being identical to an earlier sequence
+    pub dedup_collapsed: usize,
+    /// Sites which would have been removed but were kept by --keep_positions
+    pub force_kept: usize,
+    /// Total number of removed sites (the sum of the other removed counts)
+    pub removed_total: usize,
+    /// Sites removed for being outside --region
+    pub outside_region: usize,
+    /// Sites removed for not being in the --keep_from_vcf file
+    pub not_in_vcf: usize,
+    /// Sites removed by --mask_bed
+    pub masked_removed: usize,
+    /// Sites removed by --mask_from_vcf (not counting those already masked by --mask_bed)
+    pub vcf_masked_removed: usize,
+    /// Leading and trailing sites without bases removed by --trim_ends
+    pub end_trimmed: usize,
+    /// Sites removed by --invariant_only for being variable
+    pub variable_removed: usize,
+    /// Sites removed for falling below the --core threshold
+    pub non_core_removed: usize,
+    /// Sites removed for having more gaps than --max_gap_frac allows
+    pub gappy_removed: usize,
+    /// Sites removed for having more Ns and other ambiguous characters than --max_ambiguous_frac
+    /// allows
+    pub ambiguous_removed: usize,
+    /// Sites removed by --drop_empty_columns for having no bases
+    pub empty_removed: usize,
+    /// Sites removed by --pars_informative for not being parsimony-informative
+    pub uninformative_removed: usize,
+    /// Invariant sites removed (the sum of the per-base invariant counts)
+    pub invariant_removed: usize,
+    /// Invariant sites removed where the only base was A
+    pub invariant_a_removed: usize,
+    /// Invariant sites removed where the only base was C
+    pub invariant_c_removed: usize,
+    /// Invariant sites removed where the only base was G
+    pub invariant_g_removed: usize,
+    /// Invariant sites removed where the only base was T
+    pub invariant_t_removed: usize,
+    /// Invariant sites removed where every sequence with an amino acid had the same one (only with
+    /// --alphabet protein, which doesn't use the per-base counts above)
+    pub invariant_residue_removed: usize,
+    /// Invariant sites removed which had no canonical bases at all (the sum of the gap, N and
+    /// ambiguous counts)
+    pub invariant_other_removed: usize,
+    /// Invariant sites removed which had no canonical bases and were mostly gaps
+    pub invariant_gap_removed: usize,
+    /// Invariant sites removed which had no canonical bases and were mostly Ns (Xs for protein)
+    pub invariant_n_removed: usize,
+    /// Invariant sites removed which had no canonical bases and were mostly other characters
+    pub invariant_ambiguous_removed: usize,
+    /// Parsimony-informative sites in the input alignment, leaving out any sites outside --region
+    /// or not in --keep_from_vcf (whether or not --pars_informative is used)
+    pub pars_informative: usize,
+    /// Character counts over the kept sites (only with --base_composition)
+    pub base_composition: Option<BaseComposition>,
+    /// How often each character occurs in the whole alignment, most common first (only with
+    /// --report_chars)
+    pub char_counts: Option<Vec<CharCount>>,
+    /// SHA-256 of the filtered alignment as written, in lowercase hex (only with --alignment_hash,
+    /// and not from filter_records)
+    pub alignment_hash: Option<String>,
+}
+
+
+/// One character's count from --report_chars.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CharCount {
+    /// The character, or its hex code (e.g. \x00) if it isn't printable
+    pub char: String,
+    pub count: usize,
+    /// Whether the character is normal for the alphabet (see Alphabet::is_expected_char)
+    pub expected: bool,
+}
+
+
+/// How many of each character there are over the kept sites of all sequences (before --dedup).
+/// Case is ignored, and the counts add up to the number of sequences times the number of kept
+/// sites.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct BaseComposition {
+    pub a: usize,
+    pub c: usize,
+    pub g: usize,
+    pub t: usize,
+    pub n: usize,
+    pub gap: usize,
+    /// Any other character, e.g. IUPAC ambiguity codes
+    pub other: usize,
+}
+
+
+impl BaseComposition {
+    /// Adds the characters at one sequence's kept sites.
+    fn add(&mut self, seq: &[u8], keep: &BitVec, gap_chars: &[u8]) {
+        for i in keep.iter_ones() {
+            match seq[i].to_ascii_uppercase() {
+                b'A' => self.a += 1,
+                b'C' => self.c += 1,
+                b'G' => self.g += 1,
+                b'T' => self.t += 1,
+                b'N' => self.n += 1,
+                c if gap_chars.contains(&c) => self.gap += 1,
+                _ =>    self.other += 1,
+            }
+        }
+    }
+}
+
+
+/// This is the primary function of the program. It reads the alignment, decides which sites to
+/// keep and writes the filtered alignment to stdout (or any other writer, which makes testing
+/// easier). It returns summary statistics for the run, or the first error encountered.
+pub fn drop_columns(filename: &Path, options: &FilterOptions, stdout: &mut dyn io::Write)
+        -> Result<FilterStats, Error> {
+    let max_memory = (options.max_memory * 1e9) as usize;
+    let selection = sample_selection(options)?;
+    let start = Instant::now();
+    let alignment = Alignment::load_format(filename, options.input_format, max_memory,
+                                           selection.clone(), options.mmap)?;
+    if let Alignment::InMemory(records) = &alignment {
+        info!("loaded {} sequences into memory in {}", records.len(),
+              misc::format_duration(start.elapsed()));
+    } else {
+        debug!("{} is too big for --max_memory, so it will be read from disk for each pass",
+               filename.display());
+    }
+    let stats = if options.alignment_hash {
+        let mut hasher = misc::HashingWriter::new(stdout)?;
+        let mut stats = filter_alignment(alignment, selection.as_ref(), options,
+                                         &mut Output::Fasta(&mut hasher))?;
+        stats.alignment_hash = Some(hasher.finish());
+        stats
+    } else {
+        filter_alignment(alignment, selection.as_ref(), options, &mut Output::Fasta(stdout))?
+    };
+    if options.invariant_counts {
+        writeln!(stdout, "{},{},{},{}", stats.invariant_a_removed, stats.invariant_c_removed,
+                 stats.invariant_g_removed, stats.invariant_t_removed)?;
+    }
+    Ok(stats)
+}
+
+
+/// The same as drop_columns, but for sequences (headers and bases) which are already in memory,
+/// returning the filtered sequences instead of writing them. Only the extra files requested in
+/// the options (e.g. --table) are written.
+pub fn filter_records(records: Vec<(String, Vec<u8>)>, options: &FilterOptions)
+        -> Result<(Vec<(String, String)>, FilterStats), Error> {
+    let selection = sample_selection(options)?;
+    let records = records.into_iter()
+        .filter(|(header, _)| selection.as_ref().map_or(true, |s| s.is_selected(header)))
+        .map(|(header, seq)| AlignmentRecord { header, seq }).collect();
+    let mut filtered = Vec::new();
+    let stats = filter_alignment(Alignment::InMemory(records), selection.as_ref(), options,
+                                 &mut Output::Records(&mut filtered))?;
+    Ok((filtered, stats))
+}
+
+
+/// Where filter_alignment sends the filtered sequences: written as FASTA or collected in memory.
+enum Output<'a> {
+    Fasta(&'a mut dyn io::Write),
+    Records(&'a mut Vec<(String, String)>),
+}
+
+
+impl Output<'_> {
+    fn add(&mut self, header: &str, seq: &str) -> io::Result<()> {
+        match self {
+            Output::Fasta(out) => output_sequence(header, seq, *out),
+            Output::Records(records) => {
+                records.push((header.to_string(), seq.to_string()));
+                Ok(())
+            }
+        }
+    }
+}
+
+
+fn sample_selection(options: &FilterOptions) -> Result<Option<SampleSelection>, Error> {
+    if let Some(include) = &options.include_samples {
+        return Ok(Some(SampleSelection::include(include)?));
+    }
+    if let Some(exclude) = &options.exclude_samples {
+        return Ok(Some(SampleSelection::exclude(exclude)?));
+    }
+    if let Some(pattern) = &options.include_regex {
+        return Ok(Some(SampleSelection::include_regex(pattern)?));
+    }
+    if let Some(pattern) = &options.exclude_regex {
+        return Ok(Some(SampleSelection::exclude_regex(pattern)?));
+    }
+    Ok(None)
+}
+
+
+/// Filters the alignment (whose sequences have already been narrowed down by selection), sending
+/// the filtered sequences to output. Nothing is output with --invariant_counts or --count_only.
+fn filter_alignment(mut alignment: Alignment, selection: Option<&SampleSelection>,
+                    options: &FilterOptions, output: &mut Output)
+        -> Result<FilterStats, Error> {
+    if options.skip_empty {
+        let (skipped, empty) = samples::skip_empty(alignment)?;
+        alignment = skipped;
+        if !empty.is_empty() {
+            misc::print_warning(&format!("empty sequences left out of the alignment: {}",
+                                         empty.join(", ")));
+        }
+    }
+    if let Some(count) = options.subsample {
+        alignment = samples::subsample(alignment, count, options.seed)?;
+    }
+    let alignment_length = alignment.first_seq_length()?;
+    let char_counts = if options.report_chars || options.strict_chars {
+        Some(char_counts(&alignment, options.alphabet, &options.gap_chars)?)
+    } else {
+        None
+    };
+    if let (Some(char_counts), true) = (&char_counts, options.strict_chars) {
+        let unexpected: Vec<String> = char_counts.iter().filter(|c| !c.expected)
+            .map(|c| format!("{} ({})", c.char, c.count)).collect();
+        if !unexpected.is_empty() {
+            return Err(Error::MalformedFasta(format!("unexpected characters in alignment: {}",
+                                                     unexpected.join(", "))));
+        }
+    }
+    let start = Instant::now();
+    let progress = Progress::new(options.progress, "counting");
+    let counts = match options.alphabet {
+        Alphabet::Dna | Alphabet::Rna => {
+            bitvectors_and_counts(&alignment, alignment_length, options.iupac,
+                                  options.missing_chars.as_deref(),
+                                  options.present_chars.as_deref(), options.lowercase_missing,
+                                  options.alphabet == Alphabet::Rna, &options.gap_chars,
+                                  &progress)?
+        }
+        Alphabet::Protein => protein_counts(&alignment, alignment_length,
+                                            options.lowercase_missing, &options.gap_chars,
+                                            &progress)?,
+    };
+    drop(progress);
+    let SiteCounts { a, c, g, t, seq_count, acgt_counts, gap_counts, n_counts, ambiguous_counts,
+                     variable, shared_base, base_counts, residue_counts, seq_names } = counts;
+    info!("counted {} sites in {} sequences in {}", alignment_length, seq_count,
+          misc::format_duration(start.elapsed()));
+    if seq_count < options.min_sequences {
+        return Err(Error::EmptyInput(format!("too few sequences: the alignment has {} but \
+                                              --min_sequences is {}", seq_count,
+                                             options.min_sequences)));
+    }
+    if let Some(warning) = single_sequence_warning(seq_count, options) {
+        misc::print_warning(&warning);
+    }
+    let duplicates = duplicate_names(&seq_names);
+    if !duplicates.is_empty() {
+        let message = format!("repeated sequence names: {}", duplicates.join(", "));
+        if options.strict_names {
+            return Err(Error::MalformedFasta(message));
+        }
+        misc::print_warning(&message);
+    }
+    if let (Some(selection), true) = (selection, options.include_samples.is_some()) {
+        let missing = selection.missing_names(&seq_names);
+        if !missing.is_empty() {
+            misc::print_warning(&format!("samples not found in the alignment: {}",
+                                         missing.join(", ")));
+        }
+    }
+    let start = Instant::now();
+    let first_pos = if options.zero_based { 0 } else { 1 };
+    let mask = options.mask_bed.as_ref().map(|bed| {
+        positions::load_bed_mask(bed, options.mask_chrom.as_deref(), alignment_length)
+    }).transpose()?;
+    let vcf_mask = options.mask_from_vcf.as_ref().map(|vcf| {
+        positions::load_vcf_positions(vcf, alignment_length)
+    }).transpose()?;
+    let vcf_sites = options.keep_from_vcf.as_ref().map(|vcf| {
+        positions::load_vcf_positions(vcf, alignment_length)
+    }).transpose()?;
+    let force_keep = options.keep_positions.as_ref().map(|positions| {
+        positions::load_positions(positions, alignment_length, first_pos)
+    }).transpose()?;
+    let input_partitions = options.partition_in.as_ref().map(|path| {
+        partitions::load_partitions(path, alignment_length)
+    }).transpose()?;
+    let region = match options.region {
+        Some(region) => positions::region_range(region, alignment_length, first_pos)?,
+        None => 0..alignment_length,
+    };
+
+    if options.core_count.map_or(false, |count| count > seq_count) {
+        return Err(Error::BadArguments(format!("--core_count cannot be more than the number of \
+                                                sequences ({})", seq_count)));
+    }
+
+    // With --trim_ends, sites before the first (or after the last) site with a base are removed.
+    let first_with_base = acgt_counts.iter().position(|&count| count > 0)
+        .unwrap_or(alignment_length);
+    let last_with_base = acgt_counts.iter().rposition(|&count| count > 0).unwrap_or(0);
+
+    // With --count_gaps, gaps also count towards the core fraction (but they still can't make a
+    // site variable, so a site with only A and gaps is invariant).
+    let no_bases: BitVec = acgt_counts.iter().map(|&count| count == 0).collect();
+    let core_counts: Cow<[usize]> = if options.count_gaps {
+        Cow::Owned(acgt_counts.iter().zip(&gap_counts).map(|(count, gaps)| count + gaps).collect())
+    } else {
+        Cow::Borrowed(&acgt_counts)
+    };
+
+    let mut keep = bitvec![1; alignment_length];
+    let mut stats = FilterStats { seq_count, alignment_length, ..Default::default() };
+    if options.report_chars {
+        stats.char_counts = char_counts;
+    }
+    let mut table_file = create_table(options.table.as_deref(), options.with_entropy,
+                                      options.with_allele_count)?;
+    for i in 0..alignment_length {
+        // With --n_is_allele, N is a fifth state, so an N alongside a single base is variation.
+        let variation = variable[i] ||
+                        (options.n_is_allele && n_counts[i] > 0 && shared_base[i].is_some());
+        let frac = core_counts[i] as f64 / seq_count as f64;
+        let site_counts: &[u32] = match options.alphabet {
+            Alphabet::Dna | Alphabet::Rna => &base_counts[i],
+            Alphabet::Protein => &residue_counts[i],
+        };
+        let entropy = if options.with_entropy { Some(site_entropy(site_counts)) } else { None };
+        let alleles = if !options.with_allele_count {
+            None
+        } else if options.alphabet == Alphabet::Protein {
+            Some(site_counts.iter().filter(|&&count| count > 0).count())
+        } else {
+            Some(allele_count(a[i], c[i], g[i], t[i]))
+        };
+        let outside_region = !region.contains(&i);
+        let not_in_vcf = vcf_sites.as_ref().map_or(false, |v| !v[i]);
+        if outside_region || not_in_vcf {
+            // These sites aren't considered at all, not even by --keep_positions.
+            keep.set(i, false);
+            if outside_region {
+                stats.outside_region += 1;
+            } else {
+                stats.not_in_vcf += 1;
+            }
+            write_table_line(table_file.as_mut(), i + first_pos, a[i], c[i], g[i], t[i],
+                             core_counts[i], variation, frac, false, entropy, alleles)?;
+            continue;
+        }
+        let is_masked = mask.as_ref().map_or(false, |m| m[i]);
+        let is_vcf_masked = vcf_mask.as_ref().map_or(false, |m| m[i]);
+        let is_invariant = (options.exclude_invariant || options.invariant_counts) && !variation;
+        let is_variable = options.invariant_only && variation;
+        let is_non_core = frac < options.core ||
+                          options.core_count.map_or(false, |count| core_counts[i] < count);
+        let is_gappy = options.max_gap_frac.map_or(false, |max| {
+            gap_counts[i] as f64 / seq_count as f64 > max
+        });
+        let is_ambiguous = options.max_ambiguous_frac.map_or(false, |max| {
+            ambiguous_counts[i] as f64 / seq_count as f64 > max
+        });
+        let is_empty = options.drop_empty_columns && no_bases[i];
+        // With --iupac, ambiguous bases add to each base they could be, so an invariant site (e.g.
+        // all R) can look informative from its counts alone.
+        let is_informative = variable[i] && is_pars_informative(site_counts);
+        stats.pars_informative += is_informative as usize;
+        let is_uninformative = options.pars_informative && !is_informative;
+        let is_trimmed = options.trim_ends && (i < first_with_base || i > last_with_base);
+        let is_removed = is_masked || is_vcf_masked || is_trimmed || is_invariant || is_variable ||
+                         is_non_core || is_gappy || is_ambiguous || is_empty || is_uninformative;
+        if is_removed && force_keep.as_ref().map_or(false, |f| f[i]) {
+            stats.force_kept += 1;
+        } else if is_masked {
+            keep.set(i, false);
+            stats.masked_removed += 1;
+        } else if is_vcf_masked {
+            keep.set(i, false);
+            stats.vcf_masked_removed += 1;
+        } else if is_trimmed {
+            keep.set(i, false);
+            stats.end_trimmed += 1;
+        } else if is_invariant {
+            keep.set(i, false);
+            match shared_base[i] {
+                Some(_) if options.alphabet == Alphabet::Protein => {
+                    stats.invariant_residue_removed += 1
+                }
+                Some(b'A') => stats.invariant_a_removed += 1,
+                Some(b'C') => stats.invariant_c_removed += 1,
+                Some(b'G') => stats.invariant_g_removed += 1,
+                Some(_) =>    stats.invariant_t_removed += 1,
+                None => {
+                    // No canonical bases, so categorise the site by its most common character.
+                    stats.invariant_other_removed += 1;
+                    let ambiguous_count = seq_count - gap_counts[i] - n_counts[i];
+                    if gap_counts[i] >= n_counts[i] && gap_counts[i] >= ambiguous_count {
+                        stats.invariant_gap_removed += 1;
+                    } else if n_counts[i] >= ambiguous_count {
+                        stats.invariant_n_removed += 1;
+                    } else {
+                        stats.invariant_ambiguous_removed += 1;
+                    }
+                }
+            }
+        } else if is_variable {
+            keep.set(i, false);
+            stats.variable_removed += 1;
+        } else if is_non_core {
+            keep.set(i, false);
+            stats.non_core_removed += 1;
+        } else if is_gappy {
+            keep.set(i, false);
+            stats.gappy_removed += 1;
+        } else if is_ambiguous {
+            keep.set(i, false);
+            stats.ambiguous_removed += 1;
+        } else if is_empty {
+            keep.set(i, false);
+            stats.empty_removed += 1;
+        } else if is_uninformative {
+            keep.set(i, false);
+            stats.uninformative_removed += 1;
+        }
+        write_table_line(table_file.as_mut(), i + first_pos, a[i], c[i], g[i], t[i], core_counts[i],
+                         variation, frac, keep[i], entropy, alleles)?;
+    }
+    let kept_count = keep.iter().filter(|n| *n == true).count();
+    stats.invariant_removed = stats.invariant_a_removed + stats.invariant_c_removed +
+                              stats.invariant_g_removed + stats.invariant_t_removed +
+                              stats.invariant_residue_removed + stats.invariant_other_removed;
+    stats.removed_total = stats.outside_region + stats.not_in_vcf + stats.masked_removed +
+                          stats.vcf_masked_removed + stats.end_trimmed +
+                          stats.variable_removed + stats.non_core_removed + stats.gappy_removed +
+                          stats.ambiguous_removed + stats.empty_removed +
+                          stats.invariant_removed + stats.uninformative_removed;
+    assert!(alignment_length == kept_count + stats.removed_total);
+    info!("checked the sites in {}: {} passed the filters and {} were removed",
+          misc::format_duration(start.elapsed()), kept_count, stats.removed_total);
+    if kept_count == 0 && !options.invariant_counts {
+        let message = format!("no sites passed the filters ({})", active_filters(options));
+        if options.error_on_empty {
+            return Err(Error::EmptyOutput(message));
+        }
+        misc::print_warning(&message);
+    }
+    write_coord_map(options.coord_map.as_deref(), &keep, first_pos)?;
+    write_vcf(options.vcf.as_deref(), &base_counts, &acgt_counts, &variable, &keep)?;
+    if let (Some(input_partitions), Some(path)) = (&input_partitions,
+                                                    options.partition_out.as_deref()) {
+        let empty = partitions::write_partitions(path, input_partitions, &keep)?;
+        if !empty.is_empty() {
+            misc::print_warning(&format!("partitions with no sites left after filtering (not \
+                                          included in --partition_out): {}", empty.join(", ")));
+        }
+    }
+    write_mask_track(options.mask_track.as_deref(), &keep)?;
+    write_raxml_info(options.raxml_info.as_deref(), &stats)?;
+
+    // When soft-masking, filtered sites are replaced instead of removed.
+    let mask_char = if options.soft_mask { Some(options.mask_char as u8) } else { None };
+    let output_size = if options.soft_mask { alignment_length } else { kept_count };
+    stats.output_size = output_size;
+    write_consensus(options.consensus.as_deref(), &base_counts, seq_count,
+                    options.consensus_min_frac, options.consensus_tie, &keep, output_size,
+                    mask_char)?;
+    if let Some(sfs) = options.sfs.as_deref() {
+        let ancestral = match (&options.ancestral, &options.ancestral_name) {
+            (Some(path), _) => Some(load_ancestral(path, alignment_length)?),
+            (None, Some(name)) => Some(find_ancestral(&alignment, name)?),
+            (None, None) => None,
+        };
+        let (missing, mismatched) = write_sfs(sfs, &base_counts, &keep, seq_count,
+                                              ancestral.as_deref())?;
+        if missing > 0 {
+            misc::print_warning(&format!("{} biallelic sites were left out of the --sfs because \
+                                          the ancestral sequence has no base there", missing));
+        }
+        if mismatched > 0 {
+            misc::print_warning(&format!("{} biallelic sites were left out of the --sfs because \
+                                          the ancestral base wasn't one of their bases",
+                                         mismatched));
+        }
+    }
+
+    if !options.invariant_counts &&
+       (!options.count_only || options.dist_matrix.is_some() || options.dedup ||
+        options.base_composition || options.removed_output.is_some() ||
+        options.missing_report.is_some() || options.snp_counts.is_some() ||
+        options.names.is_some()) {
+        // The filtered sequences are only kept in memory if they're needed for distances,
+        // deduplication or sorting. Otherwise they are output as they go.
+        let buffer = options.dist_matrix.is_some() || options.dedup || options.sort.is_some();
+        let mut filtered_seqs = Vec::new();
+        let mut composition = if options.base_composition {
+            Some(BaseComposition::default())
+        } else {
+            None
+        };
+        let removed = !keep.clone();
+        let mut removed_file = match options.removed_output.as_deref() {
+            Some(path) => Some(BufWriter::new(misc::create_file(path)?)),
+            None => None,
+        };
+        let mut names_file = match options.names.as_deref() {
+            Some(path) => Some(BufWriter::new(misc::create_file(path)?)),
+            None => None,
+        };
+        let mut missing_report = if options.missing_report.is_some() {
+            Some(MissingReport::new(options, &keep, &core_counts, seq_count))
+        } else {
+            None
+        };
+        let mut snp_counts = if options.snp_counts.is_some() {
+            Some(SnpCounts::new(options, &keep, &base_counts, seq_count))
+        } else {
+            None
+        };
+        let mut checksums = options.checksums.as_ref().map(|_| Vec::new());
+        let chars = output_chars(options, true);
+        let removed_chars = output_chars(options, false);
+        // These are reused for each sequence, instead of allocating new ones.
+        let mut write_buffer = Vec::with_capacity(WRITE_BUFFER_SIZE);
+        let mut seq_buffer = String::with_capacity(output_size);
+        let start = Instant::now();
+        let progress = Progress::new(options.progress, "filtering");
+        alignment.for_each_record(|header, seq| {
+            progress.add(1);
+            if let Some(composition) = composition.as_mut() {
+                composition.add(seq, &keep, options.gap_chars.as_bytes());
+            }
+            if let Some(report) = missing_report.as_mut() {
+                report.add(seq);
+            }
+            if let Some(counts) = snp_counts.as_mut() {
+                counts.add(seq);
+            }
+            if let Some(file) = names_file.as_mut() {
+                writeln!(file, "{}", header)?;
+            }
+            if let Some(file) = removed_file.as_mut() {
+                write_sequence(header, seq, &removed, None, &removed_chars, &mut write_buffer,
+                               file)?;
+            }
+            if buffer {
+                let seq = remove_columns(seq, &keep, output_size, mask_char, &chars);
+                filtered_seqs.push((header.to_string(), seq));
+            } else if !options.count_only {
+                if let (Output::Fasta(out), None) = (&mut *output, &checksums) {
+                    // Written straight out, so the filtered sequence is never built in memory
+                    // (unless it's needed for its checksum).
+                    write_sequence(header, seq, &keep, mask_char, &chars, &mut write_buffer,
+                                   *out)?;
+                } else {
+                    remove_columns_into(seq, &keep, mask_char, &chars, &mut seq_buffer);
+                    add_checksum(checksums.as_mut(), header, &seq_buffer)?;
+                    output.add(header, &seq_buffer)?;
+                }
+            }
+            Ok(())
+        })?;
+        drop(progress);
+        stats.base_composition = composition;
+        if let Some(mut file) = removed_file {
+            file.flush()?;
+        }
+        if let Some(mut file) = names_file {
+            file.flush()?;
+        }
+        if let (Some(report), Some(path)) = (&missing_report, options.missing_report.as_deref()) {
+            report.write(path, &seq_names, first_pos)?;
+        }
+        if let (Some(counts), Some(path)) = (&snp_counts, options.snp_counts.as_deref()) {
+            counts.write(path, &seq_names)?;
+        }
+        if options.dedup {
+            let seq_count_before = filtered_seqs.len();
+            filtered_seqs = dedup_sequences(filtered_seqs, options.dedup_map.as_deref(),
+                                            options.dedup_counts)?;
+            stats.dedup_collapsed = seq_count_before - filtered_seqs.len();
+            debug!("deduplication left {} of {} sequences", filtered_seqs.len(),
+                   seq_count_before);
+        }
+        if let Some(order) = options.sort {
+            sort_sequences(&mut filtered_seqs, order, options.alphabet);
+        }
+        if buffer && !options.count_only {
+            for (header, seq) in &filtered_seqs {
+                add_checksum(checksums.as_mut(), header, seq)?;
+                output.add(header, seq)?;
+            }
+        }
+        if let (Some(checksums), Some(path)) = (&checksums, options.checksums.as_deref()) {
+            write_checksums(path, checksums)?;
+        }
+        write_dist_matrix(options.dist_matrix.as_deref(), &filtered_seqs,
+                          options.dist_ignore_missing)?;
+        let elapsed = misc::format_duration(start.elapsed());
+        if options.count_only {
+            info!("wrote the extra output files in {}", elapsed);
+        } else {
+            info!("wrote {} filtered sequences in {}", seq_count - stats.dedup_collapsed, elapsed);
+        }
+    }
+    Ok(stats)
+}
+
+
+/// Adds the SHA-256 of an output sequence (just the sequence, not its header) to the --checksums
+/// list, if it's being made.
+fn add_checksum(checksums: Option<&mut Vec<(String, String)>>, header: &str, seq: &str)
+        -> Result<(), Error> {
+    if let Some(checksums) = checksums {
+        checksums.push((seq_name(header).to_string(), misc::sha256_hex(seq.as_bytes())?));
+    }
+    Ok(())
+}
+
+
+/// Writes a two-column table of each output sequence's name and SHA-256, in output order.
+fn write_checksums(path: &Path, checksums: &[(String, String)]) -> Result<(), Error> {
+    let mut file = BufWriter::new(misc::create_file(path)?);
+    for (name, checksum) in checksums {
+        writeln!(file, "{}\t{}", name, checksum)?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+
+/// Describes the options which remove sites (e.g. "--core 0.95, -e"), for explaining why none
+/// were kept.
+fn active_filters(options: &FilterOptions) -> String {
+    let mut filters = Vec::new();
+    if options.core > 0.0 {
+        filters.push(format!("--core {}", options.core));
+    }
+    if let Some(count) = options.core_count {
+        filters.push(format!("--core_count {}", count));
+    }
+    if options.exclude_invariant {
+        filters.push("-e".to_string());
+    }
+    if options.invariant_only {
+        filters.push("--invariant_only".to_string());
+    }
+    if options.pars_informative {
+        filters.push("--pars_informative".to_string());
+    }
+    if let Some(max) = options.max_gap_frac {
+        filters.push(format!("--max_gap_frac {}", max));
+    }
+    if let Some(max) = options.max_ambiguous_frac {
+        filters.push(format!("--max_ambiguous_frac {}", max));
+    }
+    if options.drop_empty_columns {
+        filters.push("--drop_empty_columns".to_string());
+    }
+    if options.trim_ends {
+        filters.push("--trim_ends".to_string());
+    }
+    if let Some(bed) = &options.mask_bed {
+        filters.push(format!("--mask_bed {}", bed.display()));
+    }
+    if let Some(vcf) = &options.mask_from_vcf {
+        filters.push(format!("--mask_from_vcf {}", vcf.display()));
+    }
+    if let Some((start, end)) = options.region {
+        filters.push(format!("--region {}-{}", start, end));
+    }
+    if let Some(vcf) = &options.keep_from_vcf {
+        filters.push(format!("--keep_from_vcf {}", vcf.display()));
+    }
+    if filters.is_empty() {
+        "no filters used".to_string()
+    } else {
+        filters.join(", ")
+    }
+}
+
+
+/// With only one sequence, every site is invariant and core filtering is all-or-nothing, which can
+/// give surprising output. This returns a warning explaining what the options will do, if any of
+/// them are affected.
+fn single_sequence_warning(seq_count: usize, options: &FilterOptions) -> Option<String> {
+    if seq_count != 1 {
+        return None;
+    }
+    let core = options.core > 0.0 || options.core_count.is_some();
+    let effect = match (options.exclude_invariant, core) {
+        (true, _) => "every site is invariant, so -e will remove all of them",
+        (false, true) => "--core will only keep the sites where it has a base",
+        (false, false) => return None,
+    };
+    Some(format!("the alignment has only one sequence: {}", effect))
+}
+
+
+/// The kept sites listed by --missing_report (those with at least the threshold number of missing
+/// sequences), gathered one sequence at a time during the output pass.
+struct MissingReport {
+    /// Whether each character counts as present, the same as for the --core filters
+    present: [bool; 256],
+    sites: Vec<usize>,
+    /// The index of each sequence missing at each of the sites
+    missing: Vec<Vec<usize>>,
+    seq_index: usize,
+}
+
+
+impl MissingReport {
+    fn new(options: &FilterOptions, keep: &BitVec, core_counts: &[usize], seq_count: usize)
+            -> MissingReport {
+        let present = present_chars(options);
+        let sites: Vec<usize> = keep.iter_ones()
+            .filter(|&i| seq_count - core_counts[i] >= options.missing_report_threshold)
+            .collect();
+        let missing = vec![Vec::new(); sites.len()];
+        MissingReport { present, sites, missing, seq_index: 0 }
+    }
+
+    /// Adds the next sequence, which must be in the same order as when the sites were counted.
+    fn add(&mut self, seq: &[u8]) {
+        for (&site, missing) in self.sites.iter().zip(self.missing.iter_mut()) {
+            if !self.present[seq[site] as usize] {
+                missing.push(self.seq_index);
+            }
+        }
+        self.seq_index += 1;
+    }
+
+    /// Writes a two-column table of each site's position (in the input alignment) and the names
+    /// of its missing sequences, comma-separated.
+    fn write(&self, path: &Path, seq_names: &[String], first_pos: usize) -> Result<(), Error> {
+        let mut file = BufWriter::new(misc::create_file(path)?);
+        writeln!(file, "pos\tmissing")?;
+        for (site, missing) in self.sites.iter().zip(&self.missing) {
+            let names: Vec<&str> = missing.iter().map(|&i| seq_names[i].as_str()).collect();
+            writeln!(file, "{}\t{}", site + first_pos, names.join(","))?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+}
+
+
+/// Returns whether each character counts as present, the same as for the --core filters.
+fn present_chars(options: &FilterOptions) -> [bool; 256] {
+    let mut present = match options.alphabet {
+        Alphabet::Dna | Alphabet::Rna => {
+            CharTable::new(options.iupac, options.missing_chars.as_deref(),
+                           options.present_chars.as_deref(), options.lowercase_missing,
+                           options.alphabet == Alphabet::Rna).present
+        }
+        Alphabet::Protein => residue_slots(options.lowercase_missing).map(|slot| slot < 20),
+    };
+    for c in options.gap_chars.bytes() {
+        present[c as usize] |= options.count_gaps;
+    }
+    present
+}
+
+
+/// The per-sequence counts for --snp_counts, gathered one sequence at a time during the output
+/// pass. At each kept site, a sequence's base is a SNP if it differs from the site's most common
+/// base (ties going to A, then C, then G). Characters which aren't bases (e.g. IUPAC codes with
+/// --iupac) are neither SNPs nor missing unless they count as missing for the --core filters.
+struct SnpCounts {
+    present: [bool; 256],
+    slots: [u8; 256],
+    /// Each kept site and its most common base's slot (None if the site has no bases)
+    majority: Vec<(usize, Option<u8>)>,
+    /// The SNP and missing counts of each sequence added so far
+    counts: Vec<(usize, usize)>,
+}
+
+
+impl SnpCounts {
+    fn new(options: &FilterOptions, keep: &BitVec, base_counts: &[[u32; 4]], seq_count: usize)
+            -> SnpCounts {
+        let majority = keep.iter_ones().map(|i| {
+            let base = consensus_base(&base_counts[i], seq_count, 0.0, ConsensusTie::First);
+            (i, b"ACGT".iter().position(|&b| b == base).map(|slot| slot as u8))
+        }).collect();
+        let slots = if options.alphabet == Alphabet::Rna { RNA_BASE_SLOTS } else { BASE_SLOTS };
+        SnpCounts { present: present_chars(options), slots, majority, counts: Vec::new() }
+    }
+
+    /// Adds the next sequence, which must be in the same order as when the sites were counted.
+    fn add(&mut self, seq: &[u8]) {
+        let (mut snps, mut missing) = (0, 0);
+        for &(site, majority) in &self.majority {
+            let c = seq[site];
+            if !self.present[c as usize] {
+                missing += 1;
+            } else if self.slots[c as usize] < 4 && majority != Some(self.slots[c as usize]) {
+                snps += 1;
+            }
+        }
+        self.counts.push((snps, missing));
+    }
+
+    /// Writes a three-column table of each sequence's name, SNP count and missing count.
+    fn write(&self, path: &Path, seq_names: &[String]) -> Result<(), Error> {
+        let mut file = BufWriter::new(misc::create_file(path)?);
+        writeln!(file, "name\tsnp_count\tmissing_count")?;
+        for (name, (snps, missing)) in seq_names.iter().zip(&self.counts) {
+            writeln!(file, "{}\t{}\t{}", name, snps, missing)?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+}
+
+
+/// Checks for invalid or incompatible options, returning an error for the first one found.
+pub fn check_arguments(options: &FilterOptions) -> Result<(), Error> {
+    let non_count_option = options.exclude_invariant || options.invariant_only ||
+                           options.core != 0.0 ||
+                           options.core_count.is_some() || options.include_samples.is_some() ||
+                           options.exclude_samples.is_some() || options.subsample.is_some() ||
+                           options.include_regex.is_some() || options.exclude_regex.is_some() ||
+                           options.table.is_some() || options.coord_map.is_some() ||
+                           options.partition_in.is_some() ||
+                           options.mask_track.is_some() ||
+                           options.mask_bed.is_some() || options.keep_positions.is_some() ||
+                           options.keep_from_vcf.is_some() || options.mask_from_vcf.is_some() ||
+                           options.count_only || options.soft_mask || options.pars_informative ||
+                           options.consensus.is_some() || options.dist_matrix.is_some() ||
+                           options.sfs.is_some() || options.vcf.is_some() ||
+                           options.trim_ends || options.dedup || options.uppercase ||
+                           options.ambiguous_to_n || options.gap_to_n || options.n_to_gap ||
+                           options.base_composition || options.removed_output.is_some() ||
+                           options.zero_based || options.region.is_some() ||
+                           options.alphabet == Alphabet::Protein || options.report_chars ||
+                           options.missing_report.is_some() || options.count_gaps ||
+                           options.snp_counts.is_some() || options.checksums.is_some() ||
+                           options.alignment_hash ||
+                           options.names.is_some() ||
+                           options.max_gap_frac.is_some() || options.max_ambiguous_frac.is_some() ||
+                           options.drop_empty_columns;
+    let bad = |message: &str| Err(Error::BadArguments(message.to_string()));
+    if options.invariant_counts && non_count_option {
+        return bad("--invariant_counts cannot be used with other options");
+    }
+    if !(0.0..=1.0).contains(&options.core) {
+        return bad("--core must be between 0 and 1 (inclusive), or a percentage such as 95%");
+    }
+    let selections: Vec<&str> = [(options.include_samples.is_some(), "--include_samples"),
+                                 (options.exclude_samples.is_some(), "--exclude_samples"),
+                                 (options.include_regex.is_some(), "--include_regex"),
+                                 (options.exclude_regex.is_some(), "--exclude_regex")]
+        .iter().filter(|(used, _)| *used).map(|(_, name)| *name).collect();
+    if selections.len() > 1 {
+        return bad(&format!("{} and {} cannot be used together", selections[0], selections[1]));
+    }
+    for pattern in options.include_regex.iter().chain(&options.exclude_regex) {
+        samples::parse_regex(pattern)?;
+    }
+    if options.subsample == Some(0) {
+        return bad("--subsample must be at least 1");
+    }
+    if options.max_memory < 0.0 {
+        return bad("--max_memory cannot be negative");
+    }
+    if options.missing_report_threshold == 0 {
+        return bad("--missing_report_threshold must be at least 1");
+    }
+    if !(0.0..=1.0).contains(&options.consensus_min_frac) {
+        return bad("--consensus_min_frac must be between 0 and 1 (inclusive)");
+    }
+    if options.max_gap_frac.map_or(false, |max| !(0.0..=1.0).contains(&max)) {
+        return bad("--max_gap_frac must be between 0 and 1 (inclusive)");
+    }
+    if options.max_ambiguous_frac.map_or(false, |max| !(0.0..=1.0).contains(&max)) {
+        return bad("--max_ambiguous_frac must be between 0 and 1 (inclusive)");
+    }
+    let has_ancestral = options.ancestral.is_some() || options.ancestral_name.is_some();
+    if has_ancestral && !options.unfolded {
+        return bad("--ancestral and --ancestral_name can only be used with --unfolded");
+    }
+    if options.unfolded && !has_ancestral {
+        return bad("--unfolded needs --ancestral or --ancestral_name");
+    }
+    if options.missing_chars.is_some() && options.present_chars.is_some() {
+        return bad("--missing_chars and --present_chars cannot be used together");
+    }
+    if options.count_gaps && options.present_chars.is_some() {
+        return bad("--count_gaps cannot be used with --present_chars (list the gap characters as \
+                    present characters instead)");
+    }
+    if options.count_gaps && options.missing_chars.is_some() {
+        return bad("--count_gaps cannot be used with --missing_chars (leave the gap characters \
+                    out of the missing characters instead)");
+    }
+    if options.gap_chars.bytes().any(|c| !c.is_ascii() || c.is_ascii_alphabetic()) {
+        return bad("--gap_chars can only contain ASCII characters which aren't letters");
+    }
+    if options.soft_mask && options.coord_map.is_some() {
+        return bad("--coord_map cannot be used with --soft_mask (coordinates are unchanged)");
+    }
+    if options.soft_mask && options.partition_out.is_some() {
+        return bad("--partition_out cannot be used with --soft_mask (coordinates are unchanged)");
+    }
+    if options.checksums.is_some() && options.count_only {
+        return bad("--checksums cannot be used with --count_only (no sequences are output)");
+    }
+    if options.alignment_hash && options.count_only {
+        return bad("--alignment_hash cannot be used with --count_only (no sequences are output)");
+    }
+    if options.checksums.is_some() {
+        misc::check_sha256_support("--checksums")?;
+    }
+    if options.alignment_hash {
+        misc::check_sha256_support("--alignment_hash")?;
+    }
+    if !options.mask_char.is_ascii() {
+        return bad("--mask_char must be an ASCII character");
+    }
+    if options.alphabet == Alphabet::Protein {
+        let dna_only = [(options.iupac, "--iupac"),
+                        (options.missing_chars.is_some(), "--missing_chars"),
+                        (options.present_chars.is_some(), "--present_chars"),
+                        (options.consensus.is_some(), "--consensus"),
+                        (options.base_composition, "--base_composition"),
+                        (options.sfs.is_some(), "--sfs"),
+                        (options.vcf.is_some(), "--vcf"),
+                        (options.raxml_info.is_some(), "--raxml_info"),
+                        (options.snp_counts.is_some(), "--snp_counts"),
+                        (options.n_is_allele, "--n_is_allele"),
+                        (options.ambiguous_to_n, "--ambiguous_to_n"),
+                        (options.gap_to_n, "--gap_to_n"),
+                        (options.n_to_gap, "--n_to_gap"),
+                        (options.dist_ignore_missing, "--dist_ignore_missing")];
+        if let Some((_, name)) = dna_only.iter().find(|(used, _)| *used) {
+            return bad(&format!("{} cannot be used with --alphabet protein", name));
+        }
+    }
+    Ok(())
+}
+
+
+/// Counts each character in the alignment's sequences, returning them from most to least common.
+/// The gap characters are expected, along with those of the alphabet.
+fn char_counts(alignment: &Alignment, alphabet: Alphabet, gap_chars: &str)
+        -> Result<Vec<CharCount>, Error> {
+    let mut counts = [0; 256];
+    alignment.for_each_record(|_, seq| {
+        for &c in seq {
+            counts[c as usize] += 1;
+        }
+        Ok(())
+    })?;
+    let mut char_counts: Vec<CharCount> = (0..=255u8).filter(|&c| counts[c as usize] > 0)
+        .map(|c| CharCount {
+            char: if c.is_ascii_graphic() { (c as char).to_string() }
+                  else { format!("\\x{:02x}", c) },
+            count: counts[c as usize],
+            expected: alphabet.is_expected_char(c) || gap_chars.as_bytes().contains(&c),
+        }).collect();
+    char_counts.sort_by_key(|c| std::cmp::Reverse(c.count));
+    Ok(char_counts)
+}
+
+
+fn output_sequence(header: &str, seq: &str, stdout: &mut dyn io::Write) -> io::Result<()> {
+    writeln!(stdout, ">{}\n{}", header, seq)
+}
+
+
+/// Returns the sequence with only the kept sites, each changed to its character in chars (see
+/// output_chars). If mask_char is given, the other sites are replaced with it instead of being
+/// removed.
+fn remove_columns(full_seq: &[u8], keep: &BitVec, output_size: usize, mask_char: Option<u8>,
+                  chars: &[u8; 256]) -> String {
+    let mut kept_seq = String::with_capacity(output_size);
+    remove_columns_into(full_seq, keep, mask_char, chars, &mut kept_seq);
+    debug_assert_eq!(kept_seq.chars().count(), output_size);
+    kept_seq
+}
+
+
+/// Like remove_columns, but replaces the contents of kept_seq, so one String can be reused for
+/// each sequence.
+fn remove_columns_into(full_seq: &[u8], keep: &BitVec, mask_char: Option<u8>, chars: &[u8; 256],
+                       kept_seq: &mut String) {
+    kept_seq.clear();
+    for i in 0..full_seq.len() {
+        if keep[i] {
+            kept_seq.push(chars[full_seq[i] as usize] as char)
+        } else if let Some(c) = mask_char {
+            kept_seq.push(c as char)
+        }
+    }
+}
+
+
+/// Writes a FASTA record of only the kept sites (with mask_char at the other sites, if given),
+/// like output_sequence(header, &remove_columns(...)) but without building the sequence first.
+/// Bytes are written as they are, so non-ASCII characters aren't changed. The buffer is cleared
+/// first, so the same one can be reused for each sequence.
+fn write_sequence(header: &str, full_seq: &[u8], keep: &BitVec, mask_char: Option<u8>,
+                  chars: &[u8; 256], buffer: &mut Vec<u8>, out: &mut dyn io::Write)
+        -> io::Result<()> {
+    writeln!(out, ">{}", header)?;
+    buffer.clear();
+    for (&base, kept) in full_seq.iter().zip(keep.iter()) {
+        if *kept {
+            buffer.push(chars[base as usize]);
+        } else if let Some(c) = mask_char {
+            buffer.push(c);
+        }
+        if buffer.len() == WRITE_BUFFER_SIZE {
+            out.write_all(buffer)?;
+            buffer.clear();
+        }
+    }
+    buffer.push(b'\n');
+    out.write_all(buffer)
+}
+
+
+/// write_sequence passes its output to the writer in chunks of this many bytes.
+const WRITE_BUFFER_SIZE: usize = 64 * 1024;
+
+
+/// Returns what each character becomes in the output. The conversions are applied in order:
+/// --ambiguous_to_n (anything other than a base, N or gap becomes N, or n if lowercase), then
+/// --gap_to_n or --n_to_gap, then --uppercase. Removed sites (e.g. for --removed_output) are only
+/// uppercased. U is only a base for RNA.
+fn output_chars(options: &FilterOptions, kept: bool) -> [u8; 256] {
+    let gap_chars = options.gap_chars.as_bytes();
+    let gap = gap_chars.first().copied().unwrap_or(b'-');
+    let mut chars = [0; 256];
+    for (i, c) in chars.iter_mut().enumerate() {
+        let mut out = i as u8;
+        if kept && options.ambiguous_to_n {
+            let unambiguous = match out.to_ascii_uppercase() {
+                b'A' | b'C' | b'G' | b'T' | b'N' => true,
+                b'U' => options.alphabet == Alphabet::Rna,
+                _ => gap_chars.contains(&out),
+            };
+            if !unambiguous {
+                out = if out.is_ascii_lowercase() { b'n' } else { b'N' };
+            }
+        }
+        if kept && options.gap_to_n && gap_chars.contains(&out) {
+            out = b'N';
+        }
+        if kept && options.n_to_gap && out.eq_ignore_ascii_case(&b'N') {
+            out = gap;
+        }
+        *c = if options.uppercase { out.to_ascii_uppercase() } else { out };
+    }
+    chars
+}
+
+
+/// Returns whether a site is parsimony-informative, i.e. at least two different bases (or amino
+/// acids) each occur in at least two sequences.
+pub fn is_pars_informative(base_counts: &[u32]) -> bool {
+    base_counts.iter().filter(|&&count| count >= 2).count() >= 2
+}
+
+
+/// Returns the Shannon entropy (in bits) of a site's base (or amino acid) frequencies, ignoring
+/// missing data: 0 for an invariant site (or one with no bases) up to 2 when all four bases are
+/// equally common (or log2(20) for amino acids).
+fn site_entropy(base_counts: &[u32]) -> f64 {
+    let total: u32 = base_counts.iter().sum();
+    base_counts.iter().filter(|&&count| count > 0).map(|&count| {
+        // Written as p·log2(1/p) rather than -p·log2(p) so an invariant site is 0, not -0.
+        let p = count as f64 / total as f64;
+        p * (1.0 / p).log2()
+    }).sum()
+}
+
+
+/// Returns whether a site has variation, i.e. contains two or more different canonical bases.
+pub fn has_variation(a: bool, c: bool, g: bool, t: bool) -> bool {
+    allele_count(a, c, g, t) > 1
+}
+
+
+/// Returns the number of different canonical bases (0 to 4) at a site.
+pub fn allele_count(a: bool, c: bool, g: bool, t: bool) -> usize {
+    a as usize + c as usize + g as usize + t as usize
+}
+
+
+fn create_table(table: Option<&Path>, with_entropy: bool, with_allele_count: bool)
+        -> Result<Option<File>, Error> {
+    if let Some(table_path) = table {
+        let mut file = misc::create_file(table_path)?;
+        let entropy_column = if with_entropy { "\tentropy" } else { "" };
+        let allele_column = if with_allele_count { "\talleles" } else { "" };
+        writeln!(file, "pos\ta\tc\tg\tt\tcount\tfrac\tvar\tkeep{}{}", entropy_column,
+                 allele_column)?;
+        Ok(Some(file))
+    } else {
+        Ok(None)
+    }
+}
+
+
+#[allow(clippy::too_many_arguments)]
+fn write_table_line(file: Option<&mut File>, pos: usize, a: bool, c: bool, g: bool, t: bool,
+                    acgt_counts: usize, variation: bool, frac: f64, keep: bool,
+                    entropy: Option<f64>, alleles: Option<usize>) -> io::Result<()> {
+    if let Some(f) = file {
+        write!(f, "{}\t{}\t{}\t{}\t{}\t{}\t{:.4}\t{}\t{}", pos, a as i32, c as i32, g as i32,
+               t as i32, acgt_counts, frac, variation as i32, keep as i32)?;
+        if let Some(entropy) = entropy {
+            write!(f, "\t{:.4}", entropy)?;
+        }
+        if let Some(alleles) = alleles {
+            write!(f, "\t{}", alleles)?;
+        }
+        writeln!(f)?;
+    }
+    Ok(())
+}
+
+
+/// Writes a two-column table which translates each output position back to its position in the
+/// input alignment. Positions start at first_pos (1 by default, 0 with --zero_based).
+fn write_coord_map(coord_map: Option<&Path>, keep: &BitVec, first_pos: usize)
+        -> Result<(), Error> {
+    if let Some(coord_map_path) = coord_map {
+        let mut file = misc::create_file(coord_map_path)?;
+        writeln!(file, "new_pos\toriginal_pos")?;
+        for (new_pos, original_pos) in keep.iter_ones().enumerate() {
+            writeln!(file, "{}\t{}", new_pos + first_pos, original_pos + first_pos)?;
+        }
+    }
+    Ok(())
+}
+
+
+/// Writes the removed invariant sites of each base on one line, in A, C, G, T order and separated
+/// by spaces, which is the file RAxML's Stamatakis correction reads (via [asc~FILE] in its
+/// partition file).
+fn write_raxml_info(raxml_info: Option<&Path>, stats: &FilterStats) -> Result<(), Error> {
+    if let Some(raxml_info_path) = raxml_info {
+        let mut file = misc::create_file(raxml_info_path)?;
+        writeln!(file, "{} {} {} {}", stats.invariant_a_removed, stats.invariant_c_removed,
+                 stats.invariant_g_removed, stats.invariant_t_removed)?;
+    }
+    Ok(())
+}
+
+
+/// Writes a single line with a 1 for each kept site and a 0 for each removed site, as long as the
+/// input alignment (whether or not --soft_mask is used).
+fn write_mask_track(mask_track: Option<&Path>, keep: &BitVec) -> Result<(), Error> {
+    if let Some(mask_track_path) = mask_track {
+        let mut file = BufWriter::new(misc::create_file(mask_track_path)?);
+        let track: Vec<u8> = keep.iter().map(|kept| if *kept { b'1' } else { b'0' }).collect();
+        file.write_all(&track)?;
+        writeln!(file)?;
+        file.flush()?;
+    }
+    Ok(())
+}
+
+
+/// Writes a single-record FASTA of the majority-rule consensus over the kept sites. Ties are
+/// resolved by tie, and sites where the most common base is in less than min_frac of the sequences
+/// (or which have no bases at all) get an N.
+#[allow(clippy::too_many_arguments)]
+fn write_consensus(consensus: Option<&Path>, base_counts: &[[u32; 4]], seq_count: usize,
+                   min_frac: f64, tie: ConsensusTie, keep: &BitVec, output_size: usize,
+                   mask_char: Option<u8>)
+        -> Result<(), Error> {
+    if let Some(consensus_path) = consensus {
+        let full_seq: Vec<u8> = base_counts.iter().map(|counts| {
+            consensus_base(counts, seq_count, min_frac, tie)
+        }).collect();
+        let mut file = misc::create_file(consensus_path)?;
+        let unchanged = std::array::from_fn(|i| i as u8);
+        let seq = remove_columns(&full_seq, keep, output_size, mask_char, &unchanged);
+        writeln!(file, ">consensus\n{}", seq)?;
+    }
+    Ok(())
+}
+
+
+/// Returns the most common base at a site, or N if there are no bases or the most common base is
+/// in less than min_frac of the sequences. Ties between two or more bases are resolved by tie.
+fn consensus_base(counts: &[u32; 4], seq_count: usize, min_frac: f64, tie: ConsensusTie) -> u8 {
+    let best_count = *counts.iter().max().unwrap();
+    let frac = best_count as f64 / seq_count as f64;
+    if best_count == 0 || frac < min_frac {
+        return b'N';
+    }
+    let tied: Vec<usize> = (0..4).filter(|&b| counts[b] == best_count).collect();
+    if tied.len() == 1 {
+        return b"ACGT"[tied[0]];
+    }
+    match tie {
+        ConsensusTie::Iupac => iupac_code(tied.iter().fold(0, |bits, &b| bits | 1 << b)),
+        ConsensusTie::N => b'N',
+        ConsensusTie::First => b"ACGT"[tied[0]],
+    }
+}
+
+
+/// Writes the site frequency spectrum of the kept biallelic sites (those with exactly two of A, C,
+/// G and T): how many sites have each minor allele count (folded) or, if an ancestral sequence is
+/// given, each derived allele count (unfolded). Biallelic sites which can't be polarised are left
+/// out of an unfolded spectrum, and this returns how many there were of each kind: those where the
+/// ancestral sequence has no base, and those where its base wasn't one of their two bases.
+fn write_sfs(sfs: &Path, base_counts: &[[u32; 4]], keep: &BitVec, seq_count: usize,
+             ancestral: Option<&[u8]>) -> Result<(usize, usize), Error> {
+    let max_count = if ancestral.is_some() { seq_count.saturating_sub(1) } else { seq_count / 2 };
+    let mut spectrum = vec![0; max_count + 1];
+    let (mut missing, mut mismatched) = (0, 0);
+    for i in keep.iter_ones() {
+        let alleles: Vec<usize> = (0..4).filter(|&b| base_counts[i][b] > 0).collect();
+        if alleles.len() != 2 {
+            continue;
+        }
+        let (count_1, count_2) = (base_counts[i][alleles[0]], base_counts[i][alleles[1]]);
+        let count = match ancestral {
+            None => count_1.min(count_2),
+            Some(ancestral) => match b"ACGT".iter().position(|&b| b == ancestral[i]) {
+                Some(b) if b == alleles[0] => count_2,
+                Some(b) if b == alleles[1] => count_1,
+                Some(_) => {
+                    mismatched += 1;
+                    continue;
+                }
+                None => {
+                    missing += 1;
+                    continue;
+                }
+            },
+        };
+        spectrum[count as usize] += 1;
+    }
+    let mut file = misc::create_file(sfs)?;
+    let label = if ancestral.is_some() { "derived" } else { "minor" };
+    writeln!(file, "{}_allele_count\tsites", label)?;
+    for (count, sites) in spectrum.iter().enumerate().skip(1) {
+        writeln!(file, "{}\t{}", count, sites)?;
+    }
+    Ok((missing, mismatched))
+}
+
+
+/// Writes the kept variable sites as a sites-only VCF (no sample columns), using the input
+/// alignment's 1-based positions on a single contig named 1 (like snp-sites). REF is the most
+/// common base (the first in ACGT order if tied) and ALT is the others in ACGT order. The INFO
+/// field gives each ALT's allele count (AC) and frequency (AF), and the total allele number (AN),
+/// which is the number of sequences with a base since each one is a haploid call. With --iupac,
+/// ambiguous bases add to the AC of each base they could be, but only once to AN, and a site is
+/// only included if no single base fits every sequence (so an all-R site isn't an A/G SNP).
+fn write_vcf(vcf: Option<&Path>, base_counts: &[[u32; 4]], acgt_counts: &[usize],
+             variable: &BitVec, keep: &BitVec) -> Result<(), Error> {
+    let vcf_path = match vcf {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let mut file = BufWriter::new(misc::create_file(vcf_path)?);
+    writeln!(file, "##fileformat=VCFv4.2")?;
+    writeln!(file, "##source=Core-SNP-filter")?;
+    writeln!(file, "##contig=<ID=1,length={}>", base_counts.len())?;
+    writeln!(file, "##INFO=<ID=AC,Number=A,Type=Integer,Description=\"Allele count for each ALT \
+                    allele\">")?;
+    writeln!(file, "##INFO=<ID=AN,Number=1,Type=Integer,Description=\"Total number of alleles\">")?;
+    writeln!(file, "##INFO=<ID=AF,Number=A,Type=Float,Description=\"Allele frequency for each \
+                    ALT allele\">")?;
+    writeln!(file, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO")?;
+    for i in keep.iter_ones().filter(|&i| variable[i]) {
+        let counts = &base_counts[i];
+        let reference = (0..4).rev().max_by_key(|&b| counts[b]).unwrap();
+        let alts: Vec<usize> = (0..4).filter(|&b| b != reference && counts[b] > 0).collect();
+        let total = acgt_counts[i];
+        let join = |f: &dyn Fn(usize) -> String| {
+            alts.iter().map(|&b| f(b)).collect::<Vec<_>>().join(",")
+        };
+        writeln!(file, "1\t{}\t.\t{}\t{}\t.\t.\tAC={};AN={};AF={}", i + 1,
+                 b"ACGT"[reference] as char, join(&|b| (b"ACGT"[b] as char).to_string()),
+                 join(&|b| counts[b].to_string()), total,
+                 join(&|b| format!("{:.4}", counts[b] as f64 / total as f64)))?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+
+/// Returns the first sequence of the --ancestral FASTA file in uppercase (with U as T), which must
+/// be the same length as the alignment.
+fn load_ancestral(filename: &Path, alignment_length: usize) -> Result<Vec<u8>, Error> {
+    let mut fasta_reader = misc::open_fasta_file(filename, false)?;
+    let record = match fasta_reader.next() {
+        Some(record) => misc::check_record(record, filename)?,
+        None => return Err(Error::BadArguments(format!("{} contains no sequences",
+                                                       filename.display()))),
+    };
+    let seq = misc::remove_carriage_returns(record.full_seq());
+    if seq.len() != alignment_length {
+        return Err(Error::BadArguments(format!("the --ancestral sequence is {} bp but the \
+                                                alignment is {} bp", seq.len(),
+                                               alignment_length)));
+    }
+    Ok(ancestral_bases(&seq))
+}
+
+
+/// Returns the bases of the --ancestral_name sequence from the alignment. Its length was already
+/// checked when the sites were counted.
+fn find_ancestral(alignment: &Alignment, name: &str) -> Result<Vec<u8>, Error> {
+    let mut ancestral = None;
+    alignment.for_each_record(|header, seq| {
+        if ancestral.is_none() && seq_name(header) == name {
+            ancestral = Some(ancestral_bases(seq));
+        }
+        Ok(())
+    })?;
+    ancestral.ok_or_else(|| {
+        Error::BadArguments(format!("the --ancestral_name sequence ({}) is not in the alignment",
+                                    name))
+    })
+}
+
+
+/// Converts an ancestral sequence to uppercase, with U as T.
+fn ancestral_bases(seq: &[u8]) -> Vec<u8> {
+    seq.iter().map(|b| match b.to_ascii_uppercase() { b'U' => b'T', b => b }).collect()
+}
+
+
+/// Returns only the first of each group of identical sequences, keeping their order. If dedup_map
+/// is given, it saves a table of each kept sequence's name and the names of all sequences in its
+/// group (including itself). If counts is true, each kept sequence's header is replaced with its
+/// name and the size of its group.
+fn dedup_sequences(seqs: Vec<(String, String)>, dedup_map: Option<&Path>, counts: bool)
+        -> Result<Vec<(String, String)>, Error> {
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut group_indices: HashMap<&str, usize> = HashMap::new();
+    for (i, (_, seq)) in seqs.iter().enumerate() {
+        match group_indices.entry(seq.as_str()) {
+            Entry::Occupied(entry) => groups[*entry.get()].push(i),
+            Entry::Vacant(entry) => {
+                entry.insert(groups.len());
+                groups.push(vec![i]);
+            }
+        }
+    }
+    drop(group_indices);
+
+    if let Some(dedup_map_path) = dedup_map {
+        let name = |i: usize| seq_name(&seqs[i].0).to_string();
+        let mut file = misc::create_file(dedup_map_path)?;
+        writeln!(file, "representative\tmembers")?;
+        for group in &groups {
+            let members: Vec<String> = group.iter().map(|&i| name(i)).collect();
+            writeln!(file, "{}\t{}", name(group[0]), members.join(","))?;
+        }
+    }
+
+    // The groups are in order of their first sequence, so the output keeps the input's order.
+    let mut seqs: Vec<Option<(String, String)>> = seqs.into_iter().map(Some).collect();
+    Ok(groups.iter().map(|group| {
+        let (header, seq) = seqs[group[0]].take().unwrap();
+        if counts {
+            (format!("{} count={}", seq_name(&header), group.len()), seq)
+        } else {
+            (header, seq)
+        }
+    }).collect())
+}
+
+
+/// Sorts the filtered sequences by name or by how many bases (or amino acids) they have. The sort
+/// is stable, so sequences with the same name stay in their input order.
+fn sort_sequences(seqs: &mut [(String, String)], order: SortOrder, alphabet: Alphabet) {
+    match order {
+        SortOrder::Name => seqs.sort_by(|(a, _), (b, _)| seq_name(a).cmp(seq_name(b))),
+        SortOrder::Aligned => {
+            let residues: &[u8] = match alphabet {
+                Alphabet::Dna => b"ACGT",
+                Alphabet::Rna => b"ACGU",
+                Alphabet::Protein => AMINO_ACIDS,
+            };
+            let aligned = |seq: &str| {
+                seq.bytes().filter(|b| residues.contains(&b.to_ascii_uppercase())).count()
+            };
+            // The filtered sequences are all the same length, so counts order the same as
+            // fractions.
+            seqs.sort_by_cached_key(|(header, seq)| {
+                (std::cmp::Reverse(aligned(seq)), seq_name(header).to_string())
+            });
+        }
+    }
+}
+
+
+/// Writes a square matrix of pairwise SNP distances between the filtered sequences, in PHYLIP
+/// format (the sequence count, then one row per sequence starting with its name). Rows are
+/// calculated in parallel.
+fn write_dist_matrix(dist_matrix: Option<&Path>, seqs: &[(String, String)],
+                     ignore_missing: bool) -> Result<(), Error> {
+    if let Some(dist_matrix_path) = dist_matrix {
+        let n = seqs.len();
+        let upper: Vec<Vec<usize>> = (0..n).into_par_iter().map(|i| {
+            ((i + 1)..n).map(|j| snp_distance(&seqs[i].1, &seqs[j].1, ignore_missing)).collect()
+        }).collect();
+        let mut file = misc::create_file(dist_matrix_path)?;
+        writeln!(file, "{}", n)?;
+        for (i, (header, _)) in seqs.iter().enumerate() {
+            let name = seq_name(header);
+            let row: Vec<String> = (0..n).map(|j| {
+                match i.cmp(&j) {
+                    std::cmp::Ordering::Less => upper[i][j - i - 1],
+                    std::cmp::Ordering::Greater => upper[j][i - j - 1],
+                    std::cmp::Ordering::Equal => 0,
+                }.to_string()
+            }).collect();
+            writeln!(file, "{}\t{}", name, row.join("\t"))?;
+        }
+    }
+    Ok(())
+}
+
+
+/// Returns the number of sites where two sequences differ (ignoring case). If ignore_missing is
+/// true, sites where either sequence isn't A, C, G or T are skipped.
+fn snp_distance(seq_a: &str, seq_b: &str, ignore_missing: bool) -> usize {
+    seq_a.bytes().zip(seq_b.bytes()).filter(|&(a, b)| {
+        if ignore_missing && !(is_acgt(a) && is_acgt(b)) {
+            return false;
+        }
+        !a.eq_ignore_ascii_case(&b)
+    }).count()
+}
+
+
+fn is_acgt(base: u8) -> bool {
+    b"ACGTacgt".contains(&base)
+}
+
+
+/// Sequences are counted in batches of roughly this many bases.
+const BATCH_SIZE: usize = 64 * 1024 * 1024;
+
+/// Each parallel job counts this many alignment sites.
+const BLOCK_SIZE: usize = 4096;
+
+
+/// Per-site information gathered in a single pass over the alignment.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SiteCounts {
+    /// Whether any sequence has an A at each site (likewise for c, g and t). With IUPAC codes,
+    /// ambiguous bases set each base they could be, e.g. R sets both a and g.
+    pub a: BitVec,
+    pub c: BitVec,
+    pub g: BitVec,
+    pub t: BitVec,
+    /// Number of sequences in the alignment
+    pub seq_count: usize,
+    /// How many of the sequences aren't missing at each site. By default, this means having a
+    /// canonical base, but --iupac, --missing_chars and --present_chars change what counts.
+    pub acgt_counts: Vec<usize>,
+    /// How many of the sequences have a gap (-) at each site
+    pub gap_counts: Vec<usize>,
+    /// How many of the sequences have an N at each site
+    pub n_counts: Vec<usize>,
+    /// How many of the sequences have neither a base nor a gap at each site, e.g. N, ? or an IUPAC
+    /// code (unless --iupac is used, which makes them count as bases)
+    pub ambiguous_counts: Vec<usize>,
+    /// Whether each site has variation, i.e. no single base fits every sequence at that site
+    pub variable: BitVec,
+    /// For each invariant site with bases, the base (A, C, G or T) which fits every sequence
+    pub shared_base: Vec<Option<u8>>,
+    /// How many sequences have each base (A, C, G and T) at each site. With IUPAC codes,
+    /// ambiguous bases add to each base they could be.
+    pub base_counts: Vec<[u32; 4]>,
+    /// How many sequences have each amino acid (in AMINO_ACIDS order) at each site. This is only
+    /// filled in for protein alignments, whose per-base fields above are all zero/false.
+    pub residue_counts: Vec<[u32; 20]>,
+    /// The name (first word of the header) of each sequence, in order
+    pub seq_names: Vec<String>,
+}
+
+
+/// Counts the bases at each site of the alignment. All non-ACGT characters count as missing for
+/// the purposes of filtering, but gaps and Ns are also tallied separately for reporting. If iupac
+/// is true, ambiguity codes count as present and add to the count of each base they could be. If
+/// missing_chars is given, only those characters count as missing and all others count as present
+/// (though only bases can make a site variable). present_chars is the reverse: only those
+/// characters count as present, and only those which are bases can make a site variable. If
+/// lowercase_missing is true, lowercase characters are also missing. If rna is true, U counts as
+/// T. Only the characters in gap_chars (which shouldn't include any bases) are counted as gaps.
+/// It's an error for any sequence to differ in length from alignment_length. Counted sequences
+/// are added to progress.
+#[allow(clippy::too_many_arguments)]
+pub fn bitvectors_and_counts(alignment: &Alignment, alignment_length: usize, iupac: bool,
+                             missing_chars: Option<&str>, present_chars: Option<&str>,
+                             lowercase_missing: bool, rna: bool, gap_chars: &str,
+                             progress: &Progress)
+        -> Result<SiteCounts, Error> {
+    let mut base_counts = vec![[0; 8]; alignment_length];
+    let char_table = if iupac || missing_chars.is_some() || present_chars.is_some() ||
+                        lowercase_missing {
+        Some(CharTable::new(iupac, missing_chars, present_chars, lowercase_missing, rna))
+    } else {
+        None
+    };
+    let mut slots = if rna { RNA_BASE_SLOTS } else { BASE_SLOTS };
+    set_gap_slots(&mut slots, gap_chars, 4, 7);
+    let seq_names = count_in_batches(alignment, alignment_length, progress, |seqs| {
+        count_bases(seqs, &mut base_counts, &slots, char_table.as_ref());
+    })?;
+    let seq_count = seq_names.len();
+
+    // The fast counting path doesn't fill in the last two counts, but they're easy to derive.
+    if char_table.is_none() {
+        for counts in base_counts.iter_mut() {
+            counts[6] = counts[..4].iter().sum();
+            counts[7] = counts[6];
+        }
+    }
+
+    // A site is invariant if a single base fits every sequence which has a base there. Without
+    // IUPAC codes, that just means there's only one base at the site.
+    let shared_base: Vec<Option<u8>> = base_counts.iter().map(|counts| {
+        if counts[6] == 0 { return None; }
+        (0..4).find(|&b| counts[b] == counts[6]).map(|b| b"ACGT"[b])
+    }).collect();
+
+    Ok(SiteCounts {
+        a: base_counts.iter().map(|counts| counts[0] > 0).collect(),
+        c: base_counts.iter().map(|counts| counts[1] > 0).collect(),
+        g: base_counts.iter().map(|counts| counts[2] > 0).collect(),
+        t: base_counts.iter().map(|counts| counts[3] > 0).collect(),
+        seq_count,
+        acgt_counts: base_counts.iter().map(|counts| counts[7] as usize).collect(),
+        gap_counts: base_counts.iter().map(|counts| counts[4] as usize).collect(),
+        n_counts: base_counts.iter().map(|counts| counts[5] as usize).collect(),
+        ambiguous_counts: base_counts.iter()
+            .map(|counts| seq_count - counts[6] as usize - counts[4] as usize).collect(),
+        variable: base_counts.iter().zip(&shared_base)
+            .map(|(counts, base)| counts[6] > 0 && base.is_none()).collect(),
+        shared_base,
+        base_counts: base_counts.iter().map(|counts| [counts[0], counts[1], counts[2], counts[3]])
+            .collect(),
+        residue_counts: Vec::new(),
+        seq_names,
+    })
+}
+
+
+/// The protein version of bitvectors_and_counts: the 20 amino acids count as present and all
+/// other characters count as missing, with gaps and Xs tallied separately (in gap_counts and
+/// n_counts). A site is variable if it has more than one amino acid. If lowercase_missing is true,
+/// lowercase characters are also missing. Only the characters in gap_chars are counted as gaps.
+pub fn protein_counts(alignment: &Alignment, alignment_length: usize, lowercase_missing: bool,
+                      gap_chars: &str, progress: &Progress)
+        -> Result<SiteCounts, Error> {
+    let mut slots = residue_slots(lowercase_missing);
+    set_gap_slots(&mut slots, gap_chars, 20, 22);
+    let mut counts = vec![[0; 23]; alignment_length];
+    let seq_names = count_in_batches(alignment, alignment_length, progress, |seqs| {
+        count_residues(seqs, &mut counts, &slots);
+    })?;
+
+    let shared_base: Vec<Option<u8>> = counts.iter().map(|counts| {
+        let mut residues = (0..20).filter(|&r| counts[r] > 0);
+        match (residues.next(), residues.next()) {
+            (Some(r), None) => Some(AMINO_ACIDS[r]),
+            _ => None,
+        }
+    }).collect();
+    let present: Vec<usize> = counts.iter()
+        .map(|counts| counts[..20].iter().sum::<u32>() as usize).collect();
+    let ambiguous_counts = counts.iter().map(|counts| (counts[21] + counts[22]) as usize).collect();
+
+    Ok(SiteCounts {
+        a: bitvec![0; alignment_length],
+        c: bitvec![0; alignment_length],
+        g: bitvec![0; alignment_length],
+        t: bitvec![0; alignment_length],
+        seq_count: seq_names.len(),
+        variable: present.iter().zip(&shared_base)
+            .map(|(&count, residue)| count > 0 && residue.is_none()).collect(),
+        acgt_counts: present,
+        gap_counts: counts.iter().map(|counts| counts[20] as usize).collect(),
+        n_counts: counts.iter().map(|counts| counts[21] as usize).collect(),
+        ambiguous_counts,
+        shared_base,
+        base_counts: vec![[0; 4]; alignment_length],
+        residue_counts: counts.iter().map(|counts| {
+            let mut residue_counts = [0; 20];
+            residue_counts.copy_from_slice(&counts[..20]);
+            residue_counts
+        }).collect(),
+        seq_names,
+    })
+}
+
+
+/// Passes the alignment's sequences to count in batches of roughly BATCH_SIZE bases (or all at
+/// once if the alignment is already in memory), checking that each is alignment_length long.
+/// Returns the name of each sequence, in order.
+fn count_in_batches<F>(alignment: &Alignment, alignment_length: usize, progress: &Progress,
+                       count: F)
+        -> Result<Vec<String>, Error>
+        where F: FnMut(&[&[u8]]) {
+    count_in_batches_unchecked(alignment, alignment_length, progress, count).map_err(|e| {
+        // A length error blames the sequence which doesn't match the first, but the first might
+        // be the odd one out.
+        if let Some(warning) = first_length_warning(alignment, alignment_length) {
+            misc::print_warning(&warning);
+        }
+        e
+    })
+}
+
+
+fn count_in_batches_unchecked<F>(alignment: &Alignment, alignment_length: usize,
+                                 progress: &Progress, mut count: F)
+        -> Result<Vec<String>, Error>
+        where F: FnMut(&[&[u8]]) {
+    let mut seq_names = Vec::new();
+    if let Alignment::InMemory(records) = alignment {
+        for record in records {
+            check_seq_length(&record.header, &record.seq, alignment_length)?;
+            seq_names.push(seq_name(&record.header).to_string());
+        }
+        let seqs: Vec<&[u8]> = records.iter().map(|r| r.seq.as_slice()).collect();
+        count(&seqs);
+        progress.add(seqs.len());
+    } else {
+        let mut batch = Vec::new();
+        let mut batch_size = 0;
+        alignment.for_each_record(|header, seq| {
+            check_seq_length(header, seq, alignment_length)?;
+            seq_names.push(seq_name(header).to_string());
+            batch_size += seq.len();
+            batch.push(seq.to_vec());
+            if batch_size >= BATCH_SIZE {
+                count(&batch.iter().map(Vec::as_slice).collect::<Vec<_>>());
+                progress.add(batch.len());
+                batch.clear();
+                batch_size = 0;
+            }
+            Ok(())
+        })?;
+        count(&batch.iter().map(Vec::as_slice).collect::<Vec<_>>());
+        progress.add(batch.len());
+    }
+    Ok(seq_names)
+}
+
+
+fn check_seq_length(header: &str, seq: &[u8], alignment_length: usize) -> Result<(), Error> {
+    if seq.is_empty() {
+        return Err(Error::MalformedFasta(format!("sequence {} is empty (use --skip_empty to leave \
+                                                  out empty sequences)", seq_name(header))));
+    }
+    if alignment_length != seq.len() {
+        let name = seq_name(header);
+        return Err(Error::MalformedFasta(format!("all sequences must be equal length: {} is {} \
+                                                  bp but expected {} bp",
+                                                 name, seq.len(), alignment_length)));
+    }
+    Ok(())
+}
+
+
+/// The first sequence's length is used as the alignment's length, so if that sequence is truncated
+/// (or too long), the error names a good sequence instead. This returns a warning if the first
+/// sequence's length isn't the most common length. It takes an extra pass over the alignment, so
+/// it's only used after an error (and gives nothing if that pass fails too).
+fn first_length_warning(alignment: &Alignment, alignment_length: usize) -> Option<String> {
+    let mut first_name = None;
+    let mut lengths: HashMap<usize, usize> = HashMap::new();
+    alignment.for_each_record(|header, seq| {
+        first_name.get_or_insert_with(|| seq_name(header).to_string());
+        *lengths.entry(seq.len()).or_default() += 1;
+        Ok(())
+    }).ok()?;
+    let seq_count: usize = lengths.values().sum();
+    let (&common_length, &common_count) = lengths.iter()
+        .max_by_key(|&(&length, &count)| (count, std::cmp::Reverse(length)))?;
+    let first_count = lengths.get(&alignment_length).copied().unwrap_or(0);
+    if common_length == alignment_length || common_count == first_count {
+        return None;
+    }
+    Some(format!("the first sequence ({}) is {} bp, but most sequences ({} of {}) are {} bp, so \
+                  the first sequence may be the one with the wrong length", first_name?,
+                 alignment_length, common_count, seq_count, common_length))
+}
+
+
+/// Adds a batch of sequences to the per-site counts: A, C, G, T, gap, N, sequences with a base and
+/// sequences which aren't missing. Each site is independent, so blocks of sites are counted in
+/// parallel. Since the blocks don't overlap, no merging is needed and the results are the same
+/// regardless of thread count.
+///
+/// Without a character table (the default), only the first six counts are filled in, using a
+/// branchless lookup (see BASE_SLOTS) which also puts all other characters in the last count as
+/// scratch space. With a table, each character is looked up to see which bases it could be (e.g. R
+/// adds to both A and G) and whether it counts as missing.
+fn count_bases<S: AsRef<[u8]> + Sync>(seqs: &[S], base_counts: &mut [[u32; 8]],
+                                      slots: &[u8; 256], char_table: Option<&CharTable>) {
+    base_counts.par_chunks_mut(BLOCK_SIZE).enumerate().for_each(|(block, counts)| {
+        let start = block * BLOCK_SIZE;
+        for seq in seqs {
+            let block_seq = &seq.as_ref()[start..start + counts.len()];
+            if let Some(table) = char_table {
+                for (count, base) in counts.iter_mut().zip(block_seq) {
+                    count[4] += (slots[*base as usize] == 4) as u32;
+                    count[5] += base.eq_ignore_ascii_case(&b'N') as u32;
+                    if !table.present[*base as usize] {
+                        continue;
+                    }
+                    let bases = table.bases[*base as usize];
+                    for (b, base_count) in count[..4].iter_mut().enumerate() {
+                        *base_count += ((bases >> b) & 1) as u32;
+                    }
+                    count[6] += (bases != 0) as u32;
+                    count[7] += 1;
+                }
+            } else {
+                for (count, base) in counts.iter_mut().zip(block_seq) {
+                    count[slots[*base as usize] as usize] += 1;
+                }
+            }
+        }
+    });
+}
+
+
+/// Which count each character adds to in the default counting mode: 0-3 for A, C, G and T (either
+/// case), 4 for a gap (-, see set_gap_slots), 5 for N (either case) and 7 for anything else.
+/// Indexing this instead of matching on the character avoids a hard-to-predict branch for every
+/// base of the alignment.
+const BASE_SLOTS: [u8; 256] = base_slots(false);
+
+
+/// The same as BASE_SLOTS, but U (either case) adds to the T count.
+const RNA_BASE_SLOTS: [u8; 256] = base_slots(true);
+
+
+const fn base_slots(rna: bool) -> [u8; 256] {
+    let mut slots = [7; 256];
+    slots[b'A' as usize] = 0;
+    slots[b'a' as usize] = 0;
+    slots[b'C' as usize] = 1;
+    slots[b'c' as usize] = 1;
+    slots[b'G' as usize] = 2;
+    slots[b'g' as usize] = 2;
+    slots[b'T' as usize] = 3;
+    slots[b't' as usize] = 3;
+    slots[b'-' as usize] = 4;
+    slots[b'N' as usize] = 5;
+    slots[b'n' as usize] = 5;
+    if rna {
+        slots[b'U' as usize] = 3;
+        slots[b'u' as usize] = 3;
+    }
+    slots
+}
+
+
+/// Changes which characters go in the gap slot of a counting table (see BASE_SLOTS and
+/// residue_slots) from just - to those in gap_chars. Any others which were gaps (i.e. -) go in
+/// other_slot instead.
+fn set_gap_slots(slots: &mut [u8; 256], gap_chars: &str, gap_slot: u8, other_slot: u8) {
+    for slot in slots.iter_mut().filter(|slot| **slot == gap_slot) {
+        *slot = other_slot;
+    }
+    for c in gap_chars.bytes() {
+        slots[c as usize] = gap_slot;
+    }
+}
+
+
+/// The 20 standard amino acids, in the order of SiteCounts::residue_counts.
+const AMINO_ACIDS: &[u8; 20] = b"ACDEFGHIKLMNPQRSTVWY";
+
+
+/// Adds a batch of protein sequences to the per-site counts in the same way as count_bases: the 20
+/// amino acids (see residue_slots), then gaps, Xs and lastly everything else as scratch space.
+fn count_residues(seqs: &[&[u8]], residue_counts: &mut [[u32; 23]], slots: &[u8; 256]) {
+    residue_counts.par_chunks_mut(BLOCK_SIZE).enumerate().for_each(|(block, counts)| {
+        let start = block * BLOCK_SIZE;
+        for seq in seqs {
+            let block_seq = &seq[start..start + counts.len()];
+            for (count, residue) in counts.iter_mut().zip(block_seq) {
+                count[slots[*residue as usize] as usize] += 1;
+            }
+        }
+    });
+}
+
+
+/// Which count each character adds to when counting protein: 0-19 for the amino acids (in
+/// AMINO_ACIDS order), 20 for a gap, 21 for X and 22 for anything else.
+fn residue_slots(lowercase_missing: bool) -> [u8; 256] {
+    let mut slots = [22; 256];
+    for (i, &residue) in AMINO_ACIDS.iter().enumerate() {
+        slots[residue as usize] = i as u8;
+        if !lowercase_missing {
+            slots[residue.to_ascii_lowercase() as usize] = i as u8;
+        }
+    }
+    slots[b'-' as usize] = 20;
+    slots[b'X' as usize] = 21;
+    slots[b'x' as usize] = 21;
+    slots
+}
+
+
+/// A lookup table for the less common counting modes (--iupac, --missing_chars, --present_chars
+/// and --lowercase_missing), giving the bases each character could be (as bits: A = 1, C = 2,
+/// G = 4, T = 8) and whether it is present.
+struct CharTable {
+    bases: [u8; 256],
+    present: [bool; 256],
+}
+
+
+impl CharTable {
+    fn new(iupac: bool, missing_chars: Option<&str>, present_chars: Option<&str>,
+           lowercase_missing: bool, rna: bool) -> CharTable {
+        let mut table = CharTable { bases: [0; 256], present: [false; 256] };
+        for c in 0..=255u8 {
+            let i = c as usize;
+            if lowercase_missing && c.is_ascii_lowercase() {
+                continue;
+            }
+            if present_chars.map_or(false, |present| !present.as_bytes().contains(&c)) {
+                continue;
+            }
+            let base = if rna && c.eq_ignore_ascii_case(&b'U') { b'T' } else { c };
+            let is_canonical = b"ACGTacgt".contains(&base);
+            table.bases[i] = if iupac || is_canonical { iupac_bases(base) } else { 0 };
+            table.present[i] = match (missing_chars, present_chars) {
+                (Some(missing), _) => !missing.as_bytes().contains(&c),
+                (None, Some(_)) => true,
+                (None, None) => table.bases[i] != 0,
+            };
+        }
+        table
+    }
+}
+
+
+/// Returns the bases (as bits: A = 1, C = 2, G = 4, T = 8) which an IUPAC code could be. N is
+/// treated as missing, not as an ambiguity code, so it (and any other character) gives zero.
+fn iupac_bases(base: u8) -> u8 {
+    match base.to_ascii_uppercase() {
+        b'A' => 0b0001, b'C' => 0b0010, b'G' => 0b0100, b'T' => 0b1000,
+        b'R' => 0b0101, b'Y' => 0b1010, b'S' => 0b0110, b'W' => 0b1001,
+        b'K' => 0b1100, b'M' => 0b0011, b'B' => 0b1110, b'D' => 0b1101,
+        b'H' => 0b1011, b'V' => 0b0111,
+        _ => 0,
+    }
+}
+
+
+/// The reverse of iupac_bases: returns the IUPAC code for a set of bases (as bits: A = 1, C = 2,
+/// G = 4, T = 8). All four bases (or none) give N.
+fn iupac_code(bases: u8) -> u8 {
+    b"NACMGRSVTWYHKDBN"[bases as usize & 0b1111]
+}
+
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use std::fs::File;
+    use std::io::Write;
+    use std::str::from_utf8;
+    use tempfile::{TempDir,tempdir};
+    use super::*;
+
+    fn make_test_file(contents: &str) -> (PathBuf, TempDir) {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("test.fasta");
+        let mut file = File::create(&file_path).unwrap();
+        write!(file, "{}", contents).unwrap();
+        (file_path, dir)
+    }
+
+    fn options(exclude_invariant: bool, invariant_counts: bool, core: f64) -> FilterOptions {
+        FilterOptions { exclude_invariant, invariant_counts, core, ..Default::default() }
+    }
+
+    #[test]
+    fn test_check_arguments_1() {
+        // Valid arguments.
+        check_arguments(&options(true, false, 0.0)).unwrap();
+        check_arguments(&options(true, false, 0.5)).unwrap();
+        check_arguments(&options(true, false, 1.0)).unwrap();
+        check_arguments(&options(false, true, 0.0)).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_check_arguments_2() {
+        // Invalid arguments.
+        check_arguments(&options(true, false, -0.1)).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_check_arguments_3() {
+        // Invalid arguments.
+        check_arguments(&options(true, false, 1.1)).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_check_arguments_4() {
+        // Invalid arguments.
+        check_arguments(&options(true, true, 0.0)).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_check_arguments_5() {
+        // Invalid arguments.
+        check_arguments(&options(false, true, 0.5)).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_check_arguments_6() {
+        // Invalid arguments.
+        check_arguments(&FilterOptions { table: Some("table.tsv".into()),
+                                         ..options(false, true, 0.0) }).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_check_arguments_7() {
+        // Invalid arguments.
+        check_arguments(&FilterOptions { count_only: true, ..options(false, true, 0.0) }).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_check_arguments_8() {
+        // Invalid arguments.
+        check_arguments(&FilterOptions { soft_mask: true, coord_map: Some("map.tsv".into()),
+                                         ..options(true, false, 0.0) }).unwrap();
+    }
+
+    #[test]
+    fn test_check_arguments_9() {
+        // --core and --core_count can be used together.
+        check_arguments(&FilterOptions { core_count: Some(2), ..options(false, false, 0.5) })
+            .unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot be used together")]
+    fn test_check_arguments_11() {
+        check_arguments(&FilterOptions { include_samples: Some("a.txt".into()),
+                                         exclude_samples: Some("b.txt".into()),
+                                         ..options(false, false, 0.0) }).unwrap();
+    }
+
+    #[test]
+    fn test_parse_core() {
+        assert_eq!(parse_core("0.95"), Ok(0.95));
+        assert_eq!(parse_core("95%"), Ok(0.95));
+        assert_eq!(parse_core("100%"), Ok(1.0));
+        assert_eq!(parse_core("95"), Ok(95.0));
+        assert!(parse_core("95 percent").is_err());
+        assert!(parse_core("%").is_err());
+
+        // Percentages also work from the command line.
+        let matches = FilterOptions::augment_args(Command::new("coresnpfilter"))
+            .get_matches_from(["coresnpfilter", "--core", "95%"]);
+        assert_eq!(FilterOptions::from_arg_matches(&matches).unwrap().core, 0.95);
+    }
+
+    #[test]
+    #[should_panic(expected = "or a percentage")]
+    fn test_check_arguments_10() {
+        // A bare 95 is ambiguous, so it's rejected.
+        check_arguments(&options(false, false, parse_core("95").unwrap())).unwrap();
+    }
+
+    #[test]
+    fn test_is_pars_informative() {
+        assert!(!is_pars_informative(&[0, 0, 0, 0]));
+        assert!(!is_pars_informative(&[5, 0, 0, 0]));
+        assert!(!is_pars_informative(&[5, 1, 0, 0]));
+        assert!(!is_pars_informative(&[1, 1, 1, 1]));
+        assert!(is_pars_informative(&[2, 2, 0, 0]));
+        assert!(is_pars_informative(&[0, 3, 1, 2]));
+    }
+
+    #[test]
+    fn test_pars_informative_iupac() {
+        // The all-R site has counts of 3 for both A and G, but R fits every sequence, so it's
+        // invariant rather than informative.
+        let (path, _dir) = make_test_file(">seq_1\nRACGTA\n\
+                                           >seq_2\nRACGTA\n\
+                                           >seq_3\nRACTTA\n");
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &FilterOptions { iupac: true,
+                                                         ..options(true, false, 0.0) },
+                                 &mut stdout).unwrap();
+        assert_eq!(stats.pars_informative, 0);
+        assert_eq!(stats.output_size, 1);
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &FilterOptions { iupac: true, pars_informative: true,
+                                                         ..options(false, false, 0.0) },
+                                 &mut stdout).unwrap();
+        assert_eq!(stats.pars_informative, 0);
+        assert_eq!(stats.output_size, 0);
+    }
+
+    #[test]
+    fn test_consensus_base() {
+        // A 5-A/5-G tie under each policy.
+        let tied = [5, 0, 5, 0];
+        assert_eq!(consensus_base(&tied, 10, 0.0, ConsensusTie::Iupac), b'R');
+        assert_eq!(consensus_base(&tied, 10, 0.0, ConsensusTie::N), b'N');
+        assert_eq!(consensus_base(&tied, 10, 0.0, ConsensusTie::First), b'A');
+
+        // Without a tie, the policy doesn't matter.
+        assert_eq!(consensus_base(&[5, 0, 4, 1], 10, 0.0, ConsensusTie::Iupac), b'A');
+        assert_eq!(consensus_base(&[1, 0, 4, 0], 10, 0.0, ConsensusTie::N), b'G');
+
+        // The minimum fraction applies to the tied bases' count, before the tie is resolved.
+        assert_eq!(consensus_base(&tied, 10, 0.5, ConsensusTie::Iupac), b'R');
+        assert_eq!(consensus_base(&tied, 10, 0.6, ConsensusTie::Iupac), b'N');
+        assert_eq!(consensus_base(&[0; 4], 10, 0.0, ConsensusTie::First), b'N');
+
+        // Three- and four-way ties.
+        assert_eq!(consensus_base(&[2, 2, 0, 2], 6, 0.0, ConsensusTie::Iupac), b'H');
+        assert_eq!(consensus_base(&[2, 2, 2, 2], 8, 0.0, ConsensusTie::Iupac), b'N');
+        assert_eq!(consensus_base(&[0, 3, 3, 3], 9, 0.0, ConsensusTie::First), b'C');
+    }
+
+    #[test]
+    fn test_iupac_code() {
+        for code in b"ACGTRYSWKMBDHV" {
+            assert_eq!(iupac_code(iupac_bases(*code)), *code);
+        }
+        assert_eq!(iupac_code(0b1111), b'N');
+    }
+
+    #[test]
+    #[allow(clippy::bool_assert_comparison)]
+    fn test_has_variation() {
+        assert_eq!(has_variation(false, false, false, false), false);
+        assert_eq!(has_variation(true, false, false, false), false);
+        assert_eq!(has_variation(false, true, false, false), false);
+        assert_eq!(has_variation(false, false, true, false), false);
+        assert_eq!(has_variation(false, false, false, true), false);
+        assert_eq!(has_variation(true, true, false, false), true);
+        assert_eq!(has_variation(false, false, true, true), true);
+        assert_eq!(has_variation(true, false, true, false), true);
+        assert_eq!(has_variation(false, true, false, true), true);
+        assert_eq!(has_variation(false, true, true, true), true);
+        assert_eq!(has_variation(true, false, true, true), true);
+        assert_eq!(has_variation(true, true, false, true), true);
+        assert_eq!(has_variation(true, true, true, false), true);
+        assert_eq!(has_variation(true, true, true, true), true);
+    }
+
+    #[test]
+    fn test_allele_count() {
+        assert_eq!(allele_count(false, false, false, false), 0);
+        assert_eq!(allele_count(false, false, true, false), 1);
+        assert_eq!(allele_count(true, false, false, true), 2);
+        assert_eq!(allele_count(false, true, true, true), 3);
+        assert_eq!(allele_count(true, true, true, true), 4);
+    }
+
+    #[test]
+    fn test_bitvectors_and_counts_1() {
+        let (path, _dir) = make_test_file(">seq_1\nACGAT\n\
+                                           >seq_2\nGGT-A\n");
+        let alignment = Alignment::OnDisk(path, None, false);
+        let SiteCounts { a, c, g, t, seq_count, acgt_counts, .. } =
+            bitvectors_and_counts(&alignment, 5, false, None, None, false, false, "-",
+                                  &Progress::hidden()).unwrap();
+        assert_eq!(a, bitvec![1, 0, 0, 1, 1]);
+        assert_eq!(c, bitvec![0, 1, 0, 0, 0]);
+        assert_eq!(g, bitvec![1, 1, 1, 0, 0]);
+        assert_eq!(t, bitvec![0, 0, 1, 0, 1]);
+        assert_eq!(seq_count, 2);
+        assert_eq!(acgt_counts, vec![2, 2, 2, 1, 2]);
+    }
+
+    #[test]
+    fn test_bitvectors_and_counts_2() {
+        let (path, _dir) = make_test_file(">seq_1\naacgacta\n\
+                                           >seq_2\nAGCNACGA\n\
+                                           >seq_3\nacgGCTca\n");
+        let alignment = Alignment::OnDisk(path, None, false);
+        let SiteCounts { a, c, g, t, seq_count, acgt_counts, .. } =
+            bitvectors_and_counts(&alignment, 8, false, None, None, false, false, "-",
+                                  &Progress::hidden()).unwrap();
+        assert_eq!(a, bitvec![1, 1, 0, 0, 1, 0, 0, 1]);
+        assert_eq!(c, bitvec![0, 1, 1, 0, 1, 1, 1, 0]);
+        assert_eq!(g, bitvec![0, 1, 1, 1, 0, 0, 1, 0]);
+        assert_eq!(t, bitvec![0, 0, 0, 0, 0, 1, 1, 0]);
+        assert_eq!(seq_count, 3);
+        assert_eq!(acgt_counts, vec![3, 3, 3, 2, 3, 3, 3, 3]);
+    }
+
+    #[test]
+    fn test_bitvectors_and_counts_3() {
+        // Same as previous but with the alignment loaded into memory.
+        let (path, _dir) = make_test_file(">seq_1\naacgacta\n\
+                                           >seq_2\nAGCNACGA\n\
+                                           >seq_3\nacgGCTca\n");
+        let alignment = Alignment::load(&path, 1000, None, false).unwrap();
+        assert!(matches!(alignment, Alignment::InMemory(_)));
+        let SiteCounts { a, c, g, t, seq_count, acgt_counts, .. } =
+            bitvectors_and_counts(&alignment, 8, false, None, None, false, false, "-",
+                                  &Progress::hidden()).unwrap();
+        assert_eq!(a, bitvec![1, 1, 0, 0, 1, 0, 0, 1]);
+        assert_eq!(c, bitvec![0, 1, 1, 0, 1, 1, 1, 0]);
+        assert_eq!(g, bitvec![0, 1, 1, 1, 0, 0, 1, 0]);
+        assert_eq!(t, bitvec![0, 0, 0, 0, 0, 1, 1, 0]);
+        assert_eq!(seq_count, 3);
+        assert_eq!(acgt_counts, vec![3, 3, 3, 2, 3, 3, 3, 3]);
+    }
+
+    #[test]
+    fn test_bitvectors_and_counts_4() {
+        // Gaps and Ns are counted separately from other non-ACGT characters.
+        let (path, _dir) = make_test_file(">seq_1\nA-Nn?\n\
+                                           >seq_2\nA--NX\n\
+                                           >seq_3\nA-N-.\n");
+        let alignment = Alignment::load(&path, 1000, None, false).unwrap();
+        let counts = bitvectors_and_counts(&alignment, 5, false, None, None, false, false, "-",
+                                           &Progress::hidden()).unwrap();
+        assert_eq!(counts.acgt_counts, vec![3, 0, 0, 0, 0]);
+        assert_eq!(counts.gap_counts, vec![0, 3, 1, 1, 0]);
+        assert_eq!(counts.n_counts, vec![0, 0, 2, 2, 0]);
+    }
+
+    #[test]
+    fn test_bitvectors_and_counts_5() {
+        // IUPAC codes set each base they could be and count towards the core fraction.
+        let (path, _dir) = make_test_file(">seq_1\nRRAGAN\n\
+                                           >seq_2\nRAGRYN\n\
+                                           >seq_3\nRRRR-N\n");
+        let alignment = Alignment::load(&path, 1000, None, false).unwrap();
+        let counts = bitvectors_and_counts(&alignment, 6, true, None, None, false, false, "-",
+                                           &Progress::hidden()).unwrap();
+        assert_eq!(counts.a, bitvec![1, 1, 1, 1, 1, 0]);
+        assert_eq!(counts.c, bitvec![0, 0, 0, 0, 1, 0]);
+        assert_eq!(counts.g, bitvec![1, 1, 1, 1, 0, 0]);
+        assert_eq!(counts.t, bitvec![0, 0, 0, 0, 1, 0]);
+        assert_eq!(counts.acgt_counts, vec![3, 3, 3, 3, 2, 0]);
+        assert_eq!(counts.variable, bitvec![0, 0, 1, 0, 1, 0]);
+        assert_eq!(counts.shared_base, vec![Some(b'A'), Some(b'A'), None, Some(b'G'), None, None]);
+
+        // Without --iupac, the ambiguity codes are just missing.
+        let counts = bitvectors_and_counts(&alignment, 6, false, None, None, false, false, "-",
+                                           &Progress::hidden()).unwrap();
+        assert_eq!(counts.acgt_counts, vec![0, 1, 2, 1, 1, 0]);
+        assert_eq!(counts.variable, bitvec![0, 0, 1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_bitvectors_and_counts_6() {
+        // With custom missing characters, everything else counts as present, but only bases can
+        // make a site variable.
+        let (path, _dir) = make_test_file(">seq_1\nA?X-c\n\
+                                           >seq_2\nA.X-G\n\
+                                           >seq_3\nAXX-n\n");
+        let alignment = Alignment::load(&path, 1000, None, false).unwrap();
+        let counts = bitvectors_and_counts(&alignment, 5, false, Some("?.-n"), None, false, false,
+                                           "-", &Progress::hidden())
+            .unwrap();
+        assert_eq!(counts.acgt_counts, vec![3, 1, 3, 0, 2]);
+        assert_eq!(counts.gap_counts, vec![0, 0, 0, 3, 0]);
+        assert_eq!(counts.n_counts, vec![0, 0, 0, 0, 1]);
+        assert_eq!(counts.variable, bitvec![0, 0, 0, 0, 1]);
+        assert_eq!(counts.shared_base, vec![Some(b'A'), None, None, None, None]);
+
+        // The same but with the default missing characters.
+        let counts = bitvectors_and_counts(&alignment, 5, false, None, None, false, false, "-",
+                                           &Progress::hidden()).unwrap();
+        assert_eq!(counts.acgt_counts, vec![3, 0, 0, 0, 2]);
+    }
+
+    #[test]
+    fn test_count_bases() {
+        // Spans multiple blocks, so this checks the parallel counting against a simple count. Only
+        // the first six counts are filled in (the last is scratch space for other characters).
+        let length = BLOCK_SIZE * 2 + 100;
+        let seqs: Vec<Vec<u8>> = (0..5).map(|s| {
+            (0..length).map(|i| b"ACGTacgtNn-X?"[(i * 7 + s * 3) % 13]).collect()
+        }).collect();
+        let mut base_counts = vec![[0; 8]; length];
+        count_bases(&seqs, &mut base_counts, &BASE_SLOTS, None);
+        for i in 0..length {
+            let mut expected = [0; 8];
+            for seq in &seqs {
+                match seq[i].to_ascii_uppercase() {
+                    b'A' => expected[0] += 1,
+                    b'C' => expected[1] += 1,
+                    b'G' => expected[2] += 1,
+                    b'T' => expected[3] += 1,
+                    b'-' => expected[4] += 1,
+                    b'N' => expected[5] += 1,
+                    _ => (),
+                }
+            }
+            assert_eq!(base_counts[i][..6], expected[..6]);
+        }
+    }
+
+    #[test]
+    fn test_drop_columns_1() {
+        // No filtering - input is the same as the output.
+        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
+                                                 >seq_2\nACCATTAG\n\
+                                                 >seq_3\nACGATCAG\n");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &options(false, false, 0.0), &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nACGATCAG\n\
+                                                 >seq_2\nACCATTAG\n\
+                                                 >seq_3\nACGATCAG\n");
+    }
+
+    #[test]
+    fn test_drop_columns_2() {
+        // Dropping invariant sites.
+        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
+                                                 >seq_2\nACCATTAG\n\
+                                                 >seq_3\nACGATCAG\n");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &options(true, false, 0.0), &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nGC\n\
+                                                 >seq_2\nCT\n\
+                                                 >seq_3\nGC\n");
+    }
+
+    #[test]
+    fn test_drop_columns_3() {
+        // At 60% core, 2 out of 3 sequences is enough.
+        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
+                                                 >seq_2\nAC----CG\n\
+                                                 >seq_3\nAGGATCAG\n");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &options(false, false, 0.6), &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nACGATCAG\n\
+                                                 >seq_2\nAC----CG\n\
+                                                 >seq_3\nAGGATCAG\n");
+    }
+
+    #[test]
+    fn test_drop_columns_4() {
+        // At 70% core, 2 out of 3 sequences is not enough.
+        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
+                                                 >seq_2\nAC----CG\n\
+                                                 >seq_3\nAGGATCAG\n");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &options(false, false, 0.7), &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nACAG\n\
+                                                 >seq_2\nACCG\n\
+                                                 >seq_3\nAGAG\n");
+    }
+
+    #[test]
+    fn test_drop_columns_5() {
+        // Same as previous but dropping invariant sites and with per-site table.
+        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
+                                                 >seq_2\nAC----CG\n\
+                                                 >seq_3\nAGGATCAG\n");
+        let mut stdout = Vec::new();
+        let dir = tempdir().unwrap();
+        let table = Some(dir.path().join("table.tsv"));
+        drop_columns(&path, &FilterOptions { table, ..options(true, false, 0.7) }, &mut stdout)
+            .unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nCA\n\
+                                                 >seq_2\nCC\n\
+                                                 >seq_3\nGA\n");
+    }
+
+    #[test]
+    fn test_drop_columns_6() {
+        // Same as previous but with some descriptions in the FASTA headers.
+        let (path, _dir) =       make_test_file(">seq_1 info\nACGATCAG\n\
+                                                 >seq_2\nAC----CG\n\
+                                                 >seq_3 lots of stuff\nAGGATCAG\n");
+        let mut stdout = Vec::new();
+        let dir = tempdir().unwrap();
+        let table = Some(dir.path().join("table.tsv"));
+        drop_columns(&path, &FilterOptions { table, ..options(true, false, 0.7) }, &mut stdout)
+            .unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1 info\nCA\n\
+                                                 >seq_2\nCC\n\
+                                                 >seq_3 lots of stuff\nGA\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "seq_2 is 9 bp but expected 8 bp")]
+    fn test_drop_columns_7() {
+        // Invalid input with different sequence lengths.
+        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
+                                                 >seq_2\nAC----CGA\n\
+                                                 >seq_3\nAGGATCAG\n");
+        let mut stdout = Vec::new();
+        let dir = tempdir().unwrap();
+        let table = Some(dir.path().join("table.tsv"));
+        drop_columns(&path, &FilterOptions { table, ..options(true, false, 0.7) }, &mut stdout)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_drop_columns_8() {
+        // Every column is dropped.
+        let (path, _dir) =       make_test_file(">seq_1\nACGATCA-\n\
+                                                 >seq_2\nAC----AC\n\
+                                                 >seq_3\nACGATCAG\n");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &options(true, false, 0.7), &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\n\n\
+                                                 >seq_2\n\n\
+                                                 >seq_3\n\n");
+    }
+
+    #[test]
+    fn test_drop_columns_9() {
+        // Using a mixture of uppercase and lowercase - no columns dropped.
+        let (path, _dir) =       make_test_file(">seq_1\nACGAtCaGcAaT\n\
+                                                 >seq_2\nAcGaGCaGcAcT\n\
+                                                 >seq_3\nACGatTAgCaCT\n");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &options(false, false, 0.5), &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nACGAtCaGcAaT\n\
+                                                 >seq_2\nAcGaGCaGcAcT\n\
+                                                 >seq_3\nACGatTAgCaCT\n");
+    }
+
+    #[test]
+    fn test_drop_columns_10() {
+        // Using a mixture of uppercase and lowercase - invariant columns dropped.
+        let (path, _dir) =       make_test_file(">seq_1\nACGAtCaGcAaT\n\
+                                                 >seq_2\nAcGaGCaGcAcT\n\
+                                                 >seq_3\nACGatTAgCaCT\n");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &options(true, false, 0.5), &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\ntCa\n\
+                                                 >seq_2\nGCc\n\
+                                                 >seq_3\ntTC\n");
+    }
+
+    #[test]
+    fn test_drop_columns_11() {
+        // Using a mixture of uppercase and lowercase - non-core columns dropped.
+        let (path, _dir) =       make_test_file(">seq_1\nACG--CaGcAaT\n\
+                                                 >seq_2\nAcGaGCa--AcT\n\
+                                                 >seq_3\nACGa----CaCT\n");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &options(false, false, 0.5), &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nACG-CacAaT\n\
+                                                 >seq_2\nAcGaCa-AcT\n\
+                                                 >seq_3\nACGa--CaCT\n");
+    }
+
+    #[test]
+    fn test_drop_columns_12() {
+        // Using a mixture of uppercase and lowercase - invariant and non-core columns dropped.
+        let (path, _dir) =       make_test_file(">seq_1\nACG--CaGcAaT\n\
+                                                 >seq_2\nAcGaGCa--AcT\n\
+                                                 >seq_3\nACGa----CaCT\n");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &options(true, false, 0.5), &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\na\n\
+                                                 >seq_2\nc\n\
+                                                 >seq_3\nC\n");
+    }
+
+    #[test]
+    fn test_drop_columns_13() {
+        // Testing an input with line breaks in the FASTA sequences.
+        let (path, _dir) = make_test_file(">seq_1\nACG--\nCaGcA\naT\n\
+                                                 >seq_2\nAcGaG\nCa--A\ncT\n\
+                                                 >seq_3\nACGa-\n---Ca\nCT\n");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &options(false, false, 0.5), &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nACG-CacAaT\n\
+                                                 >seq_2\nAcGaCa-AcT\n\
+                                                 >seq_3\nACGa--CaCT\n");
+    }
+
+    #[test]
+    fn test_drop_columns_14() {
+        // Testing lots of non-base characters.
+        let (path, _dir) =       make_test_file(">seq_1\nAC---CGG\n\
+                                                 >seq_2\nCCCNNNNG\n\
+                                                 >seq_3\nACXQVPAG\n");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &options(true, false, 0.0), &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nAG\n\
+                                                 >seq_2\nCN\n\
+                                                 >seq_3\nAA\n");
+    }
+
+    #[test]
+    fn test_drop_columns_15() {
+        // Testing lots of non-base characters.
+        let (path, _dir) =       make_test_file(">seq_1\nAC---CGG\n\
+                                                 >seq_2\nCCCNNNNG\n\
+                                                 >seq_3\nACXQVPAG\n");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &options(true, false, 1.0), &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nA\n\
+                                                 >seq_2\nC\n\
+                                                 >seq_3\nA\n");
+    }
+
+    #[test]
+    fn test_drop_columns_16() {
+        // Testing invariant counts.
+        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
+                                                 >seq_2\nACCATTAG\n\
+                                                 >seq_3\nACGATCAG\n");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &options(true, true, 0.0), &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), "3,1,1,1\n");
+    }
+
+    #[test]
+    fn test_drop_columns_17() {
+        // Same as above but with different options (which makes no difference).
+        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
+                                                 >seq_2\nACCATTAG\n\
+                                                 >seq_3\nACGATCAG\n");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &options(false, true, 0.95), &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), "3,1,1,1\n");
+    }
+
+    #[test]
+    fn test_drop_columns_18() {
+        // Same as test_drop_columns_4 but with a coordinate map.
+        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
+                                                 >seq_2\nAC----CG\n\
+                                                 >seq_3\nAGGATCAG\n");
+        let mut stdout = Vec::new();
+        let dir = tempdir().unwrap();
+        let coord_map_path = dir.path().join("coord_map.tsv");
+        let options = FilterOptions { coord_map: Some(coord_map_path.clone()),
+                                      ..options(false, false, 0.7) };
+        drop_columns(&path, &options, &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nACAG\n\
+                                                 >seq_2\nACCG\n\
+                                                 >seq_3\nAGAG\n");
+        assert_eq!(std::fs::read_to_string(&coord_map_path).unwrap(),
+                   "new_pos\toriginal_pos\n1\t1\n2\t2\n3\t7\n4\t8\n");
+    }
+
+    #[test]
+    fn test_drop_columns_19() {
+        // Same as test_drop_columns_2 but with one of the variable sites masked by a BED file.
+        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
+                                                 >seq_2\nACCATTAG\n\
+                                                 >seq_3\nACGATCAG\n");
+        let dir = tempdir().unwrap();
+        let bed_path = dir.path().join("mask.bed");
+        std::fs::write(&bed_path, "chr\t2\t3\n").unwrap();
+        let mut stdout = Vec::new();
+        let options = FilterOptions { mask_bed: Some(bed_path), ..options(true, false, 0.0) };
+        drop_columns(&path, &options, &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nC\n\
+                                                 >seq_2\nT\n\
+                                                 >seq_3\nC\n");
+    }
+
+    #[test]
+    fn test_drop_columns_20() {
+        // Same as test_drop_columns_2 but with the first (invariant) site kept by force.
+        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
+                                                 >seq_2\nACCATTAG\n\
+                                                 >seq_3\nACGATCAG\n");
+        let dir = tempdir().unwrap();
+        let positions_path = dir.path().join("positions.txt");
+        std::fs::write(&positions_path, "1\n").unwrap();
+        let mut stdout = Vec::new();
+        let options = FilterOptions { keep_positions: Some(positions_path),
+                                      ..options(true, false, 0.0) };
+        drop_columns(&path, &options, &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nAGC\n\
+                                                 >seq_2\nACT\n\
+                                                 >seq_3\nAGC\n");
+    }
+
+    #[test]
+    fn test_drop_columns_21() {
+        // A force-kept site wins over a BED mask.
+        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
+                                                 >seq_2\nACCATTAG\n\
+                                                 >seq_3\nACGATCAG\n");
+        let dir = tempdir().unwrap();
+        let bed_path = dir.path().join("mask.bed");
+        std::fs::write(&bed_path, "chr\t0\t4\n").unwrap();
+        let positions_path = dir.path().join("positions.txt");
+        std::fs::write(&positions_path, "2\n3\n").unwrap();
+        let mut stdout = Vec::new();
+        let options = FilterOptions { mask_bed: Some(bed_path),
+                                      keep_positions: Some(positions_path),
+                                      ..options(false, false, 0.0) };
+        drop_columns(&path, &options, &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nCGTCAG\n\
+                                                 >seq_2\nCCTTAG\n\
+                                                 >seq_3\nCGTCAG\n");
+    }
+
+    #[test]
+    fn test_drop_columns_22() {
+        // Same as test_drop_columns_12 but with the alignment read from disk.
+        let (path, _dir) =       make_test_file(">seq_1\nACG--CaGcAaT\n\
+                                                 >seq_2\nAcGaGCa--AcT\n\
+                                                 >seq_3\nACGa----CaCT\n");
+        let mut stdout = Vec::new();
+        let options = FilterOptions { max_memory: 0.0, ..options(true, false, 0.5) };
+        drop_columns(&path, &options, &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\na\n\
+                                                 >seq_2\nc\n\
+                                                 >seq_3\nC\n");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_drop_columns_23() {
+        // Same as test_drop_columns_7 (different sequence lengths) but read from disk.
+        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
+                                                 >seq_2\nAC----CGA\n\
+                                                 >seq_3\nAGGATCAG\n");
+        let mut stdout = Vec::new();
+        let options = FilterOptions { max_memory: 0.0, ..options(true, false, 0.7) };
+        drop_columns(&path, &options, &mut stdout).unwrap();
+    }
+
+    #[test]
+    fn test_drop_columns_24() {
+        // Same as test_drop_columns_12 but checking the returned statistics.
+        let (path, _dir) =       make_test_file(">seq_1\nACG--CaGcAaT\n\
+                                                 >seq_2\nAcGaGCa--AcT\n\
+                                                 >seq_3\nACGa----CaCT\n");
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &options(true, false, 0.5), &mut stdout).unwrap();
+        assert_eq!(stats, FilterStats { seq_count: 3, alignment_length: 12, output_size: 1,
+                                        removed_total: 11, invariant_removed: 11,
+                                        invariant_a_removed: 4, invariant_c_removed: 3,
+                                        invariant_g_removed: 3, invariant_t_removed: 1,
+                                        ..Default::default() });
+    }
+
+    #[test]
+    fn test_drop_columns_25() {
+        // Same as test_drop_columns_21 but checking the returned statistics.
+        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
+                                                 >seq_2\nACCATTAG\n\
+                                                 >seq_3\nACGATCAG\n");
+        let dir = tempdir().unwrap();
+        let bed_path = dir.path().join("mask.bed");
+        std::fs::write(&bed_path, "chr\t0\t4\n").unwrap();
+        let positions_path = dir.path().join("positions.txt");
+        std::fs::write(&positions_path, "2\n3\n").unwrap();
+        let mut stdout = Vec::new();
+        let options = FilterOptions { mask_bed: Some(bed_path),
+                                      keep_positions: Some(positions_path),
+                                      ..options(true, false, 0.0) };
+        let stats = drop_columns(&path, &options, &mut stdout).unwrap();
+        assert_eq!(stats.output_size, 3);
+        assert_eq!(stats.force_kept, 2);
+        assert_eq!(stats.masked_removed, 2);
+        assert_eq!(stats.invariant_removed, 3);
+        assert_eq!(stats.removed_total, 5);
+    }
+
+    #[test]
+    fn test_drop_columns_26() {
+        // Invariant sites without canonical bases are split by their most common character.
+        let (path, _dir) = make_test_file(">seq_1\nAC-NN-?\n\
+                                           >seq_2\nGC-NX-X\n\
+                                           >seq_3\nAC-NNNX\n");
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &options(true, false, 0.0), &mut stdout).unwrap();
+        assert_eq!(std::str::from_utf8(&stdout).unwrap(), ">seq_1\nA\n>seq_2\nG\n>seq_3\nA\n");
+        assert_eq!(stats.invariant_c_removed, 1);
+        assert_eq!(stats.invariant_other_removed, 5);
+        assert_eq!(stats.invariant_gap_removed, 2);
+        assert_eq!(stats.invariant_n_removed, 2);
+        assert_eq!(stats.invariant_ambiguous_removed, 1);
+    }
+
+    #[test]
+    fn test_drop_columns_27() {
+        // With --iupac, a site of all R is invariant (it could all be A or all be G) and so is a
+        // site of G and R, but a site with both A and G is variable, regardless of R.
+        let (path, _dir) = make_test_file(">seq_1\nRAAGN\n\
+                                           >seq_2\nRGRRC\n\
+                                           >seq_3\nRRGRT\n");
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &FilterOptions { iupac: true, ..options(true, false, 0.0) },
+                                 &mut stdout).unwrap();
+        assert_eq!(std::str::from_utf8(&stdout).unwrap(),
+                   ">seq_1\nAAN\n>seq_2\nGRC\n>seq_3\nRGT\n");
+        assert_eq!(stats.invariant_a_removed, 1);
+        assert_eq!(stats.invariant_g_removed, 1);
+
+        // The R bases also count towards the core fraction.
+        let mut stdout = Vec::new();
+        drop_columns(&path, &FilterOptions { iupac: true, ..options(false, false, 1.0) },
+                     &mut stdout).unwrap();
+        assert_eq!(std::str::from_utf8(&stdout).unwrap(),
+                   ">seq_1\nRAAG\n>seq_2\nRGRR\n>seq_3\nRRGR\n");
+    }
+
+    #[test]
+    fn test_drop_columns_28() {
+        // Only the given missing characters count against the core fraction.
+        let (path, _dir) = make_test_file(">seq_1\nACGT?\n\
+                                           >seq_2\nA-XTG\n\
+                                           >seq_3\nACX?G\n");
+        let mut stdout = Vec::new();
+        let options = FilterOptions { missing_chars: Some("?".to_string()),
+                                      ..options(false, false, 1.0) };
+        drop_columns(&path, &options, &mut stdout).unwrap();
+        assert_eq!(std::str::from_utf8(&stdout).unwrap(),
+                   ">seq_1\nACG\n>seq_2\nA-X\n>seq_3\nACX\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "seq_2 is 9 bp but expected 8 bp")]
+    fn test_drop_columns_29() {
+        // Same as test_drop_columns_7 but reading from disk.
+        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
+                                                 >seq_2 description\nAC----CGA\n\
+                                                 >seq_3\nAGGATCAG\n");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &FilterOptions { max_memory: 0.0, ..options(true, false, 0.7) },
+                     &mut stdout).unwrap();
+    }
+
+    #[test]
+    fn test_drop_columns_30() {
+        // Windows-style line endings give the same result as Unix-style, in memory or on disk.
+        let unix = ">seq_1 info\nACGATCAG\n>seq_2\nAC--\nTCGA\n>seq_3\nAGGATCAG\n";
+        let (unix_path, _unix_dir) = make_test_file(unix);
+        let (windows_path, _windows_dir) = make_test_file(&unix.replace('\n', "\r\n"));
+        for max_memory in [1.0, 0.0] {
+            let options = FilterOptions { max_memory, ..options(true, false, 0.7) };
+            let mut unix_stdout = Vec::new();
+            let unix_stats = drop_columns(&unix_path, &options, &mut unix_stdout).unwrap();
+            let mut windows_stdout = Vec::new();
+            let windows_stats = drop_columns(&windows_path, &options, &mut windows_stdout).unwrap();
+            assert_eq!(std::str::from_utf8(&unix_stdout).unwrap(),
+                       ">seq_1 info\nCAG\n>seq_2\nCGA\n>seq_3\nGAG\n");
+            assert_eq!(unix_stdout, windows_stdout);
+            assert_eq!(unix_stats, windows_stats);
+        }
+    }
+
+    #[test]
+    fn test_filter_stats_json() {
+        let stats = FilterStats { seq_count: 3, alignment_length: 12, output_size: 1,
+                                  ..Default::default() };
+        let json = serde_json::to_value(&stats).unwrap();
+        assert_eq!(json["seq_count"], 3);
+        assert_eq!(json["alignment_length"], 12);
+        assert_eq!(json["output_size"], 1);
+        assert_eq!(json["invariant_a_removed"], 0);
+    }
+
+    #[test]
+    fn test_drop_columns_31() {
+        // With --count_only, nothing is output but the statistics are the same.
+        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
+                                                 >seq_2\nAC----GA\n\
+                                                 >seq_3\nAGGATCAG\n");
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &options(true, false, 0.7), &mut stdout).unwrap();
+        let mut count_only_stdout = Vec::new();
+        let count_only_stats = drop_columns(&path, &FilterOptions { count_only: true,
+                                                                    ..options(true, false, 0.7) },
+                                            &mut count_only_stdout).unwrap();
+        assert!(!stdout.is_empty());
+        assert!(count_only_stdout.is_empty());
+        assert_eq!(stats, count_only_stats);
+    }
+
+    #[test]
+    fn test_drop_columns_32() {
+        // With --soft_mask, invariant sites are replaced instead of removed.
+        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
+                                                 >seq_2\nAC----GA\n\
+                                                 >seq_3\nAGGATCAG\n");
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &FilterOptions { soft_mask: true,
+                                                         ..options(true, false, 0.0) },
+                                 &mut stdout).unwrap();
+        assert_eq!(std::str::from_utf8(&stdout).unwrap(),
+                   ">seq_1\nNCNNNNAG\n>seq_2\nNCNNNNGA\n>seq_3\nNGNNNNAG\n");
+        assert_eq!(stats.output_size, 8);
+        assert_eq!(stats.invariant_removed, 5);
+    }
+
+    #[test]
+    fn test_drop_columns_33() {
+        // A custom mask character.
+        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
+                                                 >seq_2\nAC----GA\n\
+                                                 >seq_3\nAGGATCAG\n");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &FilterOptions { soft_mask: true, mask_char: '-',
+                                             ..options(false, false, 1.0) },
+                     &mut stdout).unwrap();
+        assert_eq!(std::str::from_utf8(&stdout).unwrap(),
+                   ">seq_1\nAC----AG\n>seq_2\nAC----GA\n>seq_3\nAG----AG\n");
+    }
+
+    #[test]
+    fn test_drop_columns_34() {
+        // Only parsimony-informative sites are kept: sites 2, 3 and 6 each have two bases in two
+        // sequences, but site 4 has only one T and site 5 has one each of A, C and G.
+        let (path, _dir) =       make_test_file(">seq_1\nACAAAA\n\
+                                                 >seq_2\nACAACA\n\
+                                                 >seq_3\nAGCTGT\n\
+                                                 >seq_4\nAGCA-T\n");
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &FilterOptions { pars_informative: true,
+                                                         ..options(false, false, 0.0) },
+                                 &mut stdout).unwrap();
+        assert_eq!(std::str::from_utf8(&stdout).unwrap(),
+                   ">seq_1\nCAA\n>seq_2\nCAA\n>seq_3\nGCT\n>seq_4\nGCT\n");
+        assert_eq!(stats.pars_informative, 3);
+        assert_eq!(stats.uninformative_removed, 3);
+        assert_eq!(stats.removed_total, 3);
+
+        // The count of parsimony-informative sites is reported even without the filter.
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &options(true, false, 0.0), &mut stdout).unwrap();
+        assert_eq!(stats.pars_informative, 3);
+        assert_eq!(stats.uninformative_removed, 0);
+    }
+
+    #[test]
+    fn test_drop_columns_35() {
+        // The consensus only covers the kept sites, with ties going to the first base.
+        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
+                                                 >seq_2\nAC----GA\n\
+                                                 >seq_3\nAGGATCAG\n\
+                                                 >seq_4\nAGGATCGA\n");
+        let dir = tempdir().unwrap();
+        let consensus = dir.path().join("consensus.fasta");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &FilterOptions { consensus: Some(consensus.clone()),
+                                             ..options(true, false, 0.0) },
+                     &mut stdout).unwrap();
+        assert_eq!(std::fs::read_to_string(&consensus).unwrap(), ">consensus\nCAA\n");
+
+        // With a minimum fraction, sites without a clear majority become N.
+        let mut stdout = Vec::new();
+        drop_columns(&path, &FilterOptions { consensus: Some(consensus.clone()),
+                                             consensus_min_frac: 0.75,
+                                             ..options(false, false, 0.0) },
+                     &mut stdout).unwrap();
+        assert_eq!(std::fs::read_to_string(&consensus).unwrap(), ">consensus\nANGATCNN\n");
+
+        // All three kept sites are ties, which --consensus_tie can resolve differently.
+        for (tie, expected) in [(ConsensusTie::Iupac, "SRR"), (ConsensusTie::N, "NNN")].iter() {
+            drop_columns(&path, &FilterOptions { consensus: Some(consensus.clone()),
+                                                 consensus_tie: *tie,
+                                                 ..options(true, false, 0.0) },
+                         &mut Vec::new()).unwrap();
+            assert_eq!(std::fs::read_to_string(&consensus).unwrap(),
+                       format!(">consensus\n{}\n", expected));
+        }
+    }
+
+    #[test]
+    fn test_drop_columns_36() {
+        // Distances are only over the kept sites.
+        let (path, _dir) =       make_test_file(">seq_1 info\nACGATCAG\n\
+                                                 >seq_2\nAC----GA\n\
+                                                 >seq_3\nAGGATCAN\n");
+        let dir = tempdir().unwrap();
+        let dist_matrix = dir.path().join("dist.phylip");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &FilterOptions { dist_matrix: Some(dist_matrix.clone()),
+                                             ..options(true, false, 0.0) },
+                     &mut stdout).unwrap();
+        assert_eq!(std::fs::read_to_string(&dist_matrix).unwrap(),
+                   "3\nseq_1\t0\t2\t2\nseq_2\t2\t0\t3\nseq_3\t2\t3\t0\n");
+
+        // Sites where either sequence isn't a base can be ignored.
+        let mut stdout = Vec::new();
+        drop_columns(&path, &FilterOptions { dist_matrix: Some(dist_matrix.clone()),
+                                             dist_ignore_missing: true, count_only: true,
+                                             ..options(true, false, 0.0) },
+                     &mut stdout).unwrap();
+        assert!(stdout.is_empty());
+        assert_eq!(std::fs::read_to_string(&dist_matrix).unwrap(),
+                   "3\nseq_1\t0\t2\t1\nseq_2\t2\t0\t2\nseq_3\t1\t2\t0\n");
+    }
+
+    #[test]
+    fn test_drop_columns_37() {
+        // Only the leading and trailing sites without bases are trimmed, not the interior ones.
+        let (path, _dir) =       make_test_file(">seq_1\n--AC-NGT-\n\
+                                                 >seq_2\nN-AC-NGA?\n\
+                                                 >seq_3\n---C--GT-\n");
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &FilterOptions { trim_ends: true,
+                                                         ..options(false, false, 0.0) },
+                                 &mut stdout).unwrap();
+        assert_eq!(std::str::from_utf8(&stdout).unwrap(),
+                   ">seq_1\nAC-NGT\n>seq_2\nAC-NGA\n>seq_3\n-C--GT\n");
+        assert_eq!(stats.end_trimmed, 3);
+        assert_eq!(stats.removed_total, 3);
+    }
+
+    #[test]
+    fn test_drop_columns_38() {
+        // Trimming an alignment with no bases at all removes everything.
+        let (path, _dir) = make_test_file(">seq_1\n--N\n>seq_2\nN--\n");
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &FilterOptions { trim_ends: true,
+                                                         ..options(false, false, 0.0) },
+                                 &mut stdout).unwrap();
+        assert_eq!(std::str::from_utf8(&stdout).unwrap(), ">seq_1\n\n>seq_2\n\n");
+        assert_eq!(stats.end_trimmed, 3);
+    }
+
+    #[test]
+    fn test_drop_columns_39() {
+        // --core_count 2 gives the same result as --core 0.5 for four sequences.
+        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
+                                                 >seq_2\nAC---CGA\n\
+                                                 >seq_3\nAG--TCAN\n\
+                                                 >seq_4\nA---TCGA\n");
+        let mut count_stdout = Vec::new();
+        drop_columns(&path, &FilterOptions { core_count: Some(2), ..options(false, false, 0.0) },
+                     &mut count_stdout).unwrap();
+        let mut frac_stdout = Vec::new();
+        drop_columns(&path, &options(false, false, 0.5), &mut frac_stdout).unwrap();
+        assert_eq!(std::str::from_utf8(&count_stdout).unwrap(),
+                   ">seq_1\nACTCAG\n>seq_2\nAC-CGA\n>seq_3\nAGTCAN\n>seq_4\nA-TCGA\n");
+        assert_eq!(count_stdout, frac_stdout);
+    }
+
+    #[test]
+    #[should_panic(expected = "--core_count cannot be more than the number of sequences")]
+    fn test_drop_columns_40() {
+        let (path, _dir) = make_test_file(">seq_1\nACGA\n>seq_2\nAC-A\n");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &FilterOptions { core_count: Some(3), ..options(false, false, 0.0) },
+                     &mut stdout).unwrap();
+    }
+
+    #[test]
+    fn test_drop_columns_41() {
+        // seq_1 and seq_3 only differ at an invariant site, so they're identical after filtering,
+        // as are seq_2 and seq_4.
+        let (path, _dir) =       make_test_file(">seq_1 info\nACGATCAG\n\
+                                                 >seq_2\nAGGATCGA\n\
+                                                 >seq_3\nACGA-CAG\n\
+                                                 >seq_4\nAGGATCGA\n\
+                                                 >seq_5\nACGATCGG\n");
+        let dir = tempdir().unwrap();
+        let dedup_map = dir.path().join("dedup.tsv");
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &FilterOptions { dedup: true,
+                                                         dedup_map: Some(dedup_map.clone()),
+                                                         ..options(true, false, 0.0) },
+                                 &mut stdout).unwrap();
+        assert_eq!(std::str::from_utf8(&stdout).unwrap(),
+                   ">seq_1 info\nCAG\n>seq_2\nGGA\n>seq_5\nCGG\n");
+        assert_eq!(stats.dedup_collapsed, 2);
+        assert_eq!(std::fs::read_to_string(&dedup_map).unwrap(),
+                   "representative\tmembers\n\
+                    seq_1\tseq_1,seq_3\n\
+                    seq_2\tseq_2,seq_4\n\
+                    seq_5\tseq_5\n");
+    }
+
+    #[test]
+    fn test_drop_columns_42() {
+        // Only the included samples are counted and output, which changes the core fractions.
+        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
+                                                 >seq_2 info\nAC---CGA\n\
+                                                 >seq_3\nAGGATCAN\n\
+                                                 >seq_4\nA---TCGA\n");
+        let dir = tempdir().unwrap();
+        let samples = dir.path().join("samples.txt");
+        std::fs::write(&samples, "seq_1\nseq_2\nseq_5\n").unwrap();
+        for max_memory in [1.0, 0.0] {
+            let mut stdout = Vec::new();
+            let stats = drop_columns(&path, &FilterOptions { include_samples: Some(samples.clone()),
+                                                             max_memory,
+                                                             ..options(true, false, 1.0) },
+                                     &mut stdout).unwrap();
+            assert_eq!(std::str::from_utf8(&stdout).unwrap(), ">seq_1\nAG\n>seq_2 info\nGA\n");
+            assert_eq!(stats.seq_count, 2);
+        }
+    }
+
+    #[test]
+    fn test_drop_columns_43() {
+        // Excluding samples is the opposite of including them.
+        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
+                                                 >seq_2 info\nAC---CGA\n\
+                                                 >seq_3\nAGGATCAN\n\
+                                                 >seq_4\nA---TCGA\n");
+        let dir = tempdir().unwrap();
+        let samples = dir.path().join("samples.txt");
+        std::fs::write(&samples, "seq_3\nseq_4\n").unwrap();
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &FilterOptions { exclude_samples: Some(samples),
+                                                         ..options(true, false, 1.0) },
+                                 &mut stdout).unwrap();
+        assert_eq!(std::str::from_utf8(&stdout).unwrap(), ">seq_1\nAG\n>seq_2 info\nGA\n");
+        assert_eq!(stats.seq_count, 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "repeated sequence names: seq_1")]
+    fn test_drop_columns_44() {
+        // Repeated names are only a warning unless --strict_names is used.
+        let (path, _dir) = make_test_file(">seq_1\nACGA\n>seq_2\nAC-A\n>seq_1 again\nACGT\n");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &options(false, false, 0.0), &mut stdout).unwrap();
+        assert_eq!(std::str::from_utf8(&stdout).unwrap(),
+                   ">seq_1\nACGA\n>seq_2\nAC-A\n>seq_1 again\nACGT\n");
+        drop_columns(&path, &FilterOptions { strict_names: true, ..options(false, false, 0.0) },
+                     &mut stdout).unwrap();
+    }
+
+    #[test]
+    fn test_drop_columns_45() {
+        // An all-N site and an all-gap site are tallied separately (N is case-insensitive).
+        let (path, _dir) = make_test_file(">seq_1\nANA-C\n\
+                                           >seq_2\nAnC-C\n\
+                                           >seq_3\nANG-C\n");
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &options(true, false, 0.0), &mut stdout).unwrap();
+        assert_eq!(std::str::from_utf8(&stdout).unwrap(), ">seq_1\nA\n>seq_2\nC\n>seq_3\nG\n");
+        assert_eq!(stats.invariant_other_removed, 2);
+        assert_eq!(stats.invariant_n_removed, 1);
+        assert_eq!(stats.invariant_gap_removed, 1);
+        assert_eq!(stats.invariant_ambiguous_removed, 0);
+    }
+
+    #[test]
+    fn test_drop_columns_46() {
+        // By default, case doesn't matter. With --lowercase_missing, lowercase bases are missing,
+        // which changes both the core fraction and whether a site is variable.
+        let (path, _dir) = make_test_file(">seq_1\nACGTA\n\
+                                           >seq_2\nAcGTa\n\
+                                           >seq_3\nAcGTg\n");
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &options(true, false, 1.0), &mut stdout).unwrap();
+        assert_eq!(std::str::from_utf8(&stdout).unwrap(), ">seq_1\nA\n>seq_2\na\n>seq_3\ng\n");
+        assert_eq!(stats.invariant_removed, 4);
+
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &FilterOptions { lowercase_missing: true,
+                                                         ..options(true, false, 0.0) },
+                                 &mut stdout).unwrap();
+        assert_eq!(std::str::from_utf8(&stdout).unwrap(), ">seq_1\n\n>seq_2\n\n>seq_3\n\n");
+        assert_eq!(stats.invariant_removed, 5);
+
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &FilterOptions { lowercase_missing: true,
+                                                         ..options(false, false, 1.0) },
+                                 &mut stdout).unwrap();
+        assert_eq!(std::str::from_utf8(&stdout).unwrap(),
+                   ">seq_1\nAGT\n>seq_2\nAGT\n>seq_3\nAGT\n");
+        assert_eq!(stats.non_core_removed, 2);
+    }
+
+    #[test]
+    fn test_drop_columns_47() {
+        // Same input as test_drop_columns_9, but with --uppercase.
+        let (path, _dir) = make_test_file(">seq_1\nACGAtCaGcAaT\n\
+                                           >seq_2\nAcGaGCaGcAcT\n\
+                                           >seq_3\nACGatTAgCaCT\n");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &FilterOptions { uppercase: true, ..options(false, false, 0.5) },
+                     &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nACGATCAGCAAT\n\
+                                                 >seq_2\nACGAGCAGCACT\n\
+                                                 >seq_3\nACGATTAGCACT\n");
+    }
+
+    #[test]
+    fn test_drop_columns_48() {
+        // --base_composition counts characters over the kept sites, even with --count_only.
+        let (path, _dir) = make_test_file(">seq_1\nACGATCAGTA\n\
+                                           >seq_2\nAn-n-RGAR-\n\
+                                           >seq_3\nAGGATCAGCC\n");
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &FilterOptions { base_composition: true, count_only: true,
+                                                         ..options(true, false, 0.0) },
+                                 &mut stdout).unwrap();
+        assert!(stdout.is_empty());
+        assert_eq!(stats.output_size, 5);
+        let composition = stats.base_composition.unwrap();
+        assert_eq!(composition, BaseComposition { a: 4, c: 3, g: 4, t: 1, n: 1, gap: 1, other: 1 });
+        let total = composition.a + composition.c + composition.g + composition.t +
+                    composition.n + composition.gap + composition.other;
+        assert_eq!(total, stats.seq_count * stats.output_size);
+
+        // Without the option, there's no composition.
+        let stats = drop_columns(&path, &options(true, false, 0.0), &mut stdout).unwrap();
+        assert_eq!(stats.base_composition, None);
+    }
+
+    #[test]
+    fn test_drop_columns_49() {
+        // With both --core and --core_count, a site must pass both thresholds, so whichever is
+        // stricter decides. The sites have 6, 2, 0, 3 and 3 bases.
+        let (path, _dir) = make_test_file(">seq_1\nAA-AA\n\
+                                           >seq_2\nAA-AA\n\
+                                           >seq_3\nA--A-\n\
+                                           >seq_4\nA---A\n\
+                                           >seq_5\nA----\n\
+                                           >seq_6\nA----\n");
+        for (core, core_count, output_size) in [(0.5, 2, 3), (0.5, 4, 1), (0.9, 2, 1)] {
+            let mut stdout = Vec::new();
+            let stats = drop_columns(&path, &FilterOptions { core_count: Some(core_count),
+                                                             ..options(false, false, core) },
+                                     &mut stdout).unwrap();
+            assert_eq!(stats.output_size, output_size);
+            assert_eq!(stats.non_core_removed, 5 - output_size);
+        }
+    }
+
+    #[test]
+    fn test_drop_columns_50() {
+        // --removed_output saves the sites which aren't in the filtered alignment.
+        let (path, dir) = make_test_file(">seq_1\nACGATCAG\n\
+                                          >seq_2\nAC----GA\n\
+                                          >seq_3\nAGGATCAG\n");
+        let removed_path = dir.path().join("removed.fasta");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &FilterOptions { removed_output: Some(removed_path.clone()),
+                                             ..options(true, false, 0.7) },
+                     &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nCAG\n>seq_2\nCGA\n>seq_3\nGAG\n");
+        assert_eq!(std::fs::read_to_string(&removed_path).unwrap(),
+                   ">seq_1\nAGATC\n>seq_2\nA----\n>seq_3\nAGATC\n");
+    }
+
+    #[test]
+    fn test_drop_columns_51() {
+        // Same as test_drop_columns_18 but with 0-based positions, which also apply to the table
+        // and --keep_positions.
+        let (path, dir) = make_test_file(">seq_1\nACGATCAG\n\
+                                          >seq_2\nAC----CG\n\
+                                          >seq_3\nAGGATCAG\n");
+        let coord_map_path = dir.path().join("coord_map.tsv");
+        let table_path = dir.path().join("table.tsv");
+        let positions_path = dir.path().join("positions.txt");
+        std::fs::write(&positions_path, "2\n").unwrap();
+        let mut stdout = Vec::new();
+        drop_columns(&path, &FilterOptions { coord_map: Some(coord_map_path.clone()),
+                                             table: Some(table_path.clone()),
+                                             keep_positions: Some(positions_path),
+                                             zero_based: true, ..options(false, false, 0.7) },
+                     &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nACGAG\n\
+                                                 >seq_2\nAC-CG\n\
+                                                 >seq_3\nAGGAG\n");
+        assert_eq!(std::fs::read_to_string(&coord_map_path).unwrap(),
+                   "new_pos\toriginal_pos\n0\t0\n1\t1\n2\t2\n3\t6\n4\t7\n");
+        let table = std::fs::read_to_string(&table_path).unwrap();
+        assert!(table.lines().nth(1).unwrap().starts_with("0\t"));
+        assert!(table.lines().last().unwrap().starts_with("7\t"));
+    }
+
+    #[test]
+    fn test_parse_region() {
+        assert_eq!(parse_region("1001-2000"), Ok((1001, 2000)));
+        assert_eq!(parse_region("5-5"), Ok((5, 5)));
+        assert!(parse_region("2000-1001").is_err());
+        assert!(parse_region("1001").is_err());
+        assert!(parse_region("a-b").is_err());
+    }
+
+    #[test]
+    fn test_drop_columns_52() {
+        // Only sites 2-7 are considered, so the variable site 8 is left out, as is site 1 even
+        // though it's force-kept.
+        let (path, dir) = make_test_file(">seq_1\nACGATCAG\n\
+                                          >seq_2\nAC----GA\n\
+                                          >seq_3\nAGGATCAG\n");
+        let positions_path = dir.path().join("positions.txt");
+        std::fs::write(&positions_path, "1\n").unwrap();
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &FilterOptions { region: Some((2, 7)),
+                                                         keep_positions: Some(positions_path),
+                                                         ..options(true, false, 0.0) },
+                                 &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nCA\n>seq_2\nCG\n>seq_3\nGA\n");
+        assert_eq!(stats.outside_region, 2);
+        assert_eq!(stats.invariant_removed, 4);
+        assert_eq!(stats.removed_total, 6);
+        assert_eq!(stats.force_kept, 0);
+    }
+
+    #[test]
+    fn test_drop_columns_53() {
+        let (path, _dir) = make_test_file(">seq_1\nACGATCAG\n");
+        let mut stdout = Vec::new();
+        let result = drop_columns(&path, &FilterOptions { region: Some((2, 9)),
+                                                          ..options(false, false, 0.0) },
+                                  &mut stdout);
+        assert!(matches!(result, Err(Error::BadArguments(_))));
+    }
+
+    #[test]
+    fn test_site_entropy() {
+        assert_eq!(site_entropy(&[0, 0, 0, 0]), 0.0);
+        assert_eq!(site_entropy(&[0, 5, 0, 0]), 0.0);
+        assert_eq!(site_entropy(&[3, 0, 3, 0]), 1.0);
+        assert_eq!(site_entropy(&[2, 2, 2, 2]), 2.0);
+        assert!((site_entropy(&[3, 1, 0, 0]) - 0.8113).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_drop_columns_54() {
+        let (path, dir) = make_test_file(">seq_1\nACGA\n\
+                                          >seq_2\nAC-C\n\
+                                          >seq_3\nAGGA\n\
+                                          >seq_4\nAGGT\n");
+        let table_path = dir.path().join("table.tsv");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &FilterOptions { table: Some(table_path.clone()), with_entropy: true,
+                                             ..options(false, false, 0.0) },
+                     &mut stdout).unwrap();
+        assert_eq!(std::fs::read_to_string(&table_path).unwrap(),
+                   "pos\ta\tc\tg\tt\tcount\tfrac\tvar\tkeep\tentropy\n\
+                    1\t1\t0\t0\t0\t4\t1.0000\t0\t1\t0.0000\n\
+                    2\t0\t1\t1\t0\t4\t1.0000\t1\t1\t1.0000\n\
+                    3\t0\t0\t1\t0\t3\t0.7500\t0\t1\t0.0000\n\
+                    4\t1\t1\t0\t1\t4\t1.0000\t1\t1\t1.5000\n");
+    }
+
+    #[test]
+    fn test_protein_counts() {
+        let (path, _dir) = make_test_file(">seq_1\nMKW-LX*\n\
+                                           >seq_2\nMRW-lXQ\n\
+                                           >seq_3\nMRwAL-Q\n");
+        let alignment = Alignment::load(&path, 1000, None, false).unwrap();
+        let counts = protein_counts(&alignment, 7, false, "-", &Progress::hidden()).unwrap();
+        assert_eq!(counts.seq_count, 3);
+        assert_eq!(counts.acgt_counts, vec![3, 3, 3, 1, 3, 0, 2]);
+        assert_eq!(counts.gap_counts, vec![0, 0, 0, 2, 0, 1, 0]);
+        assert_eq!(counts.n_counts, vec![0, 0, 0, 0, 0, 2, 0]);
+        assert_eq!(counts.variable, bitvec![0, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(counts.shared_base, vec![Some(b'M'), None, Some(b'W'), Some(b'A'), Some(b'L'),
+                                            None, Some(b'Q')]);
+        assert_eq!(counts.residue_counts[1][8], 1);  // K
+        assert_eq!(counts.residue_counts[1][14], 2);  // R
+        assert!(counts.a.not_any());
+        assert_eq!(counts.base_counts, vec![[0; 4]; 7]);
+
+        let counts = protein_counts(&alignment, 7, true, "-", &Progress::hidden()).unwrap();
+        assert_eq!(counts.acgt_counts, vec![3, 3, 2, 1, 2, 0, 2]);
+    }
+
+    #[test]
+    fn test_drop_columns_55() {
+        let (path, _dir) = make_test_file(">seq_1\nMKW-LX*G\n\
+                                           >seq_2\nMRW-LXQA\n\
+                                           >seq_3\nMRWAL-QA\n\
+                                           >seq_4\nMKWALXQG\n");
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &FilterOptions { alphabet: Alphabet::Protein,
+                                                         ..options(true, false, 0.5) },
+                                 &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nKG\n>seq_2\nRA\n>seq_3\nRA\n\
+                                                 >seq_4\nKG\n");
+        assert_eq!(stats.invariant_residue_removed, 5);
+        assert_eq!(stats.invariant_a_removed, 0);
+        assert_eq!(stats.invariant_removed, 6);
+        assert_eq!(stats.invariant_n_removed, 1);
+        assert_eq!(stats.pars_informative, 2);
+    }
+
+    #[test]
+    fn test_check_arguments_12() {
+        assert!(check_arguments(&FilterOptions { alphabet: Alphabet::Protein,
+                                                 ..options(true, false, 0.95) }).is_ok());
+        assert!(matches!(check_arguments(&FilterOptions { alphabet: Alphabet::Protein,
+                                                          iupac: true,
+                                                          ..options(true, false, 0.95) }),
+                         Err(Error::BadArguments(_))));
+        assert!(matches!(check_arguments(&FilterOptions { alphabet: Alphabet::Protein,
+                                                          ..options(false, true, 0.0) }),
+                         Err(Error::BadArguments(_))));
+    }
+
+    #[test]
+    fn test_check_arguments_13() {
+        assert!(check_arguments(&FilterOptions { count_gaps: true,
+                                                 ..options(true, false, 0.95) }).is_ok());
+        assert!(matches!(check_arguments(&FilterOptions { count_gaps: true,
+                                                          missing_chars: Some("N".to_string()),
+                                                          ..options(true, false, 0.95) }),
+                         Err(Error::BadArguments(_))));
+    }
+
+    #[test]
+    fn test_check_arguments_14() {
+        assert!(check_arguments(&FilterOptions { max_gap_frac: Some(0.0),
+                                                 ..options(true, false, 0.95) }).is_ok());
+        assert!(matches!(check_arguments(&FilterOptions { max_gap_frac: Some(1.5),
+                                                          ..options(true, false, 0.95) }),
+                         Err(Error::BadArguments(_))));
+        assert!(matches!(check_arguments(&FilterOptions { max_gap_frac: Some(0.1),
+                                                          ..options(false, true, 0.0) }),
+                         Err(Error::BadArguments(_))));
+    }
+
+    #[test]
+    fn test_check_arguments_15() {
+        assert!(check_arguments(&FilterOptions { max_ambiguous_frac: Some(1.0),
+                                                 ..options(true, false, 0.95) }).is_ok());
+        assert!(matches!(check_arguments(&FilterOptions { max_ambiguous_frac: Some(-0.1),
+                                                          ..options(true, false, 0.95) }),
+                         Err(Error::BadArguments(_))));
+    }
+
+    #[test]
+    fn test_check_arguments_16() {
+        assert!(check_arguments(&FilterOptions { gap_chars: "-.*~".to_string(),
+                                                 ..options(true, false, 0.95) }).is_ok());
+        assert!(matches!(check_arguments(&FilterOptions { gap_chars: "-X".to_string(),
+                                                          ..options(true, false, 0.95) }),
+                         Err(Error::BadArguments(_))));
+    }
+
+    #[test]
+    fn test_check_arguments_17() {
+        assert!(matches!(check_arguments(&FilterOptions { raxml_info: Some("asc.txt".into()),
+                                                          alphabet: Alphabet::Protein,
+                                                          ..options(true, false, 0.0) }),
+                         Err(Error::BadArguments(_))));
+    }
+
+    #[test]
+    fn test_check_arguments_18() {
+        let regex = |include: Option<&str>, exclude: Option<&str>| {
+            check_arguments(&FilterOptions { include_regex: include.map(str::to_string),
+                                             exclude_regex: exclude.map(str::to_string),
+                                             ..options(false, false, 0.0) })
+        };
+        assert!(regex(Some("^seq_[0-9]+$"), None).is_ok());
+        assert!(matches!(regex(Some("seq_["), None), Err(Error::BadArguments(_))));
+        assert!(matches!(regex(None, Some("(")), Err(Error::BadArguments(_))));
+        assert!(matches!(regex(Some("a"), Some("b")), Err(Error::BadArguments(_))));
+        assert!(matches!(check_arguments(&FilterOptions { include_regex: Some("a".into()),
+                                                          exclude_samples: Some("b.txt".into()),
+                                                          ..options(false, false, 0.0) }),
+                         Err(Error::BadArguments(_))));
+    }
+
+    #[test]
+    fn test_check_arguments_19() {
+        check_arguments(&FilterOptions { n_is_allele: true, ..options(true, false, 0.0) })
+            .unwrap();
+        assert!(matches!(check_arguments(&FilterOptions { n_is_allele: true,
+                                                          alphabet: Alphabet::Protein,
+                                                          ..options(true, false, 0.0) }),
+                         Err(Error::BadArguments(_))));
+    }
+
+    #[test]
+    fn test_check_arguments_20() {
+        let partitions = |soft_mask: bool| {
+            check_arguments(&FilterOptions { partition_in: Some("in.txt".into()),
+                                             partition_out: Some("out.txt".into()), soft_mask,
+                                             ..options(true, false, 0.0) })
+        };
+        assert!(partitions(false).is_ok());
+        assert!(matches!(partitions(true), Err(Error::BadArguments(_))));
+    }
+
+    #[test]
+    fn test_check_arguments_21() {
+        let sfs = |unfolded: bool, ancestral_name: Option<&str>| {
+            check_arguments(&FilterOptions { sfs: Some("sfs.tsv".into()), unfolded,
+                                             ancestral_name: ancestral_name.map(str::to_string),
+                                             ..options(true, false, 0.0) })
+        };
+        assert!(sfs(false, None).is_ok());
+        assert!(sfs(true, Some("outgroup")).is_ok());
+        assert!(matches!(sfs(true, None), Err(Error::BadArguments(_))));
+        assert!(matches!(sfs(false, Some("outgroup")), Err(Error::BadArguments(_))));
+    }
+
+    #[test]
+    fn test_check_arguments_22() {
+        let chars = |missing: Option<&str>, present: Option<&str>, count_gaps: bool| {
+            check_arguments(&FilterOptions { missing_chars: missing.map(str::to_string),
+                                             present_chars: present.map(str::to_string),
+                                             count_gaps, ..options(true, false, 0.0) })
+        };
+        assert!(chars(None, Some("ACGT"), false).is_ok());
+        assert!(matches!(chars(Some("N"), Some("ACGT"), false), Err(Error::BadArguments(_))));
+        assert!(matches!(chars(None, Some("ACGT-"), true), Err(Error::BadArguments(_))));
+        assert!(matches!(check_arguments(&FilterOptions { present_chars: Some("ACGT".into()),
+                                                          alphabet: Alphabet::Protein,
+                                                          ..options(false, false, 0.0) }),
+                         Err(Error::BadArguments(_))));
+    }
+
+    #[test]
+    fn test_bitvectors_and_counts_7() {
+        // The same as test_bitvectors_and_counts_1 but as RNA, where U counts as T.
+        let (path, _dir) = make_test_file(">seq_1\nACGAU\n\
+                                           >seq_2\nGGu-A\n");
+        let alignment = Alignment::OnDisk(path, None, false);
+        let SiteCounts { a, c, g, t, seq_count, acgt_counts, .. } =
+            bitvectors_and_counts(&alignment, 5, false, None, None, false, true, "-",
+                                  &Progress::hidden()).unwrap();
+        assert_eq!(a, bitvec![1, 0, 0, 1, 1]);
+        assert_eq!(c, bitvec![0, 1, 0, 0, 0]);
+        assert_eq!(g, bitvec![1, 1, 1, 0, 0]);
+        assert_eq!(t, bitvec![0, 0, 1, 0, 1]);
+        assert_eq!(seq_count, 2);
+        assert_eq!(acgt_counts, vec![2, 2, 2, 1, 2]);
+
+        // Without the rna flag, U is just missing data.
+        let SiteCounts { t, acgt_counts, .. } =
+            bitvectors_and_counts(&alignment, 5, false, None, None, false, false, "-",
+                                  &Progress::hidden()).unwrap();
+        assert_eq!(t, bitvec![0, 0, 0, 0, 0]);
+        assert_eq!(acgt_counts, vec![2, 2, 1, 1, 1]);
+
+        // The character table path (here used for --iupac) treats U the same way.
+        let SiteCounts { t, acgt_counts, .. } =
+            bitvectors_and_counts(&alignment, 5, true, None, None, false, true, "-",
+                                  &Progress::hidden()).unwrap();
+        assert_eq!(t, bitvec![0, 0, 1, 0, 1]);
+        assert_eq!(acgt_counts, vec![2, 2, 2, 1, 2]);
+    }
+
+    #[test]
+    fn test_drop_columns_56() {
+        // U is kept as is in the output, unless --uppercase is used.
+        let (path, _dir) = make_test_file(">seq_1\nACUAu\n\
+                                           >seq_2\nGGU-A\n");
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &FilterOptions { alphabet: Alphabet::Rna,
+                                                         ..options(true, false, 1.0) },
+                                 &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nACu\n>seq_2\nGGA\n");
+        assert_eq!(stats.invariant_t_removed, 1);
+    }
+
+    #[test]
+    fn test_char_counts() {
+        let (path, _dir) = make_test_file(">seq_1\nACGAT?\n\
+                                           >seq_2\nnGT-A\x01\n");
+        let alignment = Alignment::load(&path, 1000, None, false).unwrap();
+        let counts = char_counts(&alignment, Alphabet::Dna, "-").unwrap();
+        let summary: Vec<(&str, usize, bool)> = counts.iter()
+            .map(|c| (c.char.as_str(), c.count, c.expected)).collect();
+        assert_eq!(summary, vec![("A", 3, true), ("G", 2, true), ("T", 2, true),
+                                 ("\\x01", 1, false), ("-", 1, true), ("?", 1, false),
+                                 ("C", 1, true), ("n", 1, true)]);
+        let counts = char_counts(&alignment, Alphabet::Protein, "-").unwrap();
+        assert_eq!(counts.iter().filter(|c| !c.expected).count(), 2);
+    }
+
+    #[test]
+    fn test_drop_columns_57() {
+        let (path, _dir) = make_test_file(">seq_1\nACGAT\n\
+                                           >seq_2\nNGT-?\n");
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &FilterOptions { report_chars: true,
+                                                         ..options(false, false, 0.0) },
+                                 &mut stdout).unwrap();
+        assert_eq!(stats.char_counts.unwrap().len(), 7);
+        let stats = drop_columns(&path, &options(false, false, 0.0), &mut stdout).unwrap();
+        assert_eq!(stats.char_counts, None);
+        let result = drop_columns(&path, &FilterOptions { strict_chars: true,
+                                                          ..options(false, false, 0.0) },
+                                  &mut stdout);
+        assert!(matches!(result, Err(Error::MalformedFasta(_))));
+    }
+
+    #[test]
+    fn test_drop_columns_58() {
+        // The same as test_drop_columns_41 but with the group sizes in the headers.
+        let (path, _dir) = make_test_file(">seq_1 info\nACGATCAG\n\
+                                           >seq_2\nAGGATCGA\n\
+                                           >seq_3\nACGA-CAG\n\
+                                           >seq_4\nAGGATCGA\n\
+                                           >seq_5\nACGATCGG\n");
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &FilterOptions { dedup: true, dedup_counts: true,
+                                                         ..options(true, false, 0.0) },
+                                 &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(),
+                   ">seq_1 count=2\nCAG\n>seq_2 count=2\nGGA\n>seq_5 count=1\nCGG\n");
+        assert_eq!(stats.dedup_collapsed, 2);
+    }
+
+    #[test]
+    fn test_drop_columns_59() {
+        let (path, _dir) = make_test_file(">seq_1\nACGAT\n\
+                                           >seq_2\nAGGAT\n\
+                                           >seq_3\nACGA-\n\
+                                           >seq_4\nACGTT\n\
+                                           >seq_5\nACTAT\n");
+        let run = |seed| {
+            let mut stdout = Vec::new();
+            let stats = drop_columns(&path, &FilterOptions { subsample: Some(3), seed,
+                                                             ..options(false, false, 0.0) },
+                                     &mut stdout).unwrap();
+            assert_eq!(stats.seq_count, 3);
+            String::from_utf8(stdout).unwrap()
+        };
+        assert_eq!(run(1), run(1));
+        assert_eq!(run(1).lines().count(), 6);
+    }
+
+    #[test]
+    fn test_drop_columns_60() {
+        let (path, _dir) = make_test_file(">seq_1 info\nACGAT\n\
+                                           >seq_2\nAN-AT\n\
+                                           >seq_3\nAC-a-\n\
+                                           >seq_4\nGCNTT\n");
+        let dir = tempdir().unwrap();
+        let report_path = dir.path().join("missing.tsv");
+        let run = |threshold| {
+            let mut stdout = Vec::new();
+            drop_columns(&path, &FilterOptions { missing_report: Some(report_path.clone()),
+                                                 missing_report_threshold: threshold,
+                                                 ..options(false, false, 0.5) },
+                         &mut stdout).unwrap();
+            std::fs::read_to_string(&report_path).unwrap()
+        };
+        // The third site is removed for not being core, so it isn't in the report.
+        assert_eq!(run(1), "pos\tmissing\n2\tseq_2\n5\tseq_3\n");
+        assert_eq!(run(2), "pos\tmissing\n");
+
+        let options = FilterOptions { missing_report: Some(report_path.clone()), zero_based: true,
+                                      ..options(false, false, 0.0) };
+        drop_columns(&path, &options, &mut Vec::new()).unwrap();
+        assert_eq!(std::fs::read_to_string(&report_path).unwrap(),
+                   "pos\tmissing\n1\tseq_2\n2\tseq_2,seq_3,seq_4\n4\tseq_3\n");
+    }
+
+    #[test]
+    fn test_drop_columns_61() {
+        let (path, _dir) = make_test_file(">seq_1\nA-GA\n\
+                                           >seq_2\nA-GC\n\
+                                           >seq_3\n--G-\n\
+                                           >seq_4\nA-T-\n");
+        let run = |count_gaps, exclude_invariant| {
+            let mut stdout = Vec::new();
+            let stats = drop_columns(&path, &FilterOptions { count_gaps,
+                                                             ..options(exclude_invariant, false,
+                                                                       1.0) },
+                                     &mut stdout).unwrap();
+            (String::from_utf8(stdout).unwrap(), stats)
+        };
+        let (out, _) = run(false, false);
+        assert_eq!(out, ">seq_1\nG\n>seq_2\nG\n>seq_3\nG\n>seq_4\nT\n");
+        let (out, _) = run(true, false);
+        assert_eq!(out, ">seq_1\nA-GA\n>seq_2\nA-GC\n>seq_3\n--G-\n>seq_4\nA-T-\n");
+
+        // Gaps count towards the core fraction but can't make a site variable.
+        let (out, stats) = run(true, true);
+        assert_eq!(out, ">seq_1\nGA\n>seq_2\nGC\n>seq_3\nG-\n>seq_4\nT-\n");
+        assert_eq!(stats.invariant_a_removed, 1);
+        assert_eq!(stats.invariant_gap_removed, 1);
+        assert_eq!(stats.non_core_removed, 0);
+    }
+
+    #[test]
+    fn test_drop_columns_62() {
+        // The first site is all gaps but one, and the third site has one gap.
+        let (path, _dir) = make_test_file(">seq_1\nACGT\n\
+                                           >seq_2\n-CGA\n\
+                                           >seq_3\n-C-A\n\
+                                           >seq_4\n-CGA\n");
+        let run = |exclude_invariant, core| {
+            let mut stdout = Vec::new();
+            let stats = drop_columns(&path, &FilterOptions { max_gap_frac: Some(0.5),
+                                                             ..options(exclude_invariant, false,
+                                                                       core) },
+                                     &mut stdout).unwrap();
+            (String::from_utf8(stdout).unwrap(), stats)
+        };
+        let (out, stats) = run(false, 0.0);
+        assert_eq!(out, ">seq_1\nCGT\n>seq_2\nCGA\n>seq_3\nC-A\n>seq_4\nCGA\n");
+        assert_eq!(stats.gappy_removed, 1);
+        assert_eq!(stats.removed_total, 1);
+
+        // A site removed by both --core and --max_gap_frac is counted as non-core.
+        let (out, stats) = run(false, 0.5);
+        assert_eq!(out, ">seq_1\nCGT\n>seq_2\nCGA\n>seq_3\nC-A\n>seq_4\nCGA\n");
+        assert_eq!((stats.non_core_removed, stats.gappy_removed), (1, 0));
+
+        // Likewise, an invariant site is counted as invariant.
+        let (out, stats) = run(true, 0.0);
+        assert_eq!(out, ">seq_1\nT\n>seq_2\nA\n>seq_3\nA\n>seq_4\nA\n");
+        assert_eq!((stats.invariant_removed, stats.gappy_removed), (3, 0));
+    }
+
+    #[test]
+    fn test_drop_columns_63() {
+        let (path, _dir) = make_test_file(">seq_1\nACGTA\n\
+                                           >seq_2\nNRGTA\n\
+                                           >seq_3\nN-GTC\n\
+                                           >seq_4\nA?GT-\n");
+        let run = |iupac| {
+            let mut stdout = Vec::new();
+            let stats = drop_columns(&path, &FilterOptions { max_ambiguous_frac: Some(0.4), iupac,
+                                                             ..options(false, false, 0.0) },
+                                     &mut stdout).unwrap();
+            (String::from_utf8(stdout).unwrap(), stats)
+        };
+        let (out, stats) = run(false);
+        assert_eq!(out, ">seq_1\nGTA\n>seq_2\nGTA\n>seq_3\nGTC\n>seq_4\nGT-\n");
+        assert_eq!(stats.ambiguous_removed, 2);
+
+        // With --iupac, R counts as a base, so the second site is no longer too ambiguous.
+        let (out, stats) = run(true);
+        assert_eq!(out, ">seq_1\nCGTA\n>seq_2\nRGTA\n>seq_3\n-GTC\n>seq_4\n?GT-\n");
+        assert_eq!(stats.ambiguous_removed, 1);
+    }
+
+    #[test]
+    fn test_drop_columns_64() {
+        let (path, _dir) = make_test_file(">seq_1\nACGATCAG\n\
+                                           >seq_2\nAC----CG\n\
+                                           >seq_3\nAGGATCAG\n");
+        let dir = tempdir().unwrap();
+        let mask_track = dir.path().join("mask_track.txt");
+        for soft_mask in [false, true] {
+            let options = FilterOptions { mask_track: Some(mask_track.clone()), soft_mask,
+                                          ..options(true, false, 0.7) };
+            drop_columns(&path, &options, &mut Vec::new()).unwrap();
+            assert_eq!(std::fs::read_to_string(&mask_track).unwrap(), "01000010\n");
+        }
+    }
+
+    #[test]
+    fn test_drop_columns_65() {
+        let (path, _dir) = make_test_file(">seq_1\nA.-*N\n\
+                                           >seq_2\nA.-*N\n\
+                                           >seq_3\nC.-*N\n");
+        let run = |gap_chars: &str| {
+            let mut stdout = Vec::new();
+            let stats = drop_columns(&path, &FilterOptions { gap_chars: gap_chars.to_string(),
+                                                             ..options(true, false, 0.0) },
+                                     &mut stdout).unwrap();
+            assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nA\n>seq_2\nA\n>seq_3\nC\n");
+            (stats.invariant_gap_removed, stats.invariant_ambiguous_removed,
+             stats.invariant_n_removed)
+        };
+        assert_eq!(run("-."), (2, 1, 1));
+        assert_eq!(run("-.*"), (3, 0, 1));
+        assert_eq!(run("-"), (1, 2, 1));
+        assert_eq!(run(""), (0, 3, 1));
+    }
+
+    #[test]
+    fn test_drop_columns_66() {
+        let (path, _dir) = make_test_file(">seq_1\nACGAT\n\
+                                           >seq_2\nAGGAT\n");
+        let run = |min_sequences| {
+            drop_columns(&path, &FilterOptions { min_sequences, ..options(true, false, 0.0) },
+                         &mut Vec::new())
+        };
+        assert!(run(2).is_ok());
+        assert!(matches!(run(3), Err(Error::EmptyInput(_))));
+    }
+
+    #[test]
+    fn test_single_sequence_warning() {
+        assert!(single_sequence_warning(1, &options(false, false, 0.0)).is_none());
+        assert!(single_sequence_warning(2, &options(true, false, 0.95)).is_none());
+        assert!(single_sequence_warning(1, &options(true, false, 0.95)).unwrap().contains("-e"));
+        assert!(single_sequence_warning(1, &options(false, false, 0.5)).unwrap()
+                .contains("--core"));
+        assert!(single_sequence_warning(1, &FilterOptions { core_count: Some(1),
+                                                            ..options(false, false, 0.0) })
+                .unwrap().contains("--core"));
+
+        // The output is the same as without the warning.
+        let (path, _dir) = make_test_file(">seq_1\nAC-T\n");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &options(false, false, 1.0), &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nACT\n");
+    }
+
+    #[test]
+    fn test_write_sfs() {
+        let dir = tempdir().unwrap();
+        let sfs = dir.path().join("sfs.tsv");
+        // Biallelic sites with minor counts 1, 2 and 2, then a triallelic and an invariant site.
+        let base_counts = [[4, 1, 0, 0], [0, 3, 0, 2], [2, 0, 3, 0], [1, 1, 3, 0], [5, 0, 0, 0]];
+        let keep = bitvec![1; 5];
+        assert_eq!(write_sfs(&sfs, &base_counts, &keep, 5, None).unwrap(), (0, 0));
+        assert_eq!(std::fs::read_to_string(&sfs).unwrap(), "minor_allele_count\tsites\n\
+                                                            1\t1\n2\t2\n");
+
+        let keep = bitvec![1, 0, 1, 1, 1];
+        assert_eq!(write_sfs(&sfs, &base_counts, &keep, 5, None).unwrap(), (0, 0));
+        assert_eq!(std::fs::read_to_string(&sfs).unwrap(), "minor_allele_count\tsites\n\
+                                                            1\t1\n2\t1\n");
+
+        // The third site can't be polarised, since its ancestral base (T) isn't one of its bases.
+        let keep = bitvec![1; 5];
+        assert_eq!(write_sfs(&sfs, &base_counts, &keep, 5, Some(b"ACTAA")).unwrap(), (0, 1));
+        assert_eq!(std::fs::read_to_string(&sfs).unwrap(), "derived_allele_count\tsites\n\
+                                                            1\t1\n2\t1\n3\t0\n4\t0\n");
+
+        // Without an ancestral base, the first site can't be polarised either.
+        assert_eq!(write_sfs(&sfs, &base_counts, &keep, 5, Some(b"-CTAA")).unwrap(), (1, 1));
+        assert_eq!(std::fs::read_to_string(&sfs).unwrap(), "derived_allele_count\tsites\n\
+                                                            1\t0\n2\t1\n3\t0\n4\t0\n");
+    }
+
+    #[test]
+    fn test_drop_columns_67() {
+        let (path, dir) = make_test_file(">seq_1\nACGAT\n\
+                                          >seq_2\nAGGAT\n\
+                                          >seq_3\nAGGTT\n\
+                                          >seq_4\nACGT-\n");
+        let sfs = dir.path().join("sfs.tsv");
+        let ancestral = dir.path().join("ancestral.fasta");
+        std::fs::write(&ancestral, ">ancestral\nACGAu\n").unwrap();
+        let options = FilterOptions { sfs: Some(sfs.clone()), ..options(true, false, 0.0) };
+        drop_columns(&path, &options, &mut Vec::new()).unwrap();
+        assert_eq!(std::fs::read_to_string(&sfs).unwrap(), "minor_allele_count\tsites\n\
+                                                            1\t0\n2\t2\n");
+        let options = FilterOptions { unfolded: true, ancestral: Some(ancestral.clone()),
+                                      ..options };
+        drop_columns(&path, &options, &mut Vec::new()).unwrap();
+        assert_eq!(std::fs::read_to_string(&sfs).unwrap(), "derived_allele_count\tsites\n\
+                                                            1\t0\n2\t2\n3\t0\n");
+
+        std::fs::write(&ancestral, ">ancestral\nACG\n").unwrap();
+        assert!(matches!(drop_columns(&path, &options, &mut Vec::new()),
+                         Err(Error::BadArguments(_))));
+    }
+
+    #[test]
+    fn test_drop_columns_68() {
+        let (path, _dir) = make_test_file(">seq_1\nACGATCAG\n\
+                                           >seq_2\nAC----CG\n\
+                                           >seq_3\nAGGATCAG\n");
+        let mut stdout = Vec::new();
+        let options = FilterOptions { invariant_only: true, ..options(false, false, 0.7) };
+        let stats = drop_columns(&path, &options, &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nAG\n>seq_2\nAG\n>seq_3\nAG\n");
+        assert_eq!(stats.variable_removed, 2);
+        assert_eq!(stats.non_core_removed, 4);
+        assert_eq!(stats.invariant_removed, 0);
+    }
+
+    #[test]
+    fn test_drop_columns_69() {
+        let (path, _dir) = make_test_file(">seq_3 info\nAC-AT\n\
+                                           >seq_1\nACNA-\n\
+                                           >seq_2\nACGAT\n\
+                                           >seq_0\nAC-AT\n");
+        let run = |sort| {
+            let mut stdout = Vec::new();
+            drop_columns(&path, &FilterOptions { sort: Some(sort), ..options(false, false, 0.0) },
+                         &mut stdout).unwrap();
+            String::from_utf8(stdout).unwrap()
+        };
+        assert_eq!(run(SortOrder::Name), ">seq_0\nAC-AT\n>seq_1\nACNA-\n>seq_2\nACGAT\n\
+                                          >seq_3 info\nAC-AT\n");
+        assert_eq!(run(SortOrder::Aligned), ">seq_2\nACGAT\n>seq_0\nAC-AT\n\
+                                             >seq_3 info\nAC-AT\n>seq_1\nACNA-\n");
+    }
+
+    #[test]
+    fn test_drop_columns_70() {
+        let (path, _dir) = make_test_file(">seq_1 info\nACGAT\n\
+                                           >seq_2\nAGGAT\n\
+                                           >seq_3 more info\nACGTT\n");
+        let dir = tempdir().unwrap();
+        let names = dir.path().join("names.txt");
+        let options = FilterOptions { names: Some(names.clone()), count_only: true,
+                                      ..options(true, false, 0.0) };
+        drop_columns(&path, &options, &mut Vec::new()).unwrap();
+        assert_eq!(std::fs::read_to_string(&names).unwrap(),
+                   "seq_1 info\nseq_2\nseq_3 more info\n");
+    }
+
+    #[test]
+    fn test_drop_columns_71() {
+        // The same alignment as test_drop_columns_8, where no sites pass.
+        let (path, _dir) = make_test_file(">seq_1\nACGATCA-\n\
+                                           >seq_2\nAC----AC\n\
+                                           >seq_3\nACGATCAG\n");
+        let stats = drop_columns(&path, &options(true, false, 0.7), &mut Vec::new()).unwrap();
+        assert_eq!(stats.output_size, 0);
+        let strict = FilterOptions { error_on_empty: true, ..options(true, false, 0.7) };
+        match drop_columns(&path, &strict, &mut Vec::new()) {
+            Err(Error::EmptyOutput(message)) => assert!(message.contains("(--core 0.7, -e)")),
+            _ => panic!("expected an EmptyOutput error"),
+        }
+        assert!(drop_columns(&path, &FilterOptions { error_on_empty: true,
+                                                     ..options(true, false, 0.0) },
+                             &mut Vec::new()).is_ok());
+    }
+
+    #[test]
+    fn test_drop_columns_72() {
+        // An interleaved PHYLIP alignment gives the same output as the same alignment in FASTA.
+        let (fasta, _dir_1) = make_test_file(">a\nACGATCAG\n>b\nAC-ATCAC\n>c\nACGTTCAG\n");
+        let (phylip, _dir_2) = make_test_file("3 8\na ACGA\nb AC-A\nc ACGT\n\n\
+                                               TCAG\nTCAC\nTCAG\n");
+        let mut expected = Vec::new();
+        drop_columns(&fasta, &options(true, false, 0.0), &mut expected).unwrap();
+        let phylip_options = FilterOptions { input_format: InputFormat::Phylip,
+                                             ..options(true, false, 0.0) };
+        let mut out = Vec::new();
+        let stats = drop_columns(&phylip, &phylip_options, &mut out).unwrap();
+        assert_eq!(from_utf8(&out).unwrap(), ">a\nAG\n>b\nAC\n>c\nTG\n");
+        assert_eq!(out, expected);
+        assert_eq!(stats.seq_count, 3);
+
+        // Reading it as FASTA fails.
+        assert!(matches!(drop_columns(&phylip, &options(true, false, 0.0), &mut Vec::new()),
+                         Err(Error::MalformedFasta(_))));
+    }
+
+    #[test]
+    fn test_drop_columns_73() {
+        // The same alignment as test_drop_columns_72, but in Clustal format.
+        let (clustal, _dir) = make_test_file("CLUSTAL O(1.2.4) multiple sequence alignment\n\n\
+                                              a    ACGA\nb    AC-A\nc    ACGT\n     ** \n\n\
+                                              a    TCAG\nb    TCAC\nc    TCAG\n     *** \n");
+        let clustal_options = FilterOptions { input_format: InputFormat::Clustal,
+                                              ..options(true, false, 0.0) };
+        let mut out = Vec::new();
+        drop_columns(&clustal, &clustal_options, &mut out).unwrap();
+        assert_eq!(from_utf8(&out).unwrap(), ">a\nAG\n>b\nAC\n>c\nTG\n");
+
+        // Sequences of different lengths are caught like any other alignment's.
+        let (clustal, _dir) = make_test_file("CLUSTAL W\n\na    ACGA\nb    AC\n");
+        assert!(matches!(drop_columns(&clustal, &clustal_options, &mut Vec::new()),
+                         Err(Error::MalformedFasta(_))));
+    }
+
+    #[test]
+    fn test_drop_columns_74() {
+        let (path, _dir) = make_test_file(">seq_1\nACGATCAGTT\n\
+                                           >seq_2\nACGTTCAGT-\n\
+                                           >seq_3\nACGATCCGTA\n");
+        let dir = tempdir().unwrap();
+        let raxml_info = dir.path().join("asc.txt");
+        let stats = drop_columns(&path, &FilterOptions { raxml_info: Some(raxml_info.clone()),
+                                                         ..options(true, false, 0.0) },
+                                 &mut Vec::new()).unwrap();
+        assert_eq!(std::fs::read_to_string(&raxml_info).unwrap(), "1 2 2 2\n");
+        assert_eq!((stats.invariant_a_removed, stats.invariant_c_removed,
+                    stats.invariant_g_removed, stats.invariant_t_removed), (1, 2, 2, 2));
+    }
+
+    #[test]
+    fn test_drop_columns_75() {
+        // The same selections as test_drop_columns_42 and test_drop_columns_43, made by regex.
+        let (path, _dir) = make_test_file(">seq_1\nACGATCAG\n\
+                                           >seq_2 info\nAC---CGA\n\
+                                           >seq_3\nAGGATCAN\n\
+                                           >seq_4\nA---TCGA\n");
+        for max_memory in [1.0, 0.0] {
+            let mut stdout = Vec::new();
+            let stats = drop_columns(&path, &FilterOptions { include_regex: Some("_[12]$".into()),
+                                                             max_memory,
+                                                             ..options(true, false, 1.0) },
+                                     &mut stdout).unwrap();
+            assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nAG\n>seq_2 info\nGA\n");
+            assert_eq!(stats.seq_count, 2);
+        }
+        let mut stdout = Vec::new();
+        drop_columns(&path, &FilterOptions { exclude_regex: Some("[34]".into()),
+                                             ..options(true, false, 1.0) },
+                     &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nAG\n>seq_2 info\nGA\n");
+    }
+
+    #[test]
+    fn test_drop_columns_76() {
+        let (path, _dir) = make_test_file(">seq_1 info\nACGATCAG\n\
+                                           >seq_2\nAC-TTCGG\n\
+                                           >seq_3\nTGCATcAg\n\
+                                           >seq_4\nACNATCAT\n");
+        let dir = tempdir().unwrap();
+        let snp_counts = dir.path().join("snps.tsv");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &FilterOptions { snp_counts: Some(snp_counts.clone()),
+                                             ..options(true, false, 0.0) },
+                     &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(),
+                   ">seq_1 info\nACGAAG\n>seq_2\nAC-TGG\n>seq_3\nTGCAAg\n>seq_4\nACNAAT\n");
+
+        // The third site is a tie between C and G, which goes to C.
+        assert_eq!(std::fs::read_to_string(&snp_counts).unwrap(),
+                   "name\tsnp_count\tmissing_count\n\
+                    seq_1\t1\t0\nseq_2\t2\t1\nseq_3\t2\t0\nseq_4\t1\t1\n");
+
+        // Lowercase bases can be missing too.
+        drop_columns(&path, &FilterOptions { snp_counts: Some(snp_counts.clone()),
+                                             lowercase_missing: true,
+                                             ..options(true, false, 0.0) },
+                     &mut Vec::new()).unwrap();
+        assert!(std::fs::read_to_string(&snp_counts).unwrap().contains("\nseq_3\t2\t1\n"));
+    }
+
+    #[test]
+    fn test_drop_columns_77() {
+        // Only N can make a site variable with --n_is_allele: a site with just Ns, or with a base
+        // and gaps, is still invariant.
+        let (path, _dir) = make_test_file(">seq_1\nAAACNA\n\
+                                           >seq_2\nAANGN-\n\
+                                           >seq_3\nANNGNA\n");
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &options(true, false, 0.0), &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nC\n>seq_2\nG\n>seq_3\nG\n");
+        assert_eq!(stats.invariant_removed, 5);
+
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &FilterOptions { n_is_allele: true,
+                                                         ..options(true, false, 0.0) },
+                                 &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nAAC\n>seq_2\nANG\n>seq_3\nNNG\n");
+        assert_eq!(stats.invariant_removed, 3);
+
+        let mut stdout = Vec::new();
+        drop_columns(&path, &FilterOptions { n_is_allele: true, invariant_only: true,
+                                             ..options(false, false, 0.0) },
+                     &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nANA\n>seq_2\nAN-\n>seq_3\nANA\n");
+    }
+
+    #[test]
+    fn test_drop_columns_78() {
+        // The partitions are rewritten for the kept sites, and gene_3 has none left.
+        let (path, _dir) = make_test_file(">seq_1\nAAAACGTA\n\
+                                           >seq_2\nACAAGGTA\n\
+                                           >seq_3\nACAATGTA\n");
+        let dir = tempdir().unwrap();
+        let partition_in = dir.path().join("partitions.txt");
+        let partition_out = dir.path().join("filtered_partitions.txt");
+        std::fs::write(&partition_in, "DNA, gene_1 = 1-4
+DNA, gene_2 = 5-6
+\
+                                       DNA, gene_3 = 7-8
+").unwrap();
+        let mut stdout = Vec::new();
+        drop_columns(&path, &FilterOptions { partition_in: Some(partition_in.clone()),
+                                             partition_out: Some(partition_out.clone()),
+                                             ..options(true, false, 0.0) },
+                     &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nAC\n>seq_2\nCG\n>seq_3\nCT\n");
+        assert_eq!(std::fs::read_to_string(&partition_out).unwrap(),
+                   "DNA, gene_1 = 1\nDNA, gene_2 = 2\n");
+
+        // Partitions which don't fit the alignment are an error.
+        std::fs::write(&partition_in, "DNA, gene_1 = 1-9\n").unwrap();
+        assert!(matches!(drop_columns(&path, &FilterOptions { partition_in: Some(partition_in),
+                                                              partition_out: Some(partition_out),
+                                                              ..options(true, false, 0.0) },
+                                      &mut Vec::new()),
+                         Err(Error::BadArguments(_))));
+    }
+
+    #[test]
+    fn test_drop_columns_79() {
+        // The all-gap and all-N sites are removed, even at --core 0.
+        let (path, _dir) = make_test_file(">seq_1\nA-NCA\n\
+                                           >seq_2\nA-NGA\n\
+                                           >seq_3\nA-N-A\n");
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &FilterOptions { drop_empty_columns: true,
+                                                         ..options(false, false, 0.0) },
+                                 &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nACA\n>seq_2\nAGA\n>seq_3\nA-A\n");
+        assert_eq!(stats.empty_removed, 2);
+
+        // Empty sites are counted after the other filters.
+        let stats = drop_columns(&path, &FilterOptions { drop_empty_columns: true,
+                                                         ..options(true, false, 0.0) },
+                                 &mut Vec::new()).unwrap();
+        assert_eq!((stats.invariant_removed, stats.empty_removed), (4, 0));
+        let stats = drop_columns(&path, &FilterOptions { drop_empty_columns: true,
+                                                         ..options(false, false, 0.5) },
+                                 &mut Vec::new()).unwrap();
+        assert_eq!((stats.non_core_removed, stats.empty_removed), (2, 0));
+    }
+
+    #[test]
+    fn test_drop_columns_80() {
+        // The outgroup gives the ancestral state, but not at the fourth site where it has an N.
+        let (path, dir) = make_test_file(">seq_1\nACGAT\n\
+                                          >seq_2\nAGGAT\n\
+                                          >seq_3\nAGGTT\n\
+                                          >outgroup info\nACGN-\n");
+        let sfs = dir.path().join("sfs.tsv");
+        let options = FilterOptions { sfs: Some(sfs.clone()), unfolded: true,
+                                      ancestral_name: Some("outgroup".to_string()),
+                                      ..options(true, false, 0.0) };
+        drop_columns(&path, &options, &mut Vec::new()).unwrap();
+        assert_eq!(std::fs::read_to_string(&sfs).unwrap(), "derived_allele_count\tsites\n\
+                                                            1\t0\n2\t1\n3\t0\n");
+
+        let options = FilterOptions { ancestral_name: Some("seq_4".to_string()), ..options };
+        assert!(matches!(drop_columns(&path, &options, &mut Vec::new()),
+                         Err(Error::BadArguments(_))));
+    }
+
+    #[test]
+    fn test_drop_columns_81() {
+        // Reading from disk with --mmap gives the same result as reading normally.
+        let (path, _dir) = make_test_file(">seq_1\nACGATCAG\n\
+                                           >seq_2\nAC----GA\n\
+                                           >seq_3\nAGGATCAG\n");
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &FilterOptions { mmap: true, max_memory: 0.0,
+                                                         ..options(true, false, 0.0) },
+                                 &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nCAG\n>seq_2\nCGA\n>seq_3\nGAG\n");
+        assert_eq!(stats.invariant_removed, 5);
+    }
+
+    #[test]
+    fn test_drop_columns_82() {
+        // Same alignment as test_drop_columns_54 but with the allele count column (after the
+        // entropy column when both are used).
+        let (path, dir) = make_test_file(">seq_1\nACGA\n\
+                                          >seq_2\nAC-C\n\
+                                          >seq_3\nAGGA\n\
+                                          >seq_4\nAGGT\n");
+        let table_path = dir.path().join("table.tsv");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &FilterOptions { table: Some(table_path.clone()),
+                                             with_allele_count: true,
+                                             ..options(true, false, 0.0) },
+                     &mut stdout).unwrap();
+        assert_eq!(std::fs::read_to_string(&table_path).unwrap(),
+                   "pos\ta\tc\tg\tt\tcount\tfrac\tvar\tkeep\talleles\n\
+                    1\t1\t0\t0\t0\t4\t1.0000\t0\t0\t1\n\
+                    2\t0\t1\t1\t0\t4\t1.0000\t1\t1\t2\n\
+                    3\t0\t0\t1\t0\t3\t0.7500\t0\t0\t1\n\
+                    4\t1\t1\t0\t1\t4\t1.0000\t1\t1\t3\n");
+
+        drop_columns(&path, &FilterOptions { table: Some(table_path.clone()), with_entropy: true,
+                                             with_allele_count: true,
+                                             ..options(false, false, 0.0) },
+                     &mut stdout).unwrap();
+        let table = std::fs::read_to_string(&table_path).unwrap();
+        assert!(table.starts_with("pos\ta\tc\tg\tt\tcount\tfrac\tvar\tkeep\tentropy\talleles\n"));
+        assert!(table.ends_with("\t1.5000\t3\n"));
+    }
+
+    #[test]
+    fn test_drop_columns_83() {
+        // For protein alignments, the allele count is the number of different amino acids.
+        let (path, dir) = make_test_file(">seq_1\nMKW\n\
+                                          >seq_2\nMR-\n\
+                                          >seq_3\nMLX\n");
+        let table_path = dir.path().join("table.tsv");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &FilterOptions { table: Some(table_path.clone()),
+                                             with_allele_count: true, alphabet: Alphabet::Protein,
+                                             ..options(false, false, 0.0) },
+                     &mut stdout).unwrap();
+        let alleles: Vec<String> = std::fs::read_to_string(&table_path).unwrap().lines().skip(1)
+            .map(|line| line.rsplit('\t').next().unwrap().to_string()).collect();
+        assert_eq!(alleles, vec!["1", "3", "1"]);
+    }
+
+    #[test]
+    fn test_drop_columns_84() {
+        // An empty sequence in the middle of the alignment is an error which names it, unless
+        // --skip_empty is used, in which case it's left out (and not counted for --core).
+        let (path, _dir) = make_test_file(">seq_1\nACGT\n\
+                                           >seq_2\n\
+                                           >seq_3\nAC-A\n");
+        let mut stdout = Vec::new();
+        match drop_columns(&path, &options(true, false, 1.0), &mut stdout) {
+            Err(Error::MalformedFasta(message)) => assert!(message.contains("seq_2 is empty")),
+            _ => panic!("expected an error"),
+        }
+        for max_memory in [0.0, 1.0] {
+            let mut stdout = Vec::new();
+            let stats = drop_columns(&path, &FilterOptions { skip_empty: true, max_memory,
+                                                             ..options(true, false, 1.0) },
+                                     &mut stdout).unwrap();
+            assert_eq!(stats.seq_count, 2);
+            assert_eq!(String::from_utf8(stdout).unwrap(), ">seq_1\nT\n>seq_3\nA\n");
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "sha2")]
+    fn test_drop_columns_85() {
+        // Checksums are of the output sequences (without their headers), in output order.
+        let (path, dir) = make_test_file(">seq_2 info\nACGT\n\
+                                          >seq_1\nACGA\n");
+        let checksums = dir.path().join("checksums.tsv");
+        let expected = "seq_2\te632b7095b0bf32c260fa4c539e9fd7b852d0de454e9be26f24d0d6f91d069d3\n\
+                        seq_1\t559aead08264d5795d3909718cdd05abd49572e84fe55590eef31a88a08fdffd\n";
+        let mut stdout = Vec::new();
+        drop_columns(&path, &FilterOptions { checksums: Some(checksums.clone()),
+                                             ..options(true, false, 0.0) },
+                     &mut stdout).unwrap();
+        assert_eq!(String::from_utf8(stdout).unwrap(), ">seq_2 info\nT\n>seq_1\nA\n");
+        assert_eq!(std::fs::read_to_string(&checksums).unwrap(), expected);
+
+        // Sorting changes the order, but not the checksums.
+        let mut stdout = Vec::new();
+        drop_columns(&path, &FilterOptions { checksums: Some(checksums.clone()),
+                                             sort: Some(SortOrder::Name),
+                                             ..options(true, false, 0.0) },
+                     &mut stdout).unwrap();
+        let lines: Vec<&str> = expected.lines().rev().collect();
+        assert_eq!(std::fs::read_to_string(&checksums).unwrap(), lines.join("\n") + "\n");
+
+        assert!(matches!(check_arguments(&FilterOptions { checksums: Some(checksums),
+                                                          count_only: true,
+                                                          ..options(true, false, 0.0) }),
+                         Err(Error::BadArguments(_))));
+    }
+
+    #[test]
+    #[cfg(feature = "sha2")]
+    fn test_drop_columns_86() {
+        // The alignment hash is the SHA-256 of the output, so it's the same as for sha256sum.
+        let (path, _dir) = make_test_file(">seq_1\nACGT\n\
+                                           >seq_2\nACGA\n");
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &FilterOptions { alignment_hash: true,
+                                                         ..options(true, false, 0.0) },
+                                 &mut stdout).unwrap();
+        assert_eq!(stdout, b">seq_1\nT\n>seq_2\nA\n");
+        assert_eq!(stats.alignment_hash.unwrap(),
+                   "c039bac55ae309dfae10cdfe5f070870f39c4fb2356149b8ed524d2f2c6847e2");
+
+        let stats = drop_columns(&path, &options(true, false, 0.0), &mut Vec::new()).unwrap();
+        assert!(stats.alignment_hash.is_none());
+    }
+
+    #[test]
+    fn test_drop_columns_87() {
+        // With --ambiguous_to_n, the R at the second site is output as an N, but with --iupac it
+        // still counts towards the core and makes the site invariant (A and R could both be A).
+        let (path, _dir) = make_test_file(">seq_1\nAAC?\n\
+                                           >seq_2\nARGr\n\
+                                           >seq_3\nAAGT\n");
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &FilterOptions { iupac: true, ambiguous_to_n: true,
+                                                         ..options(false, false, 1.0) },
+                                 &mut stdout).unwrap();
+        assert_eq!(stats.non_core_removed, 1);
+        assert_eq!(String::from_utf8(stdout).unwrap(), ">seq_1\nAAC\n>seq_2\nANG\n>seq_3\nAAG\n");
+
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &FilterOptions { iupac: true, ambiguous_to_n: true,
+                                                         ..options(true, false, 0.0) },
+                                 &mut stdout).unwrap();
+        assert_eq!(stats.invariant_a_removed, 2);
+        assert_eq!(String::from_utf8(stdout).unwrap(), ">seq_1\nCN\n>seq_2\nGn\n>seq_3\nGT\n");
+    }
+
+    #[test]
+    fn test_drop_columns_88() {
+        // Gaps and Ns can be swapped in the output. The filtering is unchanged.
+        let (path, dir) = make_test_file(">seq_1\nACGTA-\n\
+                                          >seq_2\nA-NTGn\n\
+                                          >seq_3\nAC-TCN\n");
+        let run = |gap_to_n, n_to_gap| {
+            let mut stdout = Vec::new();
+            drop_columns(&path, &FilterOptions { gap_to_n, n_to_gap, ..options(false, false, 0.0) },
+                         &mut stdout).unwrap();
+            String::from_utf8(stdout).unwrap()
+        };
+        assert_eq!(run(false, false), ">seq_1\nACGTA-\n>seq_2\nA-NTGn\n>seq_3\nAC-TCN\n");
+        assert_eq!(run(true, false), ">seq_1\nACGTAN\n>seq_2\nANNTGn\n>seq_3\nACNTCN\n");
+        assert_eq!(run(false, true), ">seq_1\nACGTA-\n>seq_2\nA--TG-\n>seq_3\nAC-TC-\n");
+
+        // Only the kept sites are changed, not those in --removed_output.
+        let removed = dir.path().join("removed.fasta");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &FilterOptions { n_to_gap: true, removed_output: Some(removed.clone()),
+                                             ..options(true, false, 0.0) },
+                     &mut stdout).unwrap();
+        assert_eq!(String::from_utf8(stdout).unwrap(), ">seq_1\nA\n>seq_2\nG\n>seq_3\nC\n");
+        assert_eq!(std::fs::read_to_string(&removed).unwrap(),
+                   ">seq_1\nACGT-\n>seq_2\nA-NTn\n>seq_3\nAC-TN\n");
+    }
+
+    #[test]
+    fn test_first_length_warning() {
+        let (path, _dir) = make_test_file(">seq_1\nACGT\n\
+                                           >seq_2\nACGTA\n\
+                                           >seq_3\nACGTA\n");
+        for max_memory in [0, 1000] {
+            let alignment = Alignment::load(&path, max_memory, None, false).unwrap();
+            let warning = first_length_warning(&alignment, 4).unwrap();
+            assert!(warning.contains("seq_1"));
+            assert!(warning.contains("(2 of 3) are 5 bp"));
+        }
+
+        // If the first sequence has the most common length (or is tied for it), there's no
+        // warning, since the error already names a sequence with an odd length.
+        let (path, _dir) = make_test_file(">seq_1\nACGT\n\
+                                           >seq_2\nACGT\n\
+                                           >seq_3\nACGTA\n");
+        let alignment = Alignment::load(&path, 1000, None, false).unwrap();
+        assert!(first_length_warning(&alignment, 4).is_none());
+        let (path, _dir) = make_test_file(">seq_1\nACGT\n\
+                                           >seq_2\nACGTA\n");
+        let alignment = Alignment::load(&path, 1000, None, false).unwrap();
+        assert!(first_length_warning(&alignment, 4).is_none());
+
+        // The filtering still fails, naming the sequence which doesn't match the first.
+        let (path, _dir) = make_test_file(">seq_1\nACGT\n\
+                                           >seq_2\nACGTA\n\
+                                           >seq_3\nACGTA\n");
+        match drop_columns(&path, &options(false, false, 0.0), &mut Vec::new()) {
+            Err(Error::MalformedFasta(message)) => assert!(message.contains("seq_2 is 5 bp")),
+            _ => panic!("expected an error"),
+        }
+    }
+
+    #[test]
+    fn test_drop_columns_89() {
+        // Only the VCF's sites are considered, so site 1 is left out even though it's force-kept.
+        let (path, dir) = make_test_file(">seq_1\nACGATCAG\n\
+                                          >seq_2\nAC----GA\n\
+                                          >seq_3\nAGGATCAG\n");
+        let vcf_path = dir.path().join("snps.vcf");
+        std::fs::write(&vcf_path, "##fileformat=VCFv4.1\n\
+                                   #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+                                   1\t2\t.\tC\tG\t.\t.\t.\n\
+                                   1\t8\t.\tG\tA\t.\t.\t.\n").unwrap();
+        let positions_path = dir.path().join("positions.txt");
+        std::fs::write(&positions_path, "1\n").unwrap();
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &FilterOptions { keep_from_vcf: Some(vcf_path.clone()),
+                                                         keep_positions: Some(positions_path),
+                                                         ..options(true, false, 0.0) },
+                                 &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nCG\n>seq_2\nCA\n>seq_3\nGG\n");
+        assert_eq!(stats.not_in_vcf, 6);
+        assert_eq!(stats.removed_total, 6);
+        assert_eq!(stats.force_kept, 0);
+
+        // VCF positions past the end of the alignment are an error.
+        std::fs::write(&vcf_path, "1\t9\t.\tG\tA\t.\t.\t.\n").unwrap();
+        let mut stdout = Vec::new();
+        let result = drop_columns(&path, &FilterOptions { keep_from_vcf: Some(vcf_path),
+                                                          ..options(true, false, 0.0) },
+                                  &mut stdout);
+        assert!(matches!(result, Err(Error::BadArguments(_))));
+    }
+
+    #[test]
+    fn test_drop_columns_90() {
+        // Site 2 is in both the BED file and the VCF, so it's only counted as BED-masked.
+        let (path, dir) = make_test_file(">seq_1\nACGATCAG\n\
+                                          >seq_2\nAC----GA\n\
+                                          >seq_3\nAGGATCAG\n");
+        let bed_path = dir.path().join("mask.bed");
+        std::fs::write(&bed_path, "chr\t1\t2\n").unwrap();
+        let vcf_path = dir.path().join("homoplasies.vcf");
+        std::fs::write(&vcf_path, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+                                   1\t2\t.\tC\tG\t.\t.\t.\n\
+                                   1\t3\t.\tG\tT\t.\t.\t.\n").unwrap();
+        let mut stdout = Vec::new();
+        let stats = drop_columns(&path, &FilterOptions { mask_bed: Some(bed_path),
+                                                         mask_from_vcf: Some(vcf_path),
+                                                         ..options(false, false, 0.0) },
+                                 &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(),
+                   ">seq_1\nAATCAG\n>seq_2\nA---GA\n>seq_3\nAATCAG\n");
+        assert_eq!(stats.masked_removed, 1);
+        assert_eq!(stats.vcf_masked_removed, 1);
+        assert_eq!(stats.removed_total, 2);
+    }
+
+    #[test]
+    fn test_drop_columns_91() {
+        // Site 1 has 3 As and 1 G, site 3 has 3 Gs and 1 T, and site 5 is invariant.
+        let (path, dir) = make_test_file(">seq_1\nACGTA\n\
+                                          >seq_2\nACGTA\n\
+                                          >seq_3\nACTTA\n\
+                                          >seq_4\nGCGT-\n");
+        let vcf_path = dir.path().join("sites.vcf");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &FilterOptions { vcf: Some(vcf_path.clone()),
+                                             ..options(false, false, 0.0) },
+                     &mut stdout).unwrap();
+        let vcf = std::fs::read_to_string(&vcf_path).unwrap();
+        assert!(vcf.starts_with("##fileformat=VCFv4.2\n"));
+        assert!(vcf.contains("##contig=<ID=1,length=5>\n"));
+        let records: Vec<&str> = vcf.lines().filter(|line| !line.starts_with('#')).collect();
+        assert_eq!(records, vec!["1\t1\t.\tA\tG\t.\t.\tAC=1;AN=4;AF=0.2500",
+                                 "1\t3\t.\tG\tT\t.\t.\tAC=1;AN=4;AF=0.2500"]);
+
+        // Only kept sites are included, and the VCF can be read back by --keep_from_vcf.
+        let mut stdout = Vec::new();
+        drop_columns(&path, &FilterOptions { vcf: Some(vcf_path.clone()), region: Some((2, 5)),
+                                             ..options(false, false, 0.0) },
+                     &mut stdout).unwrap();
+        let vcf = std::fs::read_to_string(&vcf_path).unwrap();
+        assert_eq!(vcf.lines().filter(|line| !line.starts_with('#')).count(), 1);
+        let mut stdout = Vec::new();
+        drop_columns(&path, &FilterOptions { keep_from_vcf: Some(vcf_path),
+                                             ..options(false, false, 0.0) },
+                     &mut stdout).unwrap();
+        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nG\n>seq_2\nG\n>seq_3\nT\n>seq_4\nG\n");
+
+        // With --iupac, the all-R site 1 is invariant (R fits every sequence), so it isn't an A/G
+        // SNP. At site 4, Y adds to both C and T but is still only one of the 3 alleles in AN.
+        let (path, dir) = make_test_file(">seq_1\nRACGTA\n\
+                                          >seq_2\nRACGTA\n\
+                                          >seq_3\nRACYTA\n");
+        let vcf_path = dir.path().join("sites.vcf");
+        let mut stdout = Vec::new();
+        drop_columns(&path, &FilterOptions { vcf: Some(vcf_path.clone()), iupac: true,
+                                             ..options(false, false, 0.0) },
+                     &mut stdout).unwrap();
+        let vcf = std::fs::read_to_string(&vcf_path).unwrap();
+        let records: Vec<&str> = vcf.lines().filter(|line| !line.starts_with('#')).collect();
+        assert_eq!(records, vec!["1\t4\t.\tG\tC,T\t.\t.\tAC=1,1;AN=3;AF=0.3333,0.3333"]);
+    }
+
+    /// A random alignment of up to six sequences, with bases (in either case), Ns and gaps.
+    fn random_alignment() -> impl Strategy<Value = Vec<Vec<u8>>> {
+        (1..6usize, 1..30usize).prop_flat_map(|(seq_count, length)| {
+            let base = prop::sample::select(b"ACGTacgtN-".to_vec());
+            prop::collection::vec(prop::collection::vec(base, length), seq_count)
+        })
+    }
+
+    fn to_fasta(seqs: &[Vec<u8>]) -> String {
+        seqs.iter().enumerate()
+            .map(|(i, seq)| format!(">seq_{}\n{}\n", i + 1, from_utf8(seq).unwrap())).collect()
+    }
+
+    proptest! {
+        #[test]
+        fn test_drop_columns_properties(seqs in random_alignment(), exclude_invariant: bool,
+                                        pars_informative: bool,
+                                        core in prop::sample::select(vec![0.0, 0.5, 0.9, 1.0])) {
+            let (path, _dir) = make_test_file(&to_fasta(&seqs));
+            let options = FilterOptions { pars_informative,
+                                          ..options(exclude_invariant, false, core) };
+            let mut stdout = Vec::new();
+            let stats = drop_columns(&path, &options, &mut stdout).unwrap();
+            prop_assert_eq!(stats.output_size + stats.removed_total, stats.alignment_length);
+            prop_assert_eq!(stats.seq_count, seqs.len());
+
+            // Every output sequence is the same length.
+            let output = String::from_utf8(stdout).unwrap();
+            let out_seqs: Vec<&str> = output.lines().filter(|l| !l.starts_with('>')).collect();
+            prop_assert_eq!(out_seqs.len(), seqs.len());
+            prop_assert!(out_seqs.iter().all(|seq| seq.len() == stats.output_size));
+
+            // Filtering the output again with the same options changes nothing.
+            if stats.output_size > 0 {
+                let (path_2, _dir_2) = make_test_file(&output);
+                let mut stdout_2 = Vec::new();
+                let stats_2 = drop_columns(&path_2, &options, &mut stdout_2).unwrap();
+                prop_assert_eq!(stats_2.removed_total, 0);
+                prop_assert_eq!(from_utf8(&stdout_2).unwrap(), output);
+            }
+        }
+    }
+
+    #[test]
+    fn test_filter_records() {
+        let records = vec![("seq_1".to_string(), b"ACGATCAG".to_vec()),
+                           ("seq_2 info".to_string(), b"AC----GA".to_vec()),
+                           ("seq_3".to_string(), b"AGGATCAG".to_vec())];
+        let (filtered, stats) = filter_records(records, &options(true, false, 0.0)).unwrap();
+        assert_eq!(filtered, vec![("seq_1".to_string(), "CAG".to_string()),
+                                  ("seq_2 info".to_string(), "CGA".to_string()),
+                                  ("seq_3".to_string(), "GAG".to_string())]);
+        assert_eq!(stats.output_size, 3);
+        assert_eq!(stats.invariant_removed, 5);
+
+        let records = vec![("seq_1".to_string(), b"ACGT".to_vec()),
+                           ("seq_2".to_string(), b"ACG".to_vec())];
+        assert!(matches!(filter_records(records, &options(true, false, 0.0)),
+                         Err(Error::MalformedFasta(_))));
+        assert!(matches!(filter_records(Vec::new(), &options(true, false, 0.0)),
+                         Err(Error::EmptyInput(_))));
+    }
+
+    #[test]
+    fn test_write_sequence() {
+        // Gives the same output as remove_columns, including for sequences longer than the
+        // write buffer.
+        let length = WRITE_BUFFER_SIZE * 2 + 10;
+        let seq: Vec<u8> = (0..length).map(|i| b"ACGTacgtN-"[i % 10]).collect();
+        let keep: BitVec = (0..length).map(|i| i % 3 != 0).collect();
+        let kept_count = keep.count_ones();
+        // The same buffer is used for each run, as it would be for each sequence.
+        let mut buffer = b"leftover".to_vec();
+        for (mask_char, uppercase) in [(None, false), (None, true), (Some(b'N'), false)] {
+            let chars = output_chars(&FilterOptions { uppercase, ..options(false, false, 0.0) },
+                                     true);
+            let output_size = if mask_char.is_some() { length } else { kept_count };
+            let mut expected = Vec::new();
+            output_sequence("seq_1 info",
+                            &remove_columns(&seq, &keep, output_size, mask_char, &chars),
+                            &mut expected).unwrap();
+            let mut out = Vec::new();
+            write_sequence("seq_1 info", &seq, &keep, mask_char, &chars, &mut buffer, &mut out)
+                .unwrap();
+            assert_eq!(out, expected);
+
+            let mut kept_seq = "leftover".to_string();
+            remove_columns_into(&seq, &keep, mask_char, &chars, &mut kept_seq);
+            assert_eq!(kept_seq, remove_columns(&seq, &keep, output_size, mask_char, &chars));
+        }
+    }
+
+    #[test]
+    fn test_output_chars() {
+        let convert = |seq: &[u8], chars: [u8; 256]| -> Vec<u8> {
+            seq.iter().map(|&c| chars[c as usize]).collect()
+        };
+        let chars = |uppercase, ambiguous_to_n, gap_to_n, n_to_gap, alphabet, gap_chars: &str| {
+            output_chars(&FilterOptions { uppercase, ambiguous_to_n, gap_to_n, n_to_gap, alphabet,
+                                          gap_chars: gap_chars.to_string(),
+                                          ..options(false, false, 0.0) }, true)
+        };
+        let (dna, rna) = (Alphabet::Dna, Alphabet::Rna);
+        let seq = b"ACGTUNacgtunRYry?*-.";
+        assert_eq!(convert(seq, chars(false, false, false, false, dna, "-.")), seq.to_vec());
+        assert_eq!(convert(seq, chars(true, false, false, false, dna, "-.")),
+                   b"ACGTUNACGTUNRYRY?*-.".to_vec());
+        assert_eq!(convert(seq, chars(false, true, false, false, dna, "-.")),
+                   b"ACGTNNacgtnnNNnnNN-.".to_vec());
+        assert_eq!(convert(seq, chars(false, true, false, false, rna, "-")),
+                   b"ACGTUNacgtunNNnnNN-N".to_vec());
+        assert_eq!(convert(seq, chars(true, true, false, false, dna, "-.")),
+                   b"ACGTNNACGTNNNNNNNN-.".to_vec());
+        assert_eq!(convert(seq, chars(false, false, true, false, dna, "-.")),
+                   b"ACGTUNacgtunRYry?*NN".to_vec());
+        assert_eq!(convert(seq, chars(false, false, false, true, dna, "-.")),
+                   b"ACGTU-acgtu-RYry?*-.".to_vec());
+        assert_eq!(convert(seq, chars(false, false, false, true, dna, ".-")),
+                   b"ACGTU.acgtu.RYry?*-.".to_vec());
+        assert_eq!(convert(seq, chars(false, true, false, true, dna, "-.")),
+                   b"ACGT--acgt---------.".to_vec());
+
+        // Removed sites are only uppercased.
+        let removed = output_chars(&FilterOptions { uppercase: true, ambiguous_to_n: true,
+                                                    n_to_gap: true, ..options(false, false, 0.0) },
+                                   false);
+        assert_eq!(convert(seq, removed), b"ACGTUNACGTUNRYRY?*-.".to_vec());
+    }
+
+    #[test]
+    fn test_bitvectors_and_counts_8() {
+        let (path, _dir) = make_test_file(">seq_1\nACNR?\n\
+                                           >seq_2\nA-nR-\n");
+        let alignment = Alignment::OnDisk(path, None, false);
+        let counts = bitvectors_and_counts(&alignment, 5, false, None, None, false, false, "-",
+                                           &Progress::hidden()).unwrap();
+        assert_eq!(counts.ambiguous_counts, vec![0, 0, 2, 2, 1]);
+        let counts = bitvectors_and_counts(&alignment, 5, true, None, None, false, false, "-",
+                                           &Progress::hidden()).unwrap();
+        assert_eq!(counts.ambiguous_counts, vec![0, 0, 2, 0, 1]);
+        let counts = bitvectors_and_counts(&alignment, 5, false, Some("-"), None, false, false, "-",
+                                           &Progress::hidden())
+            .unwrap();
+        assert_eq!(counts.ambiguous_counts, vec![0, 0, 2, 2, 1]);
+    }
+
+    #[test]
+    fn test_bitvectors_and_counts_9() {
+        let (path, _dir) = make_test_file(">seq_1\nA-.*R\n\
+                                           >seq_2\nA.-*R\n");
+        let alignment = Alignment::OnDisk(path, None, false);
+        for iupac in [false, true] {
+            let counts = bitvectors_and_counts(&alignment, 5, iupac, None, None, false, false,
+                                               "-.*", &Progress::hidden())
+                .unwrap();
+            assert_eq!(counts.gap_counts, vec![0, 2, 2, 2, 0]);
+            let counts = bitvectors_and_counts(&alignment, 5, iupac, None, None, false, false, "",
+                                               &Progress::hidden())
+                .unwrap();
+            assert_eq!(counts.gap_counts, vec![0, 0, 0, 0, 0]);
+        }
+        let (path, _dir) = make_test_file(">seq_1\nA-.*\n>seq_2\nA.-*\n");
+        let counts = protein_counts(&Alignment::OnDisk(path, None, false), 4, false, "-.",
+                                    &Progress::hidden()).unwrap();
+        assert_eq!(counts.gap_counts, vec![0, 2, 2, 0]);
+    }
+
+    #[test]
+    fn test_bitvectors_and_counts_10() {
+        // With custom present characters, nothing else counts as present (here lowercase bases),
+        // and present characters which aren't bases (U for DNA, X) can't make a site variable.
+        let (path, _dir) = make_test_file(">seq_1\nAcUXN\n\
+                                           >seq_2\nAgUX-\n\
+                                           >seq_3\naCTXN\n");
+        let alignment = Alignment::load(&path, 1000, None, false).unwrap();
+        let counts = bitvectors_and_counts(&alignment, 5, false, None, Some("ACGTUX"), false,
+                                           false, "-", &Progress::hidden()).unwrap();
+        assert_eq!(counts.acgt_counts, vec![2, 1, 3, 3, 0]);
+        assert_eq!(counts.n_counts, vec![0, 0, 0, 0, 2]);
+        assert_eq!(counts.variable, bitvec![0, 0, 0, 0, 0]);
+        assert_eq!(counts.shared_base, vec![Some(b'A'), Some(b'C'), Some(b'T'), None, None]);
+
+        // As RNA, U is a base (the same as T).
+        let counts = bitvectors_and_counts(&alignment, 5, false, None, Some("ACGTUX"), false,
+                                           true, "-", &Progress::hidden()).unwrap();
+        assert_eq!(counts.acgt_counts, vec![2, 1, 3, 3, 0]);
+        assert_eq!(counts.shared_base, vec![Some(b'A'), Some(b'C'), Some(b'T'), None, None]);
+
+        // Without custom present characters, lowercase bases count and the second site varies.
+        let counts = bitvectors_and_counts(&alignment, 5, false, None, None, false, false, "-",
+                                           &Progress::hidden()).unwrap();
+        assert_eq!(counts.acgt_counts, vec![3, 3, 1, 0, 0]);
+        assert_eq!(counts.variable, bitvec![0, 1, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,57 @@
+// Copyright 2023 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Core-SNP-filter
+
+// This file is part of Core-SNP-filter. Core-SNP-filter is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any later version.
+// Core-SNP-filter is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU General Public License for more details. You should have received a copy of the GNU
+// General Public License along with Core-SNP-filter. If not, see <http://www.gnu.org/licenses/>.
+
+//! Core-SNP-filter removes sites (i.e. columns) from a whole-genome pseudo-alignment based on
+//! whether they contain variation and how conserved they are. The `coresnpfilter` binary is a thin
+//! command-line wrapper around this library, so the same filtering can be used from other Rust
+//! tools:
+//!
+//! ```no_run
+//! # fn main() -> Result<(), coresnpfilter::Error> {
+//! use coresnpfilter::{drop_columns, FilterOptions};
+//! use std::path::Path;
+//!
+//! let options = FilterOptions { core: 0.95, exclude_invariant: true, ..Default::default() };
+//! let mut filtered = Vec::new();
+//! let stats = drop_columns(Path::new("core.full.aln"), &options, &mut filtered)?;
+//! eprintln!("kept {} of {} sites", stats.output_size, stats.alignment_length);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The default options are the same as the command line's defaults, i.e. no sites are removed.
+//! Problems (e.g. unreadable or malformed input) are returned as an [`Error`], whose kind also
+//! gives the command line's exit code.
+
+mod alignment;
+mod bgzf;
+mod clustal;
+mod error;
+mod filter;
+mod misc;
+mod partitions;
+mod phylip;
+mod positions;
+mod progress;
+mod samples;
+mod stats;
+
+pub use alignment::{Alignment, AlignmentRecord};
+pub use bgzf::BgzfWriter;
+pub use error::Error;
+pub use filter::{allele_count, bitvectors_and_counts, check_arguments, drop_columns,
+                 filter_records, has_variation, is_pars_informative, protein_counts, Alphabet,
+                 BaseComposition, CharCount, ConsensusTie, FilterOptions, FilterStats,
+                 InputFormat, SiteCounts, SortOrder};
+pub use misc::{filtered_output_path, get_first_fasta_seq_length, quit_with_error};
+pub use progress::Progress;
+pub use samples::SampleSelection;
+pub use stats::{alignment_stats, write_alignment_stats, AlignmentStats};
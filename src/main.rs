@@ -9,559 +9,549 @@
 // the GNU General Public License for more details. You should have received a copy of the GNU
 // General Public License along with Core-SNP-filter. If not, see <http://www.gnu.org/licenses/>.
 
-mod misc;
-
-use bitvec::prelude::*;
-use clap::{Parser, crate_version, crate_description};
-use seq_io::fasta::{Record, RefRecord};
+use clap::{ArgAction, Args, CommandFactory, Parser, Subcommand, crate_version,
+           crate_description};
+use clap_complete::Shell;
+use log::{Level, LevelFilter};
+use coresnpfilter::{alignment_stats, drop_columns, check_arguments, filtered_output_path,
+                    get_first_fasta_seq_length, quit_with_error, write_alignment_stats, Alignment,
+                    Alphabet, BaseComposition, BgzfWriter, CharCount, Error, FilterOptions,
+                    FilterStats, InputFormat};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::ffi::OsString;
 use std::fs::File;
-use std::io::{self, Write};
+use std::io::{self, BufWriter, Write};
 use std::path::{Path, PathBuf};
 
 
+/// The --version output (-V only gives the first line), listing the compressed input formats and
+/// optional cargo features of this build, to help when someone's alignment won't open or an option
+/// (e.g. --checksums) isn't supported.
+fn long_version() -> &'static str {
+    let compressed = if cfg!(feature = "bzip2") { "gzip, bzip2" } else { "gzip" };
+    let features: Vec<&str> = [("bzip2", cfg!(feature = "bzip2")), ("sha2", cfg!(feature = "sha2"))]
+        .iter().filter(|(_, enabled)| *enabled).map(|(name, _)| *name).collect();
+    let features = if features.is_empty() { "none".to_string() } else { features.join(", ") };
+
+    // clap needs a &'static str, and this is only made once per run.
+    Box::leak(format!("v{}\ncompressed input: {}\nfeatures: {}", crate_version!(), compressed,
+                      features).into_boxed_str())
+}
+
+
 #[derive(Parser)]
 #[clap(name = "Core-SNP-filter",
        version = concat!("v", crate_version!()),
-       about = crate_description!())]
+       long_version = long_version(),
+       about = crate_description!(),
+       after_help = "If no command is given, filter is assumed, so `coresnpfilter [OPTIONS] \
+                     <INPUT>` is the same as `coresnpfilter filter [OPTIONS] <INPUT>`.",
+       arg_required_else_help = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Print a shell completion script to stdout and exit
+    #[arg(long = "completion", value_name = "SHELL", hide = true, exclusive = true)]
+    completion: Option<Shell>,
+
+    /// Print diagnostic messages to stderr: -v for the progress of each step and -vv for more
+    /// detail (RUST_LOG overrides this)
+    #[arg(short = 'v', long = "verbose", action = ArgAction::Count, global = true)]
+    verbose: u8,
+}
+
+
+#[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
+enum Command {
+    /// Filter sites from the alignment (the default)
+    Filter(FilterArgs),
+
+    /// Describe the alignment without filtering it
+    Stats(StatsArgs),
+
+    /// Write the alignment as FASTA (one line per sequence) without filtering it
+    Convert(ConvertArgs),
+}
+
+
+#[derive(Args)]
+struct FilterArgs {
+    /// Input alignment (more than one can be given if --outdir is used)
+    #[arg(required = true)]
+    input: Vec<PathBuf>,
+
+    /// Save each filtered alignment to this directory (as <name>.filtered.fasta) instead of
+    /// writing it to stdout
+    #[arg(long = "outdir")]
+    outdir: Option<PathBuf>,
+
+    /// Compress the filtered alignment with BGZF (blocked gzip, like bgzip), e.g. for samtools
+    /// faidx
+    #[arg(long = "bgzip")]
+    bgzip: bool,
+
+    #[command(flatten)]
+    options: FilterOptions,
+
+    /// Number of CPU threads to use when counting bases
+    #[arg(long = "threads", default_value = "1")]
+    threads: usize,
+
+    /// Save a summary of the run to this JSON file
+    #[arg(long = "json")]
+    json: Option<PathBuf>,
+
+    /// Don't print the summary to stderr (errors are still printed)
+    #[arg(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Print a suggested IQ-TREE command (with -fconst set to the removed invariant sites)
+    #[arg(long = "print_iqtree_cmd", requires = "exclude_invariant")]
+    print_iqtree_cmd: bool,
+}
+
+
+#[derive(Args)]
+struct StatsArgs {
     /// Input alignment
     input: PathBuf,
 
-    /// Restrict to core genome (0.0 to 1.0, default = 0.0)
-    #[arg(short = 'c', long = "core", default_value = "0.0", hide_default_value = true)]
-    core: f64,
+    /// Number of CPU threads to use when counting bases
+    #[arg(long = "threads", default_value = "1")]
+    threads: usize,
 
-    /// Exclude invariant sites
-    #[arg(short = 'e', long = "exclude_invariant")]
-    exclude_invariant: bool,
+    /// Load the alignment into memory (so it's only read once) if it is no bigger than this many
+    /// gigabytes, otherwise read it from disk for each pass (0 = always read from disk)
+    #[arg(long = "max_memory", default_value = "1.0")]
+    max_memory: f64,
+}
+
+
+#[derive(Args)]
+struct ConvertArgs {
+    /// Input alignment
+    input: PathBuf,
+
+    /// Format of the input alignment
+    #[arg(long = "input_format", value_enum, default_value = "fasta")]
+    input_format: InputFormat,
+}
 
-    /// Create a table with per-site information
-    #[arg(short = 't', long = "table")]
-    table: Option<PathBuf>,
 
-    /// Output invariant site counts (suitable for IQ-TREE -fconst) and nothing else
-    #[arg(short = 'C', long = "invariant_counts")]
-    invariant_counts: bool,
+/// Everything in the --json file: the run's statistics plus enough context to make sense of them.
+#[derive(Serialize)]
+struct JsonSummary<'a> {
+    tool: &'a str,
+    version: &'a str,
+    input: String,
+    core: f64,
+    core_count: Option<usize>,
+    exclude_invariant: bool,
+    #[serde(flatten)]
+    stats: &'a FilterStats,
 }
 
 
 fn main() {
-    let cli = Cli::parse();
-    check_arguments(cli.exclude_invariant, cli.invariant_counts, cli.core, &cli.table);
-    drop_columns(&cli.input, cli.exclude_invariant, cli.invariant_counts, cli.core, cli.table,
-                 &mut io::stdout());
+    let cli = Cli::parse_from(args_with_default_command(std::env::args_os().collect()));
+    init_logger(cli.verbose);
+    let result = match (&cli.command, cli.completion) {
+        (_, Some(shell)) => completion(shell),
+        (Some(Command::Filter(args)), None) => filter(args),
+        (Some(Command::Stats(args)), None) => stats(args),
+        (Some(Command::Convert(args)), None) => convert(args),
+        (None, None) => Cli::command().print_help().map_err(Error::from),
+    };
+    if let Err(e) = result {
+        quit_with_error(&e);
+    }
 }
 
 
-/// This is the primary function of the program. For easier testing, I factored it out of the main
-/// function and use the stdout argument to allow for capturing the output.
-fn drop_columns(filename: &Path, exclude_invariant: bool, invariant_counts: bool, core: f64,
-                table: Option<PathBuf>, stdout: &mut dyn io::Write) {
-    let alignment_length = misc::get_first_fasta_seq_length(filename);
-    let max_width = alignment_length.to_string().len();
-    let (a, c, g, t, seq_count, acgt_counts) = bitvectors_and_counts(filename, alignment_length);
-    if !invariant_counts {
-        stderr_display_1(filename, max_width, seq_count, alignment_length);
-    }
-
-    let mut keep = bitvec![1; alignment_length];
-    let (mut inv_a, mut inv_c, mut inv_g, mut inv_t, mut inv_other) = (0, 0, 0, 0, 0);
-    let mut non_core = 0;
-    let mut table_file = create_table(table);
-    for i in 0..alignment_length {
-        let variation = has_variation(a[i], c[i], g[i], t[i]);
-        let frac = acgt_counts[i] as f64 / seq_count as f64;
-        if (exclude_invariant || invariant_counts) && !variation {
-            keep.set(i, false);
-            if a[i] { inv_a += 1; }
-            else if c[i] { inv_c += 1; }
-            else if g[i] { inv_g += 1; }
-            else if t[i] { inv_t += 1; }
-            else { inv_other += 1; }
+/// Before there were commands, the only thing Core-SNP-filter did was filter, e.g.
+/// `coresnpfilter -e core.full.aln`. To keep that working, filter is inserted when the first
+/// argument isn't a command or a top-level option.
+fn args_with_default_command(mut args: Vec<OsString>) -> Vec<OsString> {
+    let not_filter = ["filter", "stats", "convert", "help", "-h", "--help", "-V", "--version",
+                      "--completion"];
+    if let Some(first) = args.get(1) {
+        if !not_filter.iter().any(|a| first == a) {
+            args.insert(1, OsString::from("filter"));
         }
-        if keep[i] && frac < core {
-            keep.set(i, false);
-            non_core += 1;
+    }
+    args
+}
+
+
+/// Warnings are always shown, while -v and -vv add info and debug messages. The messages are
+/// labelled like the existing "Warning:" lines rather than with env_logger's usual prefix.
+fn init_logger(verbose: u8) {
+    let level = match verbose {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        _ => LevelFilter::Debug,
+    };
+    env_logger::Builder::new()
+        .filter_level(level)
+        .parse_default_env()
+        .format(|buf, record| {
+            let label = match record.level() {
+                Level::Error => "Error",
+                Level::Warn => "Warning",
+                Level::Info => "Info",
+                Level::Debug => "Debug",
+                Level::Trace => "Trace",
+            };
+            writeln!(buf, "{}: {}", label, record.args())
+        })
+        .init();
+}
+
+
+fn set_threads(threads: usize) -> Result<(), Error> {
+    if threads == 0 {
+        return Err(Error::BadArguments("--threads must be at least 1".to_string()));
+    }
+    rayon::ThreadPoolBuilder::new().num_threads(threads).build_global().unwrap();
+    Ok(())
+}
+
+
+fn filter(args: &FilterArgs) -> Result<(), Error> {
+    check_arguments(&args.options)?;
+    check_batch_arguments(args)?;
+    if args.print_iqtree_cmd && args.options.alphabet == Alphabet::Protein {
+        return Err(Error::BadArguments("--print_iqtree_cmd cannot be used with --alphabet protein"
+                                       .to_string()));
+    }
+    set_threads(args.threads)?;
+    if let Some(outdir) = &args.outdir {
+        std::fs::create_dir_all(outdir)
+            .map_err(|e| Error::Io(format!("unable to create {}\n{}", outdir.display(), e)))?;
+    }
+    let mut all_stats = Vec::with_capacity(args.input.len());
+    for input in &args.input {
+        let stats = filter_one(input, args)?;
+        if let Some(json) = &args.json {
+            write_json(json, input, args, &stats)?;
         }
-        write_table_line(table_file.as_mut(), i, a[i], c[i], g[i], t[i],  acgt_counts[i],
-                         variation, frac, keep[i]);
+        all_stats.push(stats);
     }
-    let output_size = keep.iter().filter(|n| *n == true).count();
-    let inv_total = inv_a + inv_c + inv_g + inv_t + inv_other;
-    let removed_total = inv_total + non_core;
-    assert!(alignment_length == output_size + removed_total);
-    if !invariant_counts {
-        stderr_display_2(max_width, output_size, removed_total, non_core, inv_total,
-                         inv_a, inv_c, inv_g, inv_t, inv_other);
+    if all_stats.len() > 1 && !args.quiet {
+        stderr_display_batch(&all_stats);
     }
+    Ok(())
+}
+
+
+/// Filters one input alignment, writing the result to stdout or (with --outdir) to its own file.
+fn filter_one(input: &Path, args: &FilterArgs) -> Result<FilterStats, Error> {
+    let output = args.outdir.as_ref().map(|outdir| output_path(outdir, input, args.bgzip));
+    let display = !args.options.invariant_counts && !args.quiet;
 
-    if invariant_counts {
-        writeln!(stdout, "{},{},{},{}", inv_a, inv_c, inv_g, inv_t).unwrap();
+    // The first sequence's length is quick to get, so it's shown before the slow counting pass to
+    // let users know the file parsed. Otherwise (stdin, other formats or an empty first sequence
+    // with --skip_empty) it's shown afterwards.
+    let early_length = if display && args.options.input_format == InputFormat::Fasta &&
+                          input != Path::new("-") {
+        get_first_fasta_seq_length(input).ok()
     } else {
-        let mut fasta_reader = misc::open_fasta_file(filename);
-        while let Some(record) = fasta_reader.next() {
-            let record = record.expect("Error reading record");
-            output_sequence(&record, &keep, output_size, stdout);
+        None
+    };
+    if let Some(length) = early_length {
+        stderr_display_1(input, length);
+    }
+    let stats = match &output {
+        Some(path) => {
+            let file = File::create(path)
+                .map_err(|e| Error::Io(format!("unable to create {}\n{}", path.display(), e)))?;
+            write_filtered(input, args, file)?
         }
-    }
+        None => write_filtered(input, args, io::stdout().lock())?,
+    };
+    if display {
+        if early_length.is_none() {
+            stderr_display_1(input, stats.alignment_length);
+        }
+        stderr_display_2(&stats, &args.options);
+    }
+    if args.print_iqtree_cmd {
+        // Without --outdir, the filtered alignment goes to stdout, so its filename isn't known.
+        let alignment = output.map_or("<filtered_alignment>".to_string(),
+                                      |p| p.display().to_string());
+        eprintln!("IQ-TREE command: iqtree2 -s {} -fconst {},{},{},{}", alignment,
+                  stats.invariant_a_removed, stats.invariant_c_removed,
+                  stats.invariant_g_removed, stats.invariant_t_removed);
+        eprintln!();
+    }
+    Ok(stats)
 }
 
 
-fn check_arguments(exclude_invariant: bool, invariant_counts: bool, core: f64,
-                   table: &Option<PathBuf>) {
-    let non_count_option = exclude_invariant || core != 0.0 || table.is_some();
-    if invariant_counts && non_count_option {
-        misc::quit_with_error("--invariant_counts cannot be used with other options");
-    }
-    if !(0.0..=1.0).contains(&core) {
-        misc::quit_with_error("--core must be between 0 and 1 (inclusive)");
+/// Filters the input alignment into out, which is buffered here since writing to stdout directly is
+/// slow (it's line-buffered). With --bgzip, the BGZF writer's blocks are the buffer.
+fn write_filtered<W: Write>(input: &Path, args: &FilterArgs, out: W) -> Result<FilterStats, Error> {
+    if args.bgzip {
+        let mut out = BgzfWriter::new(out);
+        let stats = drop_columns(input, &args.options, &mut out)?;
+        out.finish()?;
+        Ok(stats)
+    } else {
+        let mut out = BufWriter::new(out);
+        let stats = drop_columns(input, &args.options, &mut out)?;
+        out.flush()?;
+        Ok(stats)
     }
 }
 
 
-fn output_sequence(record: &RefRecord, keep: &BitVec, output_size: usize,
-                   stdout: &mut dyn io::Write) {
-    let header = get_fasta_header(record);
-    let seq = remove_columns(record, keep, output_size);
-    writeln!(stdout, ">{}\n{}", header, seq).unwrap();
+/// The --outdir file for an input alignment, which gets a .gz extension with --bgzip.
+fn output_path(outdir: &Path, input: &Path, bgzip: bool) -> PathBuf {
+    let path = filtered_output_path(outdir, input);
+    if bgzip {
+        let mut path = path.into_os_string();
+        path.push(".gz");
+        path.into()
+    } else {
+        path
+    }
 }
 
 
-fn remove_columns(record: &RefRecord, keep: &BitVec, output_size: usize) -> String {
-    let full_seq = record.full_seq();
-    let mut kept_seq = String::with_capacity(output_size);
-    for i in 0..full_seq.len() {
-        if keep[i] {
-            kept_seq.push(full_seq[i] as char)
+/// Multiple inputs each need their own output file, so they require --outdir and can't be used
+/// with options that save a single extra file. -C and --count_only don't make sense with --outdir
+/// because neither writes an alignment.
+fn check_batch_arguments(args: &FilterArgs) -> Result<(), Error> {
+    let bad = |message: &str| Err(Error::BadArguments(message.to_string()));
+    if args.outdir.is_some() && (args.options.invariant_counts || args.options.count_only) {
+        return bad("--outdir cannot be used with --invariant_counts or --count_only");
+    }
+    if args.bgzip && (args.options.invariant_counts || args.options.count_only) {
+        return bad("--bgzip cannot be used with --invariant_counts or --count_only");
+    }
+    if args.input.len() < 2 {
+        return Ok(());
+    }
+    if args.outdir.is_none() {
+        return bad("multiple input alignments require --outdir");
+    }
+    let options = &args.options;
+    let single_file = [(options.table.is_some(), "--table"),
+                       (options.coord_map.is_some(), "--coord_map"),
+                       (options.partition_out.is_some(), "--partition_out"),
+                       (options.mask_track.is_some(), "--mask_track"),
+                       (options.consensus.is_some(), "--consensus"),
+                       (options.sfs.is_some(), "--sfs"),
+                       (options.vcf.is_some(), "--vcf"),
+                       (options.raxml_info.is_some(), "--raxml_info"),
+                       (options.dist_matrix.is_some(), "--dist_matrix"),
+                       (options.dedup_map.is_some(), "--dedup_map"),
+                       (options.removed_output.is_some(), "--removed_output"),
+                       (options.missing_report.is_some(), "--missing_report"),
+                       (options.snp_counts.is_some(), "--snp_counts"),
+                       (options.checksums.is_some(), "--checksums"),
+                       (options.names.is_some(), "--names"),
+                       (args.json.is_some(), "--json")];
+    if let Some((_, name)) = single_file.iter().find(|(used, _)| *used) {
+        return bad(&format!("{} cannot be used with multiple input alignments", name));
+    }
+    let mut outputs = HashSet::new();
+    for input in &args.input {
+        let output = output_path(args.outdir.as_ref().unwrap(), input, args.bgzip);
+        if !outputs.insert(output.clone()) {
+            return bad(&format!("more than one input alignment would be saved to {}",
+                                output.display()));
         }
     }
-    assert!(kept_seq.len() == output_size);
-    kept_seq
+    Ok(())
 }
 
 
-fn get_fasta_header(record: &RefRecord) -> String {
-    let mut header = String::new();
-    header += record.id().unwrap();
-    if let Some(x) = record.desc() {
-        header += &format!(" {}", x.unwrap());
+fn stats(args: &StatsArgs) -> Result<(), Error> {
+    if args.max_memory < 0.0 {
+        return Err(Error::BadArguments("--max_memory cannot be negative".to_string()));
     }
-    header
+    set_threads(args.threads)?;
+    let stats = alignment_stats(&args.input, (args.max_memory * 1e9) as usize)?;
+    let mut stdout = BufWriter::new(io::stdout().lock());
+    write_alignment_stats(&stats, &mut stdout)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+
+/// Writes the alignment back out as FASTA, in a single pass. FASTA input is read from disk as it
+/// goes, since nothing else needs it (though stdin still has to be loaded into memory).
+fn convert(args: &ConvertArgs) -> Result<(), Error> {
+    let max_memory = if args.input == Path::new("-") { usize::MAX } else { 0 };
+    let alignment = Alignment::load_format(&args.input, args.input_format, max_memory, None,
+                                           false)?;
+    let mut stdout = BufWriter::new(io::stdout().lock());
+    alignment.for_each_record(|header, seq| {
+        writeln!(stdout, ">{}", header)?;
+        stdout.write_all(seq)?;
+        writeln!(stdout)?;
+        Ok(())
+    })?;
+    stdout.flush()?;
+    Ok(())
+}
+
+
+fn completion(shell: Shell) -> Result<(), Error> {
+    let mut stdout = BufWriter::new(io::stdout().lock());
+    clap_complete::generate(shell, &mut Cli::command(), "coresnpfilter", &mut stdout);
+    stdout.flush()?;
+    Ok(())
 }
 
 
-fn has_variation(a: bool, c: bool, g: bool, t: bool) -> bool {
-    let total = a as i32 + c as i32 + g as i32 + t as i32;
-    total > 1
+fn write_json(filename: &Path, input: &Path, args: &FilterArgs, stats: &FilterStats)
+        -> Result<(), Error> {
+    let summary = JsonSummary {
+        tool: "Core-SNP-filter",
+        version: crate_version!(),
+        input: input.display().to_string(),
+        core: args.options.core,
+        core_count: args.options.core_count,
+        exclude_invariant: args.options.exclude_invariant,
+        stats,
+    };
+    let file = File::create(filename)
+        .map_err(|e| Error::Io(format!("unable to create {}\n{}", filename.display(), e)))?;
+    serde_json::to_writer_pretty(file, &summary)
+        .map_err(|e| Error::Io(format!("unable to write {}\n{}", filename.display(), e)))
 }
 
 
-fn stderr_display_1(filename: &Path, max_width: usize, seq_count: usize, alignment_length: usize) {
+fn stderr_display_1(filename: &Path, alignment_length: usize) {
+    let max_width = alignment_length.to_string().len();
     eprintln!();
     eprintln!("Core-SNP-filter");
     eprintln!("{}", "─".repeat(max_width+37));
     eprintln!("input file: {:>w$}", filename.display(), w = max_width+25);
-    eprintln!("number of sequences:                 {:>w$}", seq_count, w = max_width);
     eprintln!("input sequence length:               {:>w$}", alignment_length, w = max_width);
 }
 
 
-fn stderr_display_2(max_width: usize, output_size: usize, removed_total: usize, non_core: usize,
-                    inv_total: usize, inv_a: usize, inv_c: usize, inv_g: usize, inv_t: usize,
-                    inv_other: usize) {
-    eprintln!("├ output sequence length:            {:>w$}", output_size, w = max_width);
-    eprintln!("└ total sites removed:               {:>w$}", removed_total, w = max_width);
-    eprintln!("  ├ non-core sites removed:          {:>w$}", non_core, w = max_width);
-    eprintln!("  └ invariant sites removed:         {:>w$}", inv_total, w = max_width);
-    eprintln!("    ├ invariant-A sites removed:     {:>w$}", inv_a, w = max_width);
-    eprintln!("    ├ invariant-C sites removed:     {:>w$}", inv_c, w = max_width);
-    eprintln!("    ├ invariant-G sites removed:     {:>w$}", inv_g, w = max_width);
-    eprintln!("    ├ invariant-T sites removed:     {:>w$}", inv_t, w = max_width);
-    eprintln!("    └ other invariant sites removed: {:>w$}", inv_other, w = max_width);
-    eprintln!();
-}
-
-
-fn create_table(table: Option<PathBuf>) -> Option<File> {
-    if let Some(table_path) = table {
-        let mut file = File::create(&table_path).expect("Failed to create table file");
-        writeln!(file, "pos\ta\tc\tg\tt\tcount\tfrac\tvar\tkeep")
-            .expect("Failed to write to table file");
-        Some(file)
+fn stderr_display_2(stats: &FilterStats, options: &FilterOptions) {
+    let w = stats.alignment_length.to_string().len();
+    if options.include_samples.is_some() || options.exclude_samples.is_some() ||
+       options.include_regex.is_some() || options.exclude_regex.is_some() ||
+       options.subsample.is_some() {
+        eprintln!("number of selected sequences:        {:>w$}", stats.seq_count);
     } else {
-        None
+        eprintln!("number of sequences:                 {:>w$}", stats.seq_count);
+    }
+    eprintln!("parsimony-informative sites:         {:>w$}", stats.pars_informative);
+    if options.dedup {
+        eprintln!("├ duplicate sequences collapsed:     {:>w$}", stats.dedup_collapsed);
+    }
+    eprintln!("├ output sequence length:            {:>w$}", stats.output_size);
+    if options.keep_positions.is_some() {
+        eprintln!("│ └ force-kept sites:               {:>w$}", stats.force_kept);
+    }
+    eprintln!("└ total sites removed:               {:>w$}", stats.removed_total);
+    if options.region.is_some() {
+        eprintln!("  ├ sites outside region:            {:>w$}", stats.outside_region);
+    }
+    if options.keep_from_vcf.is_some() {
+        eprintln!("  ├ sites not in VCF:                {:>w$}", stats.not_in_vcf);
+    }
+    if options.mask_bed.is_some() {
+        eprintln!("  ├ masked sites removed:            {:>w$}", stats.masked_removed);
+    }
+    if options.mask_from_vcf.is_some() {
+        eprintln!("  ├ VCF-masked sites removed:        {:>w$}", stats.vcf_masked_removed);
+    }
+    if options.trim_ends {
+        eprintln!("  ├ end sites trimmed:               {:>w$}", stats.end_trimmed);
+    }
+    if options.invariant_only {
+        eprintln!("  ├ variable sites removed:          {:>w$}", stats.variable_removed);
+    }
+    eprintln!("  ├ non-core sites removed:          {:>w$}", stats.non_core_removed);
+    if options.max_gap_frac.is_some() {
+        eprintln!("  ├ gappy sites removed:             {:>w$}", stats.gappy_removed);
+    }
+    if options.max_ambiguous_frac.is_some() {
+        eprintln!("  ├ ambiguous sites removed:         {:>w$}", stats.ambiguous_removed);
+    }
+    if options.drop_empty_columns {
+        eprintln!("  ├ empty sites removed:             {:>w$}", stats.empty_removed);
     }
+    if options.pars_informative {
+        eprintln!("  ├ uninformative sites removed:     {:>w$}", stats.uninformative_removed);
+    }
+    eprintln!("  └ invariant sites removed:         {:>w$}", stats.invariant_removed);
+    if options.alphabet == Alphabet::Protein {
+        eprintln!("    ├ single-residue sites removed:  {:>w$}",
+                  stats.invariant_residue_removed);
+    } else {
+        eprintln!("    ├ invariant-A sites removed:     {:>w$}", stats.invariant_a_removed);
+        eprintln!("    ├ invariant-C sites removed:     {:>w$}", stats.invariant_c_removed);
+        eprintln!("    ├ invariant-G sites removed:     {:>w$}", stats.invariant_g_removed);
+        eprintln!("    ├ invariant-T sites removed:     {:>w$}", stats.invariant_t_removed);
+    }
+    eprintln!("    └ other invariant sites removed: {:>w$}", stats.invariant_other_removed);
+    eprintln!("      ├ gap sites:                   {:>w$}", stats.invariant_gap_removed);
+    if options.alphabet == Alphabet::Protein {
+        eprintln!("      ├ X sites:                     {:>w$}", stats.invariant_n_removed);
+    } else {
+        eprintln!("      ├ N sites:                     {:>w$}", stats.invariant_n_removed);
+    }
+    eprintln!("      └ ambiguous sites:             {:>w$}", stats.invariant_ambiguous_removed);
+    if let Some(composition) = &stats.base_composition {
+        stderr_display_composition(composition, w);
+    }
+    if let Some(char_counts) = &stats.char_counts {
+        stderr_display_char_counts(char_counts, w);
+    }
+    if let Some(hash) = &stats.alignment_hash {
+        eprintln!("filtered alignment SHA-256: {}", hash);
+    }
+    eprintln!();
 }
 
 
-fn write_table_line(file: Option<&mut File>, i: usize, a: bool, c: bool, g: bool, t: bool,
-                    acgt_counts: usize, variation: bool, frac: f64, keep: bool) {
-    if let Some(f) = file {
-        writeln!(f, "{}\t{}\t{}\t{}\t{}\t{}\t{:.4}\t{}\t{}", i+1, a as i32, c as i32, g as i32,
-                 t as i32, acgt_counts, frac, variation as i32, keep as i32)
-            .expect("Failed to write to table file");
+fn stderr_display_composition(composition: &BaseComposition, w: usize) {
+    let counts = [("A", composition.a), ("C", composition.c), ("G", composition.g),
+                  ("T", composition.t), ("N", composition.n), ("gap", composition.gap),
+                  ("other", composition.other)];
+    let total: usize = counts.iter().map(|(_, count)| count).sum();
+    let w = w.max(total.to_string().len());
+    eprintln!("base composition of kept sites:");
+    for (i, (label, count)) in counts.iter().enumerate() {
+        let branch = if i + 1 < counts.len() { '├' } else { '└' };
+        eprintln!("{:<37}{:>w$}", format!("{} {}:", branch, label), count);
     }
 }
 
 
-/// Returns:
-/// * a bitvector for each of the four canonical bases for each position of the alignment
-/// * the number of sequences in the alignment
-/// * how many of the sequences have a canonical base for each position of the alignment
-fn bitvectors_and_counts(filename: &Path, alignment_length: usize)
-        -> (BitVec, BitVec, BitVec, BitVec, usize, Vec<usize>){
-    let mut a = bitvec![0; alignment_length];
-    let mut c = bitvec![0; alignment_length];
-    let mut g = bitvec![0; alignment_length];
-    let mut t = bitvec![0; alignment_length];
-    let mut seq_count = 0;
-    let mut acgt_counts = vec![0; alignment_length];
-
-    let mut fasta_reader = misc::open_fasta_file(filename);
-    while let Some(record) = fasta_reader.next() {
-        let record = record.expect("Error reading record");
-        let seq = record.full_seq();
-        if alignment_length != seq.len() {
-            misc::quit_with_error("all sequences must be equal length");
-        }
-        seq_count += 1;
-        for i in 0..alignment_length {
-            match seq[i] {
-                65 | 97 =>  {a.set(i, true); acgt_counts[i] += 1;},
-                67 | 99 =>  {c.set(i, true); acgt_counts[i] += 1;},
-                71 | 103 => {g.set(i, true); acgt_counts[i] += 1;},
-                84 | 116 => {t.set(i, true); acgt_counts[i] += 1;},
-                _ => (),
-            }
-        }
+fn stderr_display_char_counts(char_counts: &[CharCount], w: usize) {
+    let total: usize = char_counts.iter().map(|c| c.count).sum();
+    let w = w.max(total.to_string().len());
+    eprintln!("characters in alignment:");
+    for (i, c) in char_counts.iter().enumerate() {
+        let branch = if i + 1 < char_counts.len() { '├' } else { '└' };
+        let flag = if c.expected { "" } else { " (unexpected)" };
+        eprintln!("{:<37}{:>w$}{}", format!("{} {}:", branch, c.char), c.count, flag);
     }
-    (a, c, g, t, seq_count, acgt_counts)
 }
 
 
-#[cfg(test)]
-mod tests {
-    use std::fs::File;
-    use std::io::Write;
-    use std::str::from_utf8;
-    use tempfile::{TempDir,tempdir};
-    use super::*;
-
-    fn make_test_file(contents: &str) -> (PathBuf, TempDir) {
-        let dir = tempdir().unwrap();
-        let file_path = dir.path().join("test.fasta");
-        let mut file = File::create(&file_path).unwrap();
-        write!(file, "{}", contents).unwrap();
-        (file_path, dir)
-    }
-
-    #[test]
-    fn test_check_arguments_1() {
-        // Valid arguments.
-        check_arguments(true, false, 0.0, &None);
-        check_arguments(true, false, 0.5, &None);
-        check_arguments(true, false, 1.0, &None);
-        check_arguments(false, true, 0.0, &None);
-    }
-
-    #[test]
-    #[should_panic]
-    fn test_check_arguments_2() {
-        // Invalid arguments.
-        check_arguments(true, false, -0.1, &None);
-    }
-
-    #[test]
-    #[should_panic]
-    fn test_check_arguments_3() {
-        // Invalid arguments.
-        check_arguments(true, false, 1.1, &None);
-    }
-
-    #[test]
-    #[should_panic]
-    fn test_check_arguments_4() {
-        // Invalid arguments.
-        check_arguments(true, true, 0.0, &None);
-    }
-
-    #[test]
-    #[should_panic]
-    fn test_check_arguments_5() {
-        // Invalid arguments.
-        check_arguments(false, true, 0.5, &None);
-    }
-
-    #[test]
-    #[should_panic]
-    fn test_check_arguments_6() {
-        // Invalid arguments.
-        check_arguments(false, true, 0.0, &Some("table.tsv".into()));
-    }
-
-    #[test]
-    fn test_has_variation() {
-        assert_eq!(has_variation(false, false, false, false), false);
-        assert_eq!(has_variation(true, false, false, false), false);
-        assert_eq!(has_variation(false, true, false, false), false);
-        assert_eq!(has_variation(false, false, true, false), false);
-        assert_eq!(has_variation(false, false, false, true), false);
-        assert_eq!(has_variation(true, true, false, false), true);
-        assert_eq!(has_variation(false, false, true, true), true);
-        assert_eq!(has_variation(true, false, true, false), true);
-        assert_eq!(has_variation(false, true, false, true), true);
-        assert_eq!(has_variation(false, true, true, true), true);
-        assert_eq!(has_variation(true, false, true, true), true);
-        assert_eq!(has_variation(true, true, false, true), true);
-        assert_eq!(has_variation(true, true, true, false), true);
-        assert_eq!(has_variation(true, true, true, true), true);
-    }
-
-    #[test]
-    fn test_bitvectors_and_counts_1() {
-        let (path, _dir) = make_test_file(">seq_1\nACGAT\n\
-                                           >seq_2\nGGT-A\n");
-        let (a, c, g, t, seq_count, acgt_counts) = bitvectors_and_counts(&path, 5);
-        assert_eq!(a, bitvec![1, 0, 0, 1, 1]);
-        assert_eq!(c, bitvec![0, 1, 0, 0, 0]);
-        assert_eq!(g, bitvec![1, 1, 1, 0, 0]);
-        assert_eq!(t, bitvec![0, 0, 1, 0, 1]);
-        assert_eq!(seq_count, 2);
-        assert_eq!(acgt_counts, vec![2, 2, 2, 1, 2]);
-    }
-
-    #[test]
-    fn test_bitvectors_and_counts_2() {
-        let (path, _dir) = make_test_file(">seq_1\naacgacta\n\
-                                           >seq_2\nAGCNACGA\n\
-                                           >seq_3\nacgGCTca\n");
-        let (a, c, g, t, seq_count, acgt_counts) = bitvectors_and_counts(&path, 8);
-        assert_eq!(a, bitvec![1, 1, 0, 0, 1, 0, 0, 1]);
-        assert_eq!(c, bitvec![0, 1, 1, 0, 1, 1, 1, 0]);
-        assert_eq!(g, bitvec![0, 1, 1, 1, 0, 0, 1, 0]);
-        assert_eq!(t, bitvec![0, 0, 0, 0, 0, 1, 1, 0]);
-        assert_eq!(seq_count, 3);
-        assert_eq!(acgt_counts, vec![3, 3, 3, 2, 3, 3, 3, 3]);
-    }
-
-    #[test]
-    fn test_drop_columns_1() {
-        // No filtering - input is the same as the output.
-        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
-                                                 >seq_2\nACCATTAG\n\
-                                                 >seq_3\nACGATCAG\n");
-        let mut stdout = Vec::new();
-        drop_columns(&path, false, false, 0.0, None, &mut stdout);
-        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nACGATCAG\n\
-                                                 >seq_2\nACCATTAG\n\
-                                                 >seq_3\nACGATCAG\n");
-    }
-
-    #[test]
-    fn test_drop_columns_2() {
-        // Dropping invariant sites.
-        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
-                                                 >seq_2\nACCATTAG\n\
-                                                 >seq_3\nACGATCAG\n");
-        let mut stdout = Vec::new();
-        drop_columns(&path, true, false, 0.0, None, &mut stdout);
-        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nGC\n\
-                                                 >seq_2\nCT\n\
-                                                 >seq_3\nGC\n");
-    }
-
-    #[test]
-    fn test_drop_columns_3() {
-        // At 60% core, 2 out of 3 sequences is enough.
-        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
-                                                 >seq_2\nAC----CG\n\
-                                                 >seq_3\nAGGATCAG\n");
-        let mut stdout = Vec::new();
-        drop_columns(&path, false, false, 0.6, None, &mut stdout);
-        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nACGATCAG\n\
-                                                 >seq_2\nAC----CG\n\
-                                                 >seq_3\nAGGATCAG\n");
-    }
-
-    #[test]
-    fn test_drop_columns_4() {
-        // At 70% core, 2 out of 3 sequences is not enough.
-        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
-                                                 >seq_2\nAC----CG\n\
-                                                 >seq_3\nAGGATCAG\n");
-        let mut stdout = Vec::new();
-        drop_columns(&path, false, false, 0.7, None, &mut stdout);
-        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nACAG\n\
-                                                 >seq_2\nACCG\n\
-                                                 >seq_3\nAGAG\n");
-    }
-
-    #[test]
-    fn test_drop_columns_5() {
-        // Same as previous but dropping invariant sites and with per-site table.
-        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
-                                                 >seq_2\nAC----CG\n\
-                                                 >seq_3\nAGGATCAG\n");
-        let mut stdout = Vec::new();
-        let dir = tempdir().unwrap();
-        let table_path = dir.path().join("table.tsv");
-        drop_columns(&path, true, false, 0.7, Some(table_path), &mut stdout);
-        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nCA\n\
-                                                 >seq_2\nCC\n\
-                                                 >seq_3\nGA\n");
-    }
-
-    #[test]
-    fn test_drop_columns_6() {
-        // Same as previous but with some descriptions in the FASTA headers.
-        let (path, _dir) =       make_test_file(">seq_1 info\nACGATCAG\n\
-                                                 >seq_2\nAC----CG\n\
-                                                 >seq_3 lots of stuff\nAGGATCAG\n");
-        let mut stdout = Vec::new();
-        let dir = tempdir().unwrap();
-        let table_path = dir.path().join("table.tsv");
-        drop_columns(&path, true, false, 0.7, Some(table_path), &mut stdout);
-        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1 info\nCA\n\
-                                                 >seq_2\nCC\n\
-                                                 >seq_3 lots of stuff\nGA\n");
-    }
-
-    #[test]
-    #[should_panic]
-    fn test_drop_columns_7() {
-        // Invalid input with different sequence lengths.
-        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
-                                                 >seq_2\nAC----CGA\n\
-                                                 >seq_3\nAGGATCAG\n");
-        let mut stdout = Vec::new();
-        let dir = tempdir().unwrap();
-        let table_path = dir.path().join("table.tsv");
-        drop_columns(&path, true, false, 0.7, Some(table_path), &mut stdout);
-    }
-
-    #[test]
-    fn test_drop_columns_8() {
-        // Every column is dropped.
-        let (path, _dir) =       make_test_file(">seq_1\nACGATCA-\n\
-                                                 >seq_2\nAC----AC\n\
-                                                 >seq_3\nACGATCAG\n");
-        let mut stdout = Vec::new();
-        drop_columns(&path, true, false, 0.7, None, &mut stdout);
-        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\n\n\
-                                                 >seq_2\n\n\
-                                                 >seq_3\n\n");
-    }
-
-    #[test]
-    fn test_drop_columns_9() {
-        // Using a mixture of uppercase and lowercase - no columns dropped.
-        let (path, _dir) =       make_test_file(">seq_1\nACGAtCaGcAaT\n\
-                                                 >seq_2\nAcGaGCaGcAcT\n\
-                                                 >seq_3\nACGatTAgCaCT\n");
-        let mut stdout = Vec::new();
-        drop_columns(&path, false, false, 0.5, None, &mut stdout);
-        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nACGAtCaGcAaT\n\
-                                                 >seq_2\nAcGaGCaGcAcT\n\
-                                                 >seq_3\nACGatTAgCaCT\n");
-    }
-
-    #[test]
-    fn test_drop_columns_10() {
-        // Using a mixture of uppercase and lowercase - invariant columns dropped.
-        let (path, _dir) =       make_test_file(">seq_1\nACGAtCaGcAaT\n\
-                                                 >seq_2\nAcGaGCaGcAcT\n\
-                                                 >seq_3\nACGatTAgCaCT\n");
-        let mut stdout = Vec::new();
-        drop_columns(&path, true, false, 0.5, None, &mut stdout);
-        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\ntCa\n\
-                                                 >seq_2\nGCc\n\
-                                                 >seq_3\ntTC\n");
-    }
-
-    #[test]
-    fn test_drop_columns_11() {
-        // Using a mixture of uppercase and lowercase - non-core columns dropped.
-        let (path, _dir) =       make_test_file(">seq_1\nACG--CaGcAaT\n\
-                                                 >seq_2\nAcGaGCa--AcT\n\
-                                                 >seq_3\nACGa----CaCT\n");
-        let mut stdout = Vec::new();
-        drop_columns(&path, false, false, 0.5, None, &mut stdout);
-        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nACG-CacAaT\n\
-                                                 >seq_2\nAcGaCa-AcT\n\
-                                                 >seq_3\nACGa--CaCT\n");
-    }
-
-    #[test]
-    fn test_drop_columns_12() {
-        // Using a mixture of uppercase and lowercase - invariant and non-core columns dropped.
-        let (path, _dir) =       make_test_file(">seq_1\nACG--CaGcAaT\n\
-                                                 >seq_2\nAcGaGCa--AcT\n\
-                                                 >seq_3\nACGa----CaCT\n");
-        let mut stdout = Vec::new();
-        drop_columns(&path, true, false, 0.5, None, &mut stdout);
-        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\na\n\
-                                                 >seq_2\nc\n\
-                                                 >seq_3\nC\n");
-    }
-
-    #[test]
-    fn test_drop_columns_13() {
-        // Testing an input with line breaks in the FASTA sequences.
-        let (path, _dir) = make_test_file(">seq_1\nACG--\nCaGcA\naT\n\
-                                                 >seq_2\nAcGaG\nCa--A\ncT\n\
-                                                 >seq_3\nACGa-\n---Ca\nCT\n");
-        let mut stdout = Vec::new();
-        drop_columns(&path, false, false, 0.5, None, &mut stdout);
-        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nACG-CacAaT\n\
-                                                 >seq_2\nAcGaCa-AcT\n\
-                                                 >seq_3\nACGa--CaCT\n");
-    }
-
-    #[test]
-    fn test_drop_columns_14() {
-        // Testing lots of non-base characters.
-        let (path, _dir) =       make_test_file(">seq_1\nAC---CGG\n\
-                                                 >seq_2\nCCCNNNNG\n\
-                                                 >seq_3\nACXQVPAG\n");
-        let mut stdout = Vec::new();
-        drop_columns(&path, true, false, 0.0, None, &mut stdout);
-        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nAG\n\
-                                                 >seq_2\nCN\n\
-                                                 >seq_3\nAA\n");
-    }
-
-    #[test]
-    fn test_drop_columns_15() {
-        // Testing lots of non-base characters.
-        let (path, _dir) =       make_test_file(">seq_1\nAC---CGG\n\
-                                                 >seq_2\nCCCNNNNG\n\
-                                                 >seq_3\nACXQVPAG\n");
-        let mut stdout = Vec::new();
-        drop_columns(&path, true, false, 1.0, None, &mut stdout);
-        assert_eq!(from_utf8(&stdout).unwrap(), ">seq_1\nA\n\
-                                                 >seq_2\nC\n\
-                                                 >seq_3\nA\n");
-    }
-
-    #[test]
-    fn test_drop_columns_16() {
-        // Testing invariant counts.
-        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
-                                                 >seq_2\nACCATTAG\n\
-                                                 >seq_3\nACGATCAG\n");
-        let mut stdout = Vec::new();
-        drop_columns(&path, true, true, 0.0, None, &mut stdout);
-        assert_eq!(from_utf8(&stdout).unwrap(), "3,1,1,1\n");
-    }
-
-    #[test]
-    fn test_drop_columns_17() {
-        // Same as above but with different options (which makes no difference).
-        let (path, _dir) =       make_test_file(">seq_1\nACGATCAG\n\
-                                                 >seq_2\nACCATTAG\n\
-                                                 >seq_3\nACGATCAG\n");
-        let mut stdout = Vec::new();
-        drop_columns(&path, false, true, 0.95, None, &mut stdout);
-        assert_eq!(from_utf8(&stdout).unwrap(), "3,1,1,1\n");
-    }
+/// After a batch of alignments (each with its own summary), shows their totals.
+fn stderr_display_batch(all_stats: &[FilterStats]) {
+    let input_sites: usize = all_stats.iter().map(|s| s.alignment_length).sum();
+    let output_sites: usize = all_stats.iter().map(|s| s.output_size).sum();
+    let w = input_sites.to_string().len();
+    eprintln!("All input files");
+    eprintln!("{}", "─".repeat(w+37));
+    eprintln!("number of input files:               {:>w$}", all_stats.len());
+    eprintln!("total input sequence length:         {:>w$}", input_sites);
+    eprintln!("total output sequence length:        {:>w$}", output_sites);
+    eprintln!();
 }
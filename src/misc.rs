@@ -9,93 +9,356 @@
 // the GNU General Public License for more details. You should have received a copy of the GNU
 // General Public License along with Core-SNP-filter. If not, see <http://www.gnu.org/licenses/>.
 
+use std::borrow::Cow;
 use std::fs::{File, metadata};
-use std::io::{prelude::*, BufReader};
-use std::path::Path;
+use std::io::{prelude::*, BufReader, Cursor};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use seq_io::fasta::Reader;
 use flate2::read::GzDecoder;
+use memmap2::Mmap;
 
+use crate::error::Error;
 
-#[cfg(not(test))]
-/// For friendly error messages, this function normally just prints the error and quits. But when
-/// running unit tests, it instead panics so the test can catch it.
-pub fn quit_with_error(text: &str) -> ! {
+
+/// For friendly error messages, errors are printed without a backtrace and the program quits with
+/// the error's exit code. This is the only place errors are shown to the user.
+pub fn quit_with_error(error: &Error) -> ! {
     eprintln!();
-    eprintln!("Error: {}", text);
-    std::process::exit(1);
+    eprintln!("Error: {}", error);
+    std::process::exit(error.exit_code());
 }
-#[cfg(test)]
-pub fn quit_with_error(text: &str) -> ! {
-    panic!("{}", text);
+
+
+/// Warnings go through the log crate, so the binary shows them at its default verbosity and
+/// library users can route them wherever they like.
+pub fn print_warning(text: &str) {
+    log::warn!("{}", text);
+}
+
+
+/// Returns the lines of a text file.
+pub fn read_lines(filename: &Path) -> Result<Vec<String>, Error> {
+    let file = open_file(filename)?;
+    BufReader::new(file).lines().collect::<Result<_, _>>()
+        .map_err(|e| Error::Io(format!("unable to read {}\n{}", filename.display(), e)))
+}
+
+
+/// Opens a file for reading, with the filename in the error message if it can't be opened.
+pub fn open_file(filename: &Path) -> Result<File, Error> {
+    File::open(filename)
+        .map_err(|e| Error::Io(format!("unable to open {}\n{}", filename.display(), e)))
 }
 
 
-pub fn check_if_file_is_empty(filename: &Path) {
-    if let Ok(metadata) = metadata(filename) {
-        if metadata.len() == 0 {
-            quit_with_error(&format!("{} is empty", filename.display()));
+/// Creates a file for writing, with the filename in the error message if it can't be created.
+pub fn create_file(filename: &Path) -> Result<File, Error> {
+    File::create(filename)
+        .map_err(|e| Error::Io(format!("unable to create {}\n{}", filename.display(), e)))
+}
+
+
+pub fn check_if_file_is_empty(filename: &Path) -> Result<(), Error> {
+    match metadata(filename) {
+        Ok(metadata) if metadata.len() == 0 => {
+            Err(Error::EmptyInput(format!("empty alignment: {} is empty", filename.display())))
         }
-    } else {
-        quit_with_error(&format!("could not access {}", filename.display()));
+        Ok(_) => Ok(()),
+        Err(_) => Err(Error::Io(format!("could not access {}", filename.display()))),
     }
 }
 
 
-pub fn check_if_file_exists(filename: &Path) {
+pub fn check_if_file_exists(filename: &Path) -> Result<(), Error> {
     if !Path::new(filename).exists() {
-        quit_with_error(&format!("{} does not exist", filename.display()));
+        return Err(Error::Io(format!("{} does not exist", filename.display())));
+    }
+    Ok(())
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompressionFormat {
+    Uncompressed,
+    Gzip,
+    Bzip2,
+}
+
+
+/// Works out how a file is compressed from its first bytes (the gzip and bzip2 magic numbers).
+/// A file too small to hold them isn't compressed. bzip2 files are recognised even if bzip2
+/// support wasn't built in, so they give a helpful error instead of a FASTA parsing error.
+pub fn detect_compression(filename: &Path) -> Result<CompressionFormat, Error> {
+    let file = open_file(filename)?;
+    let mut buf = Vec::with_capacity(3);
+    if let Err(e) = BufReader::new(file).take(3).read_to_end(&mut buf) {
+        return Err(Error::Io(format!("unable to read {}\n{}", filename.display(), e)));
+    }
+    Ok(compression_from_magic(&buf))
+}
+
+
+fn compression_from_magic(start: &[u8]) -> CompressionFormat {
+    if start.starts_with(&[31, 139]) {
+        CompressionFormat::Gzip
+    } else if start.starts_with(b"BZh") {
+        CompressionFormat::Bzip2
+    } else {
+        CompressionFormat::Uncompressed
     }
 }
 
 
-/// This function returns true if the file appears to be gzipped (based on the first two bytes) and
-/// false if not. If it can't open the file or read the first two bytes, it will quit with an error
-/// message.
-pub fn is_file_gzipped(filename: &Path) -> bool {
-    let open_result = File::open(filename);
-    match open_result {
-        Ok(_)  => (),
-        Err(e) => quit_with_error(&format!("unable to open {}\n{}", filename.display(), e)),
+/// Decompresses a stream which can't be reopened (e.g. stdin) if necessary. Its first bytes are
+/// read to check for compression and then put back in front of the rest, so nothing is lost
+/// before the decoder (or the FASTA parser) reads it. name is only for error messages.
+pub fn decompress_stream<R: Read + 'static>(mut reader: R, name: &Path)
+        -> Result<Box<dyn Read>, Error> {
+    let mut start = Vec::with_capacity(3);
+    if let Err(e) = reader.by_ref().take(3).read_to_end(&mut start) {
+        return Err(Error::Io(format!("unable to read {}\n{}", name.display(), e)));
+    }
+    let compression = compression_from_magic(&start);
+    let reader = Cursor::new(start).chain(reader);
+    Ok(match compression {
+        CompressionFormat::Uncompressed => Box::new(reader),
+        CompressionFormat::Gzip => Box::new(GzDecoder::new(reader)),
+        CompressionFormat::Bzip2 => bzip2_reader(reader, name)?,
+    })
+}
+
+
+/// Returns an iterator over a FASTA file - works with uncompressed, gzipped or (with the bzip2
+/// feature) bzip2-compressed FASTAs. With mmap (--mmap), uncompressed files are memory-mapped.
+pub fn open_fasta_file(filename: &Path, mmap: bool)
+        -> Result<Reader<Box<dyn std::io::Read>>, Error> {
+    Ok(Reader::new(open_reader(filename, mmap)?))
+}
+
+
+/// Opens a non-empty file for reading, decompressing it if necessary.
+pub fn open_decompressed(filename: &Path) -> Result<Box<dyn Read>, Error> {
+    open_reader(filename, false)
+}
+
+
+fn open_reader(filename: &Path, mmap: bool) -> Result<Box<dyn Read>, Error> {
+    check_if_file_exists(filename)?;
+    check_if_file_is_empty(filename)?;
+    let file = open_file(filename)?;
+    Ok(match detect_compression(filename)? {
+        CompressionFormat::Uncompressed if mmap => mmap_reader(file, filename),
+        CompressionFormat::Uncompressed => Box::new(file),
+        CompressionFormat::Gzip => Box::new(GzDecoder::new(file)),
+        CompressionFormat::Bzip2 => bzip2_reader(file, filename)?,
+    })
+}
+
+
+/// Memory-maps the file, falling back to reading it normally if it can't be mapped (e.g. it's a
+/// pipe).
+fn mmap_reader(file: File, filename: &Path) -> Box<dyn Read> {
+    // SAFETY: the mapping is only read from. Like any reader, this assumes that the file isn't
+    // changed by another process while it's being read.
+    match unsafe { Mmap::map(&file) } {
+        Ok(mmap) => Box::new(Cursor::new(mmap)),
+        Err(e) => {
+            log::debug!("unable to memory-map {}, reading it normally instead: {}",
+                        filename.display(), e);
+            Box::new(file)
+        }
     }
-    let file = open_result.unwrap();
+}
+
+
+#[cfg(feature = "bzip2")]
+fn bzip2_reader<R: Read + 'static>(reader: R, _filename: &Path) -> Result<Box<dyn Read>, Error> {
+    Ok(Box::new(bzip2::read::MultiBzDecoder::new(reader)))
+}
+
+
+#[cfg(not(feature = "bzip2"))]
+fn bzip2_reader<R: Read + 'static>(_reader: R, filename: &Path) -> Result<Box<dyn Read>, Error> {
+    Err(Error::Io(format!("{} is bzip2-compressed, but this build of Core-SNP-filter doesn't \
+                           include bzip2 support (the bzip2 feature)", filename.display())))
+}
+
+
+/// Returns the SHA-256 digest of the data as lowercase hex, for --checksums.
+#[cfg(feature = "sha2")]
+pub fn sha256_hex(data: &[u8]) -> Result<String, Error> {
+    use sha2::{Digest, Sha256};
+    Ok(to_hex(&Sha256::digest(data)))
+}
+
+
+#[cfg(not(feature = "sha2"))]
+pub fn sha256_hex(_data: &[u8]) -> Result<String, Error> {
+    Err(no_sha256("--checksums"))
+}
+
+
+/// Returns an error if this build can't calculate SHA-256 digests, which the option needs.
+pub fn check_sha256_support(option: &str) -> Result<(), Error> {
+    if cfg!(feature = "sha2") { Ok(()) } else { Err(no_sha256(option)) }
+}
+
 
-    let mut reader = BufReader::new(file);
-    let mut buf = vec![0u8; 2];
+fn no_sha256(option: &str) -> Error {
+    Error::BadArguments(format!("{} needs SHA-256 support, but this build of Core-SNP-filter \
+                                 doesn't include it (the sha2 feature)", option))
+}
+
+
+#[cfg(feature = "sha2")]
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+
+/// Passes everything written on to another writer, while calculating the SHA-256 digest of it all
+/// (for --alignment_hash).
+#[cfg(feature = "sha2")]
+pub struct HashingWriter<'a> {
+    inner: &'a mut dyn Write,
+    hasher: sha2::Sha256,
+}
+
+
+/// Without SHA-256 support, a HashingWriter can't be made.
+#[cfg(not(feature = "sha2"))]
+pub struct HashingWriter<'a> {
+    never: std::convert::Infallible,
+    _inner: std::marker::PhantomData<&'a mut dyn Write>,
+}
 
-    let read_result = reader.read_exact(&mut buf);
-    match read_result {
-        Ok(_)  => (),
-        Err(e) => quit_with_error(&format!("{} is too small\n{}", filename.display(), e)),
+
+#[cfg(feature = "sha2")]
+impl<'a> HashingWriter<'a> {
+    pub fn new(inner: &'a mut dyn Write) -> Result<HashingWriter<'a>, Error> {
+        use sha2::Digest;
+        Ok(HashingWriter { inner, hasher: sha2::Sha256::new() })
     }
 
-    buf[0] == 31 && buf[1] == 139
+    /// Returns the digest of everything written, as lowercase hex.
+    pub fn finish(self) -> String {
+        use sha2::Digest;
+        to_hex(&self.hasher.finalize())
+    }
 }
 
 
-/// Returns an iterator over a FASTA file - works with either uncompressed or gzipped FASTAs.
-pub fn open_fasta_file(filename: &Path) -> Reader<Box<dyn std::io::Read>> {
-    check_if_file_exists(filename);
-    check_if_file_is_empty(filename);
-    let file = match File::open(filename) {
-        Ok(file) => file,
-        Err(e) => quit_with_error(&format!("There was a problem opening {}:\n{}",
-                                           filename.display(), e)),
-    };
-    let reader: Box<dyn Read> = match is_file_gzipped(filename) {
-        true => Box::new(GzDecoder::new(file)),
-        _ => Box::new(file),
-    };
-    Reader::new(reader)
+#[cfg(feature = "sha2")]
+impl Write for HashingWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        use sha2::Digest;
+        let size = self.inner.write(buf)?;
+        self.hasher.update(&buf[..size]);
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
 }
 
 
-pub fn get_first_fasta_seq_length(filename: &Path) -> usize {
-    let mut fasta_reader = open_fasta_file(filename);
+#[cfg(not(feature = "sha2"))]
+impl<'a> HashingWriter<'a> {
+    pub fn new(_inner: &'a mut dyn Write) -> Result<HashingWriter<'a>, Error> {
+        Err(no_sha256("--alignment_hash"))
+    }
+
+    pub fn finish(self) -> String {
+        match self.never {}
+    }
+}
+
+
+#[cfg(not(feature = "sha2"))]
+impl Write for HashingWriter<'_> {
+    fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+        match self.never {}
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.never {}
+    }
+}
+
+
+/// Returns the length of the first sequence in a FASTA file, which is an error if the file has no
+/// sequences or the first sequence has no bases (e.g. a header-only file).
+pub fn get_first_fasta_seq_length(filename: &Path) -> Result<usize, Error> {
+    let mut fasta_reader = open_fasta_file(filename, false)?;
     if let Some(record) = fasta_reader.next() {
-        let record = record.expect("Error reading record");
-        return record.full_seq().len();
+        let record = check_record(record, filename)?;
+        let seq = remove_carriage_returns(record.full_seq());
+        return check_seq_length_not_zero(seq.len(), filename);
+    }
+    Err(Error::EmptyInput(format!("empty alignment: {} contains no sequences",
+                                  filename.display())))
+}
+
+
+/// Removes any carriage returns (from Windows-style line endings) from a sequence. Most sequences
+/// won't have any, in which case no copy is made.
+pub fn remove_carriage_returns(seq: Cow<[u8]>) -> Cow<[u8]> {
+    if seq.contains(&b'\r') {
+        Cow::Owned(seq.iter().copied().filter(|&b| b != b'\r').collect())
+    } else {
+        seq
+    }
+}
+
+
+/// Returns an error if the alignment's sequences have no bases.
+pub fn check_seq_length_not_zero(length: usize, filename: &Path) -> Result<usize, Error> {
+    if length == 0 {
+        return Err(Error::EmptyInput(format!("empty alignment: {} contains only headers",
+                                             filename.display())));
+    }
+    Ok(length)
+}
+
+
+/// Returns where --outdir saves an input's filtered alignment: the input's filename without its
+/// extension (or .gz/.bz2 and its extension), plus .filtered.fasta. For example,
+/// clusters/c1.aln.gz gives outdir/c1.filtered.fasta.
+pub fn filtered_output_path(outdir: &Path, input: &Path) -> PathBuf {
+    let mut name = Path::new(input.file_name().unwrap_or_default());
+    if name.extension().map_or(false, |e| e.eq_ignore_ascii_case("gz") ||
+                                          e.eq_ignore_ascii_case("bz2")) {
+        name = Path::new(name.file_stem().unwrap_or_default());
+    }
+    let mut filename = name.file_stem().unwrap_or_default().to_os_string();
+    filename.push(".filtered.fasta");
+    outdir.join(filename)
+}
+
+
+/// Formats a duration for people: milliseconds under a second, seconds under a minute, and whole
+/// minutes and seconds (or hours and minutes) beyond that.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs == 0 {
+        format!("{} ms", duration.as_millis())
+    } else if secs < 60 {
+        format!("{:.1} s", duration.as_secs_f64())
+    } else if secs < 3600 {
+        format!("{} min {} s", secs / 60, secs % 60)
+    } else {
+        format!("{} h {} min", secs / 3600, (secs % 3600) / 60)
     }
-    quit_with_error(&format!("{} contains no sequences", filename.display()));
+}
+
+
+/// Converts a FASTA parsing error (which has no filename) into a malformed FASTA error.
+pub fn check_record<R>(record: Result<R, seq_io::fasta::Error>, filename: &Path)
+        -> Result<R, Error> {
+    record.map_err(|e| Error::MalformedFasta(format!("unable to read {} as FASTA\n{}",
+                                                     filename.display(), e)))
 }
 
 
@@ -103,6 +366,7 @@ pub fn get_first_fasta_seq_length(filename: &Path) -> usize {
 mod tests {
     use flate2::Compression;
     use flate2::write::GzEncoder;
+    use proptest::prelude::*;
     use std::fs::File;
     use std::io::Write;
     use std::path::PathBuf;
@@ -130,45 +394,116 @@ mod tests {
     #[test]
     fn test_check_if_file_is_empty_1() {
         let (path, _dir) = make_test_file(">seq_1\nACGAT\n");
-        check_if_file_is_empty(&path);
+        check_if_file_is_empty(&path).unwrap();
     }
 
     #[test]
-    #[should_panic]
     fn test_check_if_file_is_empty_2() {
         let (path, _dir) = make_test_file("");
-        check_if_file_is_empty(&path);
+        assert!(matches!(check_if_file_is_empty(&path), Err(Error::EmptyInput(_))));
     }
 
     #[test]
-    #[should_panic]
     fn test_check_if_file_exists() {
-        check_if_file_exists(&PathBuf::from("not_a_real_file"));
+        assert!(matches!(check_if_file_exists(&PathBuf::from("not_a_real_file")),
+                         Err(Error::Io(_))));
+    }
+
+    /// Only the magic number matters for detecting bzip2, so the rest is made up when the bzip2
+    /// feature (and so the encoder) isn't available.
+    fn make_bzipped_test_file(contents: &str) -> (PathBuf, TempDir) {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("test.fasta.bz2");
+        let mut file = File::create(&file_path).unwrap();
+        #[cfg(feature = "bzip2")]
+        let bytes = {
+            let mut e = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+            e.write_all(contents.as_bytes()).unwrap();
+            e.finish().unwrap()
+        };
+        #[cfg(not(feature = "bzip2"))]
+        let bytes = [b"BZh9", contents.as_bytes()].concat();
+        file.write_all(&bytes).unwrap();
+        (file_path, dir)
     }
 
     #[test]
-    fn test_is_file_gzipped_1() {
+    fn test_detect_compression_1() {
         let (path, _dir) = make_test_file(">seq_1\nACGAT\n");
-        assert!(!is_file_gzipped(&path));
+        assert_eq!(detect_compression(&path).unwrap(), CompressionFormat::Uncompressed);
     }
 
     #[test]
-    fn test_is_file_gzipped_2() {
+    fn test_detect_compression_2() {
         let (path, _dir) = make_gzipped_test_file(">seq_1\nACGAT\n");
-        assert!(is_file_gzipped(&path));
+        assert_eq!(detect_compression(&path).unwrap(), CompressionFormat::Gzip);
     }
 
     #[test]
-    #[should_panic]
-    fn test_is_file_gzipped_3() {
+    fn test_detect_compression_3() {
+        // Files too small to be compressed are just uncompressed.
         let (path, _dir) = make_test_file("");
-        is_file_gzipped(&path);
+        assert_eq!(detect_compression(&path).unwrap(), CompressionFormat::Uncompressed);
+        let (path, _dir) = make_test_file(">");
+        assert_eq!(detect_compression(&path).unwrap(), CompressionFormat::Uncompressed);
     }
 
     #[test]
-    #[should_panic]
-    fn test_is_file_gzipped_4() {
-        is_file_gzipped(&PathBuf::from("not_a_real_file"));
+    fn test_detect_compression_4() {
+        assert!(matches!(detect_compression(&PathBuf::from("not_a_real_file")),
+                         Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn test_detect_compression_5() {
+        let (path, _dir) = make_bzipped_test_file(">seq_1\nACGAT\n");
+        assert_eq!(detect_compression(&path).unwrap(), CompressionFormat::Bzip2);
+    }
+
+    #[cfg(feature = "bzip2")]
+    #[test]
+    fn test_open_fasta_file_bzip2() {
+        let (path, _dir) = make_bzipped_test_file(">seq_1\nACGAT\n>seq_2\nACGTT\n");
+        let mut reader = open_fasta_file(&path, false).unwrap();
+        let mut seqs = Vec::new();
+        while let Some(record) = reader.next() {
+            seqs.push(record.unwrap().full_seq().into_owned());
+        }
+        assert_eq!(seqs, vec![b"ACGAT".to_vec(), b"ACGTT".to_vec()]);
+        assert_eq!(get_first_fasta_seq_length(&path).unwrap(), 5);
+    }
+
+    #[cfg(not(feature = "bzip2"))]
+    #[test]
+    fn test_open_fasta_file_bzip2() {
+        let (path, _dir) = make_bzipped_test_file(">seq_1\nACGAT\n");
+        assert!(matches!(open_fasta_file(&path, false), Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn test_open_fasta_file_mmap() {
+        // Only uncompressed files are memory-mapped, but the records are the same either way.
+        let read_seqs = |path: &Path, mmap: bool| {
+            let mut reader = open_fasta_file(path, mmap).unwrap();
+            let mut seqs = Vec::new();
+            while let Some(record) = reader.next() {
+                seqs.push(record.unwrap().full_seq().into_owned());
+            }
+            seqs
+        };
+        let (path, _dir) = make_test_file(">seq_1\nACGAT\n>seq_2\nACG\nTT\n");
+        let (gz_path, _gz_dir) = make_gzipped_test_file(">seq_1\nACGAT\n>seq_2\nACGTT\n");
+        let seqs = read_seqs(&path, true);
+        assert_eq!(seqs, vec![b"ACGAT".to_vec(), b"ACGTT".to_vec()]);
+        assert_eq!(read_seqs(&path, false), seqs);
+        assert_eq!(read_seqs(&gz_path, true), seqs);
+    }
+
+    #[test]
+    fn test_remove_carriage_returns() {
+        assert_eq!(remove_carriage_returns(Cow::Borrowed(b"ACGT")), Cow::Borrowed(b"ACGT"));
+        assert_eq!(remove_carriage_returns(Cow::Borrowed(b"AC\rGT\r")),
+                   Cow::Borrowed(b"ACGT"));
     }
 
     #[test]
@@ -176,21 +511,101 @@ mod tests {
         let (path, _dir) = make_test_file(">seq_1\nACGAT\n\
                                            >seq_2\nGGTA\n\
                                            >seq_3\nCTCGCATCAG\n");
-        let first_seq_len = get_first_fasta_seq_length(&path);
+        let first_seq_len = get_first_fasta_seq_length(&path).unwrap();
         assert_eq!(first_seq_len, 5);
     }
 
     #[test]
-    #[should_panic]
     fn test_get_first_fasta_seq_length_2() {
         let (path, _dir) = make_test_file("");
-        get_first_fasta_seq_length(&path);
+        assert!(matches!(get_first_fasta_seq_length(&path), Err(Error::EmptyInput(_))));
     }
 
     #[test]
-    #[should_panic]
     fn test_get_first_fasta_seq_length_3() {
         let (path, _dir) = make_gzipped_test_file("");
-        get_first_fasta_seq_length(&path);
+        assert!(matches!(get_first_fasta_seq_length(&path), Err(Error::EmptyInput(_))));
+    }
+
+    #[test]
+    fn test_get_first_fasta_seq_length_4() {
+        let (path, _dir) = make_test_file(">seq_1\n>seq_2\n");
+        assert!(matches!(get_first_fasta_seq_length(&path), Err(Error::EmptyInput(_))));
+    }
+
+    #[test]
+    fn test_get_first_fasta_seq_length_5() {
+        // A single byte used to be too small to check for gzip.
+        let (path, _dir) = make_test_file("\n");
+        assert!(matches!(get_first_fasta_seq_length(&path), Err(Error::EmptyInput(_))));
+    }
+
+    #[test]
+    fn test_get_first_fasta_seq_length_6() {
+        let (path, _dir) = make_test_file("ACGT\n");
+        assert!(matches!(get_first_fasta_seq_length(&path), Err(Error::MalformedFasta(_))));
+    }
+
+    #[test]
+    fn test_get_first_fasta_seq_length_7() {
+        // A truncated gzip file is an error, not a panic.
+        let (path, _dir) = make_gzipped_test_file(">seq_1\nACGATCAGCATCAGCACTACGACT\n");
+        let gzipped = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &gzipped[..gzipped.len() / 2]).unwrap();
+        assert!(matches!(get_first_fasta_seq_length(&path), Err(Error::MalformedFasta(_))));
+    }
+
+    #[test]
+    fn test_get_first_fasta_seq_length_8() {
+        // Like reading /dev/zero, but with an end.
+        let (path, _dir) = make_test_file("");
+        std::fs::write(&path, vec![0; 10000]).unwrap();
+        assert!(matches!(get_first_fasta_seq_length(&path), Err(Error::MalformedFasta(_))));
+    }
+
+    proptest! {
+        #[test]
+        fn test_arbitrary_input(bytes in prop::collection::vec(any::<u8>(), 0..300),
+                                gzip_magic: bool) {
+            // Any bytes (optionally starting like a gzip file) give a result, never a panic.
+            let (path, _dir) = make_test_file("");
+            let bytes = if gzip_magic { [&[0x1f, 0x8b][..], &bytes].concat() } else { bytes };
+            std::fs::write(&path, &bytes).unwrap();
+            let _ = get_first_fasta_seq_length(&path);
+            if let Ok(mut fasta_reader) = open_fasta_file(&path, false) {
+                while let Some(record) = fasta_reader.next() {
+                    if check_record(record, &path).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_filtered_output_path() {
+        let outdir = Path::new("out");
+        assert_eq!(filtered_output_path(outdir, Path::new("clusters/c1.aln.gz")),
+                   Path::new("out/c1.filtered.fasta"));
+        assert_eq!(filtered_output_path(outdir, Path::new("c2.fasta")),
+                   Path::new("out/c2.filtered.fasta"));
+        assert_eq!(filtered_output_path(outdir, Path::new("core.full.aln")),
+                   Path::new("out/core.full.filtered.fasta"));
+        assert_eq!(filtered_output_path(outdir, Path::new("c3.GZ")),
+                   Path::new("out/c3.filtered.fasta"));
+        assert_eq!(filtered_output_path(outdir, Path::new("c4")),
+                   Path::new("out/c4.filtered.fasta"));
+        assert_eq!(filtered_output_path(outdir, Path::new("c5.fasta.bz2")),
+                   Path::new("out/c5.filtered.fasta"));
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_millis(0)), "0 ms");
+        assert_eq!(format_duration(Duration::from_millis(999)), "999 ms");
+        assert_eq!(format_duration(Duration::from_millis(1300)), "1.3 s");
+        assert_eq!(format_duration(Duration::from_secs(59)), "59.0 s");
+        assert_eq!(format_duration(Duration::from_secs(125)), "2 min 5 s");
+        assert_eq!(format_duration(Duration::from_secs(7384)), "2 h 3 min");
     }
 }
@@ -0,0 +1,180 @@
+// Copyright 2023 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Core-SNP-filter
+
+// This file is part of Core-SNP-filter. Core-SNP-filter is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any later version.
+// Core-SNP-filter is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU General Public License for more details. You should have received a copy of the GNU
+// General Public License along with Core-SNP-filter. If not, see <http://www.gnu.org/licenses/>.
+
+use bitvec::prelude::*;
+use std::io::Write;
+use std::path::Path;
+
+use crate::error::Error;
+use crate::misc::{create_file, read_lines};
+
+
+/// One line of a RAxML-style partition file, e.g. "DNA, gene_1 = 1-500, 601-700".
+#[derive(Clone, Debug, PartialEq)]
+pub struct Partition {
+    /// The model or data type before the comma (e.g. DNA or GTR+G), which is kept as is
+    pub model: String,
+    pub name: String,
+    /// The partition's sites as 1-based inclusive ranges, in the order given
+    pub ranges: Vec<(usize, usize)>,
+}
+
+
+/// Loads a RAxML-style partition file (which IQ-TREE also reads), with one partition per line:
+/// the model, a comma, the name, an equals sign and a comma-separated list of 1-based inclusive
+/// ranges (or single positions). Blank lines and lines starting with # are skipped. Codon-style
+/// ranges with a stride (e.g. 1-500\3) aren't supported, since filtering doesn't keep their
+/// spacing.
+pub fn load_partitions(filename: &Path, alignment_length: usize)
+        -> Result<Vec<Partition>, Error> {
+    let mut partitions = Vec::new();
+    for line in read_lines(filename)? {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let bad = |message: &str| {
+            Err(Error::BadArguments(format!("{} in {}: {}", message, filename.display(), line)))
+        };
+        let (model_and_name, ranges) = match line.split_once('=') {
+            Some(parts) => parts,
+            None => return bad("partition line has no '='"),
+        };
+        let (model, name) = match model_and_name.split_once(',') {
+            Some((model, name)) if !model.trim().is_empty() && !name.trim().is_empty() => {
+                (model.trim(), name.trim())
+            }
+            _ => return bad("partition line should start with a model and name, e.g. DNA, gene"),
+        };
+        let mut partition = Partition { model: model.to_string(), name: name.to_string(),
+                                        ranges: Vec::new() };
+        for range in ranges.split(',') {
+            let range = range.trim();
+            if range.contains('\\') {
+                return bad("partition ranges with a stride aren't supported");
+            }
+            let (start, end) = range.split_once('-').unwrap_or((range, range));
+            let (start, end) = match (start.trim().parse::<usize>(), end.trim().parse::<usize>()) {
+                (Ok(start), Ok(end)) => (start, end),
+                _ => return bad("invalid partition range"),
+            };
+            if start == 0 || start > end {
+                return bad("invalid partition range");
+            }
+            if end > alignment_length {
+                return bad(&format!("partition range extends past the end of the alignment \
+                                     ({} bp)", alignment_length));
+            }
+            partition.ranges.push((start, end));
+        }
+        partitions.push(partition);
+    }
+    Ok(partitions)
+}
+
+
+/// Writes the partitions with their ranges translated to positions in the filtered alignment,
+/// merging the kept sites of each partition into as few ranges as possible. Partitions with no
+/// kept sites are left out, and their names are returned.
+pub fn write_partitions(filename: &Path, partitions: &[Partition], keep: &BitVec)
+        -> Result<Vec<String>, Error> {
+    // The new position of each kept site is the number of kept sites before it.
+    let mut new_positions = vec![0; keep.len()];
+    for (new_pos, original_pos) in keep.iter_ones().enumerate() {
+        new_positions[original_pos] = new_pos + 1;
+    }
+    let mut file = create_file(filename)?;
+    let mut empty = Vec::new();
+    for partition in partitions {
+        let ranges = new_ranges(partition, keep, &new_positions);
+        if ranges.is_empty() {
+            empty.push(partition.name.clone());
+            continue;
+        }
+        let ranges: Vec<String> = ranges.iter().map(|&(start, end)| {
+            if start == end { start.to_string() } else { format!("{}-{}", start, end) }
+        }).collect();
+        writeln!(file, "{}, {} = {}", partition.model, partition.name, ranges.join(", "))?;
+    }
+    Ok(empty)
+}
+
+
+/// Returns the partition's kept sites as 1-based inclusive ranges of new positions.
+fn new_ranges(partition: &Partition, keep: &BitVec, new_positions: &[usize])
+        -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &(start, end) in &partition.ranges {
+        for i in (start - 1..end).filter(|&i| keep[i]) {
+            let pos = new_positions[i];
+            match ranges.last_mut() {
+                Some((_, end)) if *end + 1 == pos => *end = pos,
+                _ => ranges.push((pos, pos)),
+            }
+        }
+    }
+    ranges
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::path::PathBuf;
+    use tempfile::{TempDir,tempdir};
+    use super::*;
+
+    fn make_test_file(contents: &str) -> (PathBuf, TempDir) {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("partitions.txt");
+        let mut file = File::create(&file_path).unwrap();
+        write!(file, "{}", contents).unwrap();
+        (file_path, dir)
+    }
+
+    #[test]
+    fn test_load_partitions() {
+        let (path, _dir) = make_test_file("DNA, gene_1 = 1-4, 9\n\n# comment\n\
+                                           GTR+G,gene_2=5-8\n");
+        assert_eq!(load_partitions(&path, 10).unwrap(),
+                   vec![Partition { model: "DNA".to_string(), name: "gene_1".to_string(),
+                                    ranges: vec![(1, 4), (9, 9)] },
+                        Partition { model: "GTR+G".to_string(), name: "gene_2".to_string(),
+                                    ranges: vec![(5, 8)] }]);
+    }
+
+    #[test]
+    fn test_load_partitions_errors() {
+        for contents in ["gene_1 = 1-4\n", "DNA, gene_1\n", "DNA, gene_1 = 1-11\n",
+                         "DNA, gene_1 = 0-4\n", "DNA, gene_1 = 4-1\n", "DNA, gene_1 = a-4\n",
+                         "DNA, codon_1 = 1-9\\3\n"].iter() {
+            let (path, _dir) = make_test_file(contents);
+            assert!(matches!(load_partitions(&path, 10), Err(Error::BadArguments(_))));
+        }
+    }
+
+    #[test]
+    fn test_write_partitions() {
+        let partitions = [
+            Partition { model: "DNA".to_string(), name: "a".to_string(), ranges: vec![(1, 4)] },
+            Partition { model: "DNA".to_string(), name: "b".to_string(), ranges: vec![(5, 6)] },
+            Partition { model: "DNA".to_string(), name: "c".to_string(),
+                        ranges: vec![(7, 8), (2, 2)] },
+        ];
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("out.txt");
+        let keep = bitvec![1, 1, 0, 1, 0, 0, 1, 1];
+        let empty = write_partitions(&path, &partitions, &keep).unwrap();
+        assert_eq!(empty, vec!["b".to_string()]);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(),
+                   "DNA, a = 1-3\nDNA, c = 4-5, 2\n");
+    }
+}
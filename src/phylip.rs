@@ -0,0 +1,138 @@
+// Copyright 2023 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Core-SNP-filter
+
+// This file is part of Core-SNP-filter. Core-SNP-filter is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any later version.
+// Core-SNP-filter is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU General Public License for more details. You should have received a copy of the GNU
+// General Public License along with Core-SNP-filter. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io::BufRead;
+use std::path::Path;
+
+use crate::alignment::AlignmentRecord;
+use crate::error::Error;
+
+
+/// Reads a relaxed PHYLIP alignment: a first line with the number of sequences and the number of
+/// sites, then each sequence's name and bases separated by whitespace. In sequential files, each
+/// sequence is on one line. In interleaved files, later blocks continue the sequences (in the same
+/// order, without names). Blank lines and whitespace within the bases are ignored. Both counts on
+/// the first line are checked, so a file with multi-line sequential records fails to load instead
+/// of being read as interleaved.
+pub fn read_phylip<R: BufRead>(reader: R, filename: &Path) -> Result<Vec<AlignmentRecord>, Error> {
+    let malformed = |message: String| {
+        Error::MalformedFasta(format!("unable to read {} as PHYLIP\n{}", filename.display(),
+                                      message))
+    };
+    let mut lines = reader.lines()
+        .map(|line| line.map_err(|e| Error::Io(format!("unable to read {}\n{}",
+                                                       filename.display(), e))))
+        .filter(|line| line.as_ref().map_or(true, |l| !l.trim().is_empty()));
+    let first_line = match lines.next() {
+        Some(line) => line?,
+        None => return Err(Error::EmptyInput(format!("empty alignment: {} is empty",
+                                                     filename.display()))),
+    };
+    let (seq_count, seq_length) = parse_first_line(&first_line).ok_or_else(|| {
+        malformed(format!("the first line should give the number of sequences and sites: {}",
+                          first_line.trim()))
+    })?;
+    if seq_count == 0 {
+        return Err(Error::EmptyInput(format!("empty alignment: {} contains no sequences",
+                                             filename.display())));
+    }
+
+    let mut records: Vec<AlignmentRecord> = Vec::new();
+    for (i, line) in lines.enumerate() {
+        let line = line?;
+        if i < seq_count {
+            let line = line.trim();
+            let (name, bases) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            records.push(AlignmentRecord { header: name.to_string(), seq: without_spaces(bases) });
+        } else {
+            records[i % seq_count].seq.extend(without_spaces(&line));
+        }
+    }
+    if records.len() != seq_count {
+        return Err(malformed(format!("the first line gives {} sequences but there are {}",
+                                     seq_count, records.len())));
+    }
+    for record in &records {
+        if record.seq.len() != seq_length {
+            return Err(malformed(format!("the first line gives {} sites but {} has {}",
+                                         seq_length, record.header, record.seq.len())));
+        }
+    }
+    Ok(records)
+}
+
+
+/// Returns the number of sequences and sites from the first line, ignoring anything after them
+/// (some programs add options there).
+fn parse_first_line(line: &str) -> Option<(usize, usize)> {
+    let mut words = line.split_whitespace();
+    let seq_count = words.next()?.parse().ok()?;
+    let seq_length = words.next()?.parse().ok()?;
+    Some((seq_count, seq_length))
+}
+
+
+fn without_spaces(bases: &str) -> Vec<u8> {
+    bases.bytes().filter(|b| !b.is_ascii_whitespace()).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(contents: &str) -> Result<Vec<(String, String)>, Error> {
+        let records = read_phylip(contents.as_bytes(), Path::new("test.phy"))?;
+        Ok(records.into_iter()
+            .map(|r| (r.header, String::from_utf8(r.seq).unwrap())).collect())
+    }
+
+    fn pairs(records: &[(&str, &str)]) -> Vec<(String, String)> {
+        records.iter().map(|(h, s)| (h.to_string(), s.to_string())).collect()
+    }
+
+    #[test]
+    fn test_read_phylip_sequential() {
+        let expected = pairs(&[("seq_1", "ACGT-A"), ("sequence_two", "ACGTNA")]);
+        assert_eq!(read("2 6\nseq_1  ACGT-A\nsequence_two ACGTNA\n").unwrap(), expected);
+        assert_eq!(read(" 2 6 \r\n\r\nseq_1\tACG T-A\r\nsequence_two ACGTNA\r\n").unwrap(),
+                   expected);
+    }
+
+    #[test]
+    fn test_read_phylip_interleaved() {
+        let records = read("3 10\n\
+                            seq_1 ACGTA\nseq_2 ACGTT\nseq_3 A-GTA\n\n\
+                            CCGTA\nCC-TA\nCCGTN\n").unwrap();
+        assert_eq!(records, pairs(&[("seq_1", "ACGTACCGTA"), ("seq_2", "ACGTTCC-TA"),
+                                    ("seq_3", "A-GTACCGTN")]));
+        // A block can also start on the same line as the names.
+        assert_eq!(read("2 4\na AC\nb AG\nGT\nTT\n").unwrap(),
+                   pairs(&[("a", "ACGT"), ("b", "AGTT")]));
+        assert_eq!(read("2 4\na\nb\nACGT\nAGTT\n").unwrap(),
+                   pairs(&[("a", "ACGT"), ("b", "AGTT")]));
+    }
+
+    #[test]
+    fn test_read_phylip_errors() {
+        assert!(matches!(read(""), Err(Error::EmptyInput(_))));
+        assert!(matches!(read("0 5\n"), Err(Error::EmptyInput(_))));
+        assert!(matches!(read(">seq_1\nACGT\n"), Err(Error::MalformedFasta(_))));
+        assert!(matches!(read("2\na ACGT\nb ACGT\n"), Err(Error::MalformedFasta(_))));
+        assert!(matches!(read("3 4\na ACGT\nb ACGT\n"), Err(Error::MalformedFasta(_))));
+        assert!(matches!(read("2 4\na ACGT\nb ACG\n"), Err(Error::MalformedFasta(_))));
+        assert!(matches!(read("2 4\na ACGT\nb ACGT\nAC\n"), Err(Error::MalformedFasta(_))));
+
+        // Sequential records which span lines look interleaved, so their lengths are wrong.
+        assert!(matches!(read("2 8\na ACGT\nACGT\nb ACGT\nACGT\n"),
+                         Err(Error::MalformedFasta(_))));
+    }
+}
@@ -0,0 +1,236 @@
+// Copyright 2023 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Core-SNP-filter
+
+// This file is part of Core-SNP-filter. Core-SNP-filter is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any later version.
+// Core-SNP-filter is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU General Public License for more details. You should have received a copy of the GNU
+// General Public License along with Core-SNP-filter. If not, see <http://www.gnu.org/licenses/>.
+
+use bitvec::prelude::*;
+use std::io::{BufRead, BufReader};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::error::Error;
+use crate::misc::{open_decompressed, read_lines};
+
+
+/// Loads a BED file and returns a bitvector (the length of the alignment) which is true for each
+/// site covered by one of the BED's intervals. Since a pseudo-alignment has no contig names, the
+/// BED coordinates (0-based, half-open) are treated as offsets into the alignment. If chrom is
+/// given, only intervals with a matching chromosome name are used, otherwise all are.
+pub fn load_bed_mask(filename: &Path, chrom: Option<&str>, alignment_length: usize)
+        -> Result<BitVec, Error> {
+    let mut mask = bitvec![0; alignment_length];
+    for line in read_lines(filename)? {
+        if line.trim().is_empty() || line.starts_with('#') || line.starts_with("track") ||
+                line.starts_with("browser") {
+            continue;
+        }
+        let parts: Vec<&str> = line.split('\t').collect();
+        if parts.len() < 3 {
+            return Err(Error::BadArguments(format!("BED line has fewer than three columns: {}",
+                                                   line)));
+        }
+        if let Some(chrom) = chrom {
+            if parts[0] != chrom {
+                continue;
+            }
+        }
+        let start = parse_bed_coordinate(parts[1], &line)?;
+        let end = parse_bed_coordinate(parts[2], &line)?;
+        if start > end {
+            return Err(Error::BadArguments(format!("BED interval start is after its end: {}",
+                                                   line)));
+        }
+        if end > alignment_length {
+            return Err(Error::BadArguments(format!("BED interval extends past the end of the \
+                                                    alignment ({} bp): {}",
+                                                   alignment_length, line)));
+        }
+        mask[start..end].fill(true);
+    }
+    Ok(mask)
+}
+
+
+/// Loads a file of positions (one per line, the first site being first_pos, i.e. 1 for 1-based or 0
+/// for 0-based) and returns a bitvector (the length of the alignment) which is true for each
+/// listed site.
+pub fn load_positions(filename: &Path, alignment_length: usize, first_pos: usize)
+        -> Result<BitVec, Error> {
+    let mut positions = bitvec![0; alignment_length];
+    for line in read_lines(filename)? {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let pos: usize = match line.parse() {
+            Ok(pos) => pos,
+            Err(_) => return Err(Error::BadArguments(format!("invalid position in {}: {}",
+                                                             filename.display(), line))),
+        };
+        if pos < first_pos || pos >= alignment_length + first_pos {
+            return Err(Error::BadArguments(format!("position {} is outside the alignment ({}-{})",
+                                                   pos, first_pos,
+                                                   alignment_length + first_pos - 1)));
+        }
+        positions.set(pos - first_pos, true);
+    }
+    Ok(positions)
+}
+
+
+/// Loads the positions (the POS column, which is always 1-based) of a VCF's records and returns a
+/// bitvector (the length of the alignment) which is true for each listed site. Like with a BED
+/// file, the CHROM column is ignored, since a VCF made from an alignment (e.g. by snp-sites) uses
+/// the alignment's coordinates. The VCF can be gzipped.
+pub fn load_vcf_positions(filename: &Path, alignment_length: usize) -> Result<BitVec, Error> {
+    let mut positions = bitvec![0; alignment_length];
+    for line in BufReader::new(open_decompressed(filename)?).lines() {
+        let line = line.map_err(|e| Error::Io(format!("unable to read {}\n{}",
+                                                      filename.display(), e)))?;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let pos: usize = match line.split('\t').nth(1).map(|pos| pos.trim().parse()) {
+            Some(Ok(pos)) => pos,
+            _ => return Err(Error::BadArguments(format!("invalid VCF position in {}: {}",
+                                                        filename.display(), line))),
+        };
+        if pos == 0 || pos > alignment_length {
+            return Err(Error::BadArguments(format!("VCF position {} in {} is outside the \
+                                                    alignment (1-{})", pos, filename.display(),
+                                                   alignment_length)));
+        }
+        positions.set(pos - 1, true);
+    }
+    Ok(positions)
+}
+
+
+/// Converts an inclusive --region (whose first site is first_pos) into a 0-based half-open range of
+/// alignment indices, checking that it fits in the alignment.
+pub fn region_range(region: (usize, usize), alignment_length: usize, first_pos: usize)
+        -> Result<Range<usize>, Error> {
+    let (start, end) = region;
+    if start < first_pos || end >= alignment_length + first_pos {
+        return Err(Error::BadArguments(format!("region {}-{} is outside the alignment ({}-{})",
+                                               start, end, first_pos,
+                                               alignment_length + first_pos - 1)));
+    }
+    Ok(start - first_pos..end - first_pos + 1)
+}
+
+
+fn parse_bed_coordinate(coord: &str, line: &str) -> Result<usize, Error> {
+    coord.trim().parse()
+        .map_err(|_| Error::BadArguments(format!("invalid BED coordinate: {}", line)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use tempfile::{TempDir,tempdir};
+    use super::*;
+
+    fn make_test_file(contents: &str) -> (PathBuf, TempDir) {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("test.bed");
+        let mut file = File::create(&file_path).unwrap();
+        write!(file, "{}", contents).unwrap();
+        (file_path, dir)
+    }
+
+    #[test]
+    fn test_load_bed_mask_1() {
+        let (path, _dir) = make_test_file("chr\t1\t3\n\
+                                           chr\t6\t7\n");
+        let mask = load_bed_mask(&path, None, 8).unwrap();
+        assert_eq!(mask, bitvec![0, 1, 1, 0, 0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn test_load_bed_mask_2() {
+        // Only intervals on the given chromosome are used, and header lines are skipped.
+        let (path, _dir) = make_test_file("track name=recombination\n\
+                                           chr_a\t0\t2\n\
+                                           chr_b\t4\t8\tlabel\n");
+        assert_eq!(load_bed_mask(&path, Some("chr_b"), 8).unwrap(),
+                   bitvec![0, 0, 0, 0, 1, 1, 1, 1]);
+        assert_eq!(load_bed_mask(&path, Some("chr_c"), 8).unwrap(), bitvec![0; 8]);
+    }
+
+    #[test]
+    fn test_load_bed_mask_3() {
+        // Interval extends past the end of the alignment.
+        let (path, _dir) = make_test_file("chr\t5\t9\n");
+        assert!(matches!(load_bed_mask(&path, None, 8), Err(Error::BadArguments(_))));
+    }
+
+    #[test]
+    fn test_load_bed_mask_4() {
+        // Non-numeric coordinate.
+        let (path, _dir) = make_test_file("chr\tone\t3\n");
+        assert!(matches!(load_bed_mask(&path, None, 8), Err(Error::BadArguments(_))));
+    }
+
+    #[test]
+    fn test_load_positions_1() {
+        let (path, _dir) = make_test_file("1\n4\n\n# comment\n8\n4\n");
+        assert_eq!(load_positions(&path, 8, 1).unwrap(), bitvec![1, 0, 0, 1, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_load_positions_2() {
+        // Positions are 1-based, so zero is invalid.
+        let (path, _dir) = make_test_file("0\n");
+        assert!(matches!(load_positions(&path, 8, 1), Err(Error::BadArguments(_))));
+    }
+
+    #[test]
+    fn test_load_positions_3() {
+        let (path, _dir) = make_test_file("9\n");
+        assert!(matches!(load_positions(&path, 8, 1), Err(Error::BadArguments(_))));
+    }
+
+    #[test]
+    fn test_load_positions_4() {
+        // 0-based positions go up to one less than the alignment length.
+        let (path, _dir) = make_test_file("0\n7\n");
+        assert_eq!(load_positions(&path, 8, 0).unwrap(), bitvec![1, 0, 0, 0, 0, 0, 0, 1]);
+        let (path, _dir) = make_test_file("8\n");
+        assert!(matches!(load_positions(&path, 8, 0), Err(Error::BadArguments(_))));
+    }
+
+    #[test]
+    fn test_load_vcf_positions() {
+        let (path, _dir) = make_test_file("##fileformat=VCFv4.1\n\
+                                           #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+                                           1\t2\t.\tA\tG\t.\t.\t.\n\
+                                           1\t5\t.\tC\tT\t.\t.\t.\n\
+                                           1\t8\t.\tG\tA,T\t.\t.\t.\n");
+        assert_eq!(load_vcf_positions(&path, 8).unwrap(), bitvec![0, 1, 0, 0, 1, 0, 0, 1]);
+        assert!(matches!(load_vcf_positions(&path, 7), Err(Error::BadArguments(_))));
+        for contents in ["1\t0\t.\tA\tG\n", "1\tx\t.\tA\tG\n", "1 2 . A G\n"].iter() {
+            let (path, _dir) = make_test_file(contents);
+            assert!(matches!(load_vcf_positions(&path, 8), Err(Error::BadArguments(_))));
+        }
+    }
+
+    #[test]
+    fn test_region_range() {
+        assert_eq!(region_range((1, 8), 8, 1).unwrap(), 0..8);
+        assert_eq!(region_range((3, 3), 8, 1).unwrap(), 2..3);
+        assert_eq!(region_range((0, 7), 8, 0).unwrap(), 0..8);
+        assert!(matches!(region_range((0, 4), 8, 1), Err(Error::BadArguments(_))));
+        assert!(matches!(region_range((1, 9), 8, 1), Err(Error::BadArguments(_))));
+        assert!(matches!(region_range((1, 8), 8, 0), Err(Error::BadArguments(_))));
+    }
+}
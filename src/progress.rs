@@ -0,0 +1,57 @@
+// Copyright 2023 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Core-SNP-filter
+
+// This file is part of Core-SNP-filter. Core-SNP-filter is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any later version.
+// Core-SNP-filter is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU General Public License for more details. You should have received a copy of the GNU
+// General Public License along with Core-SNP-filter. If not, see <http://www.gnu.org/licenses/>.
+
+use indicatif::{ProgressBar, ProgressStyle};
+use std::time::Duration;
+
+
+/// A spinner on stderr with a running count of the sequences processed by one pass over the
+/// alignment (for --progress). The total isn't known in advance, so there's no bar. A hidden
+/// spinner does nothing, and indicatif doesn't draw a visible one unless stderr is a terminal. The
+/// spinner is cleared when dropped.
+pub struct Progress(Option<ProgressBar>);
+
+
+impl Progress {
+    /// Starts a spinner labelled with the pass's task ("counting" or "filtering") if enabled is
+    /// true.
+    pub fn new(enabled: bool, task: &str) -> Progress {
+        if !enabled {
+            return Progress::hidden();
+        }
+        let spinner = ProgressBar::new_spinner();
+        spinner.set_style(ProgressStyle::with_template("{spinner} {msg}: {pos} sequences \
+                                                        [{elapsed}]").unwrap());
+        spinner.set_message(task.to_string());
+        spinner.enable_steady_tick(Duration::from_millis(100));
+        Progress(Some(spinner))
+    }
+
+    pub fn hidden() -> Progress {
+        Progress(None)
+    }
+
+    /// Adds to the count of processed sequences.
+    pub fn add(&self, sequences: usize) {
+        if let Some(spinner) = &self.0 {
+            spinner.inc(sequences as u64);
+        }
+    }
+}
+
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if let Some(spinner) = &self.0 {
+            spinner.finish_and_clear();
+        }
+    }
+}
@@ -0,0 +1,309 @@
+// Copyright 2023 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Core-SNP-filter
+
+// This file is part of Core-SNP-filter. Core-SNP-filter is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any later version.
+// Core-SNP-filter is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU General Public License for more details. You should have received a copy of the GNU
+// General Public License along with Core-SNP-filter. If not, see <http://www.gnu.org/licenses/>.
+
+use log::info;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use regex::Regex;
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::alignment::Alignment;
+use crate::error::Error;
+use crate::misc::{print_warning, read_lines};
+
+
+/// A set of sample names (or a regex) which limits which sequences of the alignment are used:
+/// either only the matching samples (include) or all but the matching samples (exclude). Sequences
+/// are matched by their name (the first word of the header).
+#[derive(Clone, Debug)]
+pub struct SampleSelection {
+    names: HashSet<String>,
+    pattern: Option<Regex>,
+    include: bool,
+}
+
+
+impl SampleSelection {
+    /// Loads a file of sample names (one per line) to include.
+    pub fn include(filename: &Path) -> Result<SampleSelection, Error> {
+        Ok(SampleSelection { names: load_names(filename)?, pattern: None, include: true })
+    }
+
+    /// Loads a file of sample names (one per line) to exclude.
+    pub fn exclude(filename: &Path) -> Result<SampleSelection, Error> {
+        Ok(SampleSelection { names: load_names(filename)?, pattern: None, include: false })
+    }
+
+    /// Includes the samples whose names match the regex (anywhere in the name, unless anchored).
+    pub fn include_regex(pattern: &str) -> Result<SampleSelection, Error> {
+        Ok(SampleSelection { names: HashSet::new(), pattern: Some(parse_regex(pattern)?),
+                             include: true })
+    }
+
+    /// Excludes the samples whose names match the regex (anywhere in the name, unless anchored).
+    pub fn exclude_regex(pattern: &str) -> Result<SampleSelection, Error> {
+        Ok(SampleSelection { names: HashSet::new(), pattern: Some(parse_regex(pattern)?),
+                             include: false })
+    }
+
+    /// Returns whether the sequence with this header should be used.
+    pub fn is_selected(&self, header: &str) -> bool {
+        let name = seq_name(header);
+        let matches = match &self.pattern {
+            Some(pattern) => pattern.is_match(name),
+            None => self.names.contains(name),
+        };
+        matches == self.include
+    }
+
+    /// Returns (in sorted order) any selected names which aren't in the given sequence names. A
+    /// regex selection has no names, so nothing is missing.
+    pub fn missing_names(&self, seq_names: &[String]) -> Vec<String> {
+        let seq_names: HashSet<&str> = seq_names.iter().map(|name| name.as_str()).collect();
+        let mut missing: Vec<String> = self.names.iter()
+            .filter(|name| !seq_names.contains(name.as_str())).cloned().collect();
+        missing.sort();
+        missing
+    }
+}
+
+
+/// Randomly chooses count of the alignment's sequences (keeping their order), using seed so the
+/// same sequences are chosen every time. If the alignment stays on disk, the chosen sequences are
+/// selected by name, so any other sequences with the same names are also kept. The chosen names
+/// are logged at the info level (shown with --verbose).
+pub fn subsample(alignment: Alignment, count: usize, seed: u64) -> Result<Alignment, Error> {
+    let mut names = Vec::new();
+    alignment.for_each_record(|header, _| {
+        names.push(seq_name(header).to_string());
+        Ok(())
+    })?;
+    if count >= names.len() {
+        print_warning(&format!("--subsample {} is not less than the number of sequences ({}), \
+                                so all sequences are used", count, names.len()));
+        return Ok(alignment);
+    }
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut chosen = rand::seq::index::sample(&mut rng, names.len(), count).into_vec();
+    chosen.sort_unstable();
+    info!("--subsample chose {} of {} sequences: {}", count, names.len(),
+          chosen.iter().map(|&i| names[i].as_str()).collect::<Vec<_>>().join(", "));
+    Ok(match alignment {
+        Alignment::InMemory(records) => {
+            let mut chosen = chosen.into_iter().peekable();
+            Alignment::InMemory(records.into_iter().enumerate().filter_map(|(i, record)| {
+                chosen.next_if_eq(&i).map(|_| record)
+            }).collect())
+        }
+        Alignment::OnDisk(filename, _, mmap) => {
+            let names = chosen.into_iter().map(|i| names[i].clone()).collect();
+            Alignment::OnDisk(filename, Some(SampleSelection { names, pattern: None,
+                                                               include: true }), mmap)
+        }
+    })
+}
+
+
+/// Leaves out any sequences with no bases at all (e.g. a failed sample in an exported alignment),
+/// returning the alignment without them and their names. As with subsample, an alignment on disk
+/// is narrowed down by name, so an empty sequence shares its fate with any others of its name.
+pub fn skip_empty(alignment: Alignment) -> Result<(Alignment, Vec<String>), Error> {
+    let mut names = Vec::new();
+    let mut empty = Vec::new();
+    alignment.for_each_record(|header, seq| {
+        let name = seq_name(header).to_string();
+        if seq.is_empty() { empty.push(name) } else { names.push(name) }
+        Ok(())
+    })?;
+    if empty.is_empty() {
+        return Ok((alignment, empty));
+    }
+    if names.is_empty() {
+        return Err(Error::EmptyInput("empty alignment: all of the sequences are empty"
+                                     .to_string()));
+    }
+    Ok((match alignment {
+        Alignment::InMemory(records) => {
+            Alignment::InMemory(records.into_iter().filter(|r| !r.seq.is_empty()).collect())
+        }
+        Alignment::OnDisk(filename, _, mmap) => {
+            let names = names.into_iter().filter(|name| !empty.contains(name)).collect();
+            Alignment::OnDisk(filename, Some(SampleSelection { names, pattern: None,
+                                                               include: true }), mmap)
+        }
+    }, empty))
+}
+
+
+/// Loads sample names from a file, skipping blank lines and lines starting with #. Only the first
+/// word of each line is used, so a --names file (which includes descriptions) works too.
+fn load_names(filename: &Path) -> Result<HashSet<String>, Error> {
+    Ok(read_lines(filename)?.iter()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| seq_name(line).to_string())
+        .collect())
+}
+
+
+/// Compiles a --include_regex or --exclude_regex pattern, which is an argument error if invalid.
+pub fn parse_regex(pattern: &str) -> Result<Regex, Error> {
+    Regex::new(pattern).map_err(|e| Error::BadArguments(format!("invalid regex {}\n{}",
+                                                                  pattern, e)))
+}
+
+
+/// Returns (in order of first repeat) any names which occur more than once.
+pub fn duplicate_names(seq_names: &[String]) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let mut duplicates = Vec::new();
+    for name in seq_names {
+        if !seen.insert(name.as_str()) && !duplicates.contains(name) {
+            duplicates.push(name.clone());
+        }
+    }
+    duplicates
+}
+
+
+/// Returns a sequence's name, i.e. the first word of its header.
+pub fn seq_name(header: &str) -> &str {
+    header.split_whitespace().next().unwrap_or("")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use tempfile::{TempDir,tempdir};
+    use super::*;
+
+    fn make_test_file(contents: &str) -> (PathBuf, TempDir) {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("samples.txt");
+        let mut file = File::create(&file_path).unwrap();
+        write!(file, "{}", contents).unwrap();
+        (file_path, dir)
+    }
+
+    #[test]
+    fn test_include() {
+        let (path, _dir) = make_test_file("seq_1\n\n# comment\n  seq_3  \n");
+        let selection = SampleSelection::include(&path).unwrap();
+        assert!(selection.is_selected("seq_1"));
+        assert!(selection.is_selected("seq_3 some description"));
+        assert!(!selection.is_selected("seq_2"));
+        assert!(!selection.is_selected("seq_10"));
+    }
+
+    #[test]
+    fn test_exclude() {
+        let (path, _dir) = make_test_file("seq_1\nseq_3 description from --names\n");
+        let selection = SampleSelection::exclude(&path).unwrap();
+        assert!(!selection.is_selected("seq_1"));
+        assert!(!selection.is_selected("seq_3 some description"));
+        assert!(selection.is_selected("seq_2"));
+    }
+
+    #[test]
+    fn test_regex() {
+        let selection = SampleSelection::include_regex("^seq_[13]$").unwrap();
+        assert!(selection.is_selected("seq_1"));
+        assert!(selection.is_selected("seq_3 some description"));
+        assert!(!selection.is_selected("seq_2"));
+        assert!(!selection.is_selected("seq_10"));
+        assert!(selection.missing_names(&["seq_2".to_string()]).is_empty());
+
+        let selection = SampleSelection::exclude_regex("outgroup").unwrap();
+        assert!(!selection.is_selected("my_outgroup_1"));
+        assert!(!selection.is_selected("outgroup description"));
+        assert!(selection.is_selected("seq_1 outgroup"));
+
+        assert!(matches!(SampleSelection::include_regex("seq_("), Err(Error::BadArguments(_))));
+    }
+
+    #[test]
+    fn test_missing_names() {
+        let (path, _dir) = make_test_file("seq_4\nseq_1\nseq_0\n");
+        let selection = SampleSelection::include(&path).unwrap();
+        let seq_names = vec!["seq_1".to_string(), "seq_2".to_string()];
+        assert_eq!(selection.missing_names(&seq_names), vec!["seq_0", "seq_4"]);
+    }
+
+    #[test]
+    fn test_duplicate_names() {
+        let names: Vec<String> = ["a", "b", "c", "b", "a", "b"].iter().map(|s| s.to_string())
+            .collect();
+        assert_eq!(duplicate_names(&names), vec!["b", "a"]);
+        assert!(duplicate_names(&names[..3]).is_empty());
+    }
+
+    #[test]
+    fn test_seq_name() {
+        assert_eq!(seq_name("seq_1 some description"), "seq_1");
+        assert_eq!(seq_name("seq_1"), "seq_1");
+        assert_eq!(seq_name(""), "");
+    }
+
+    #[test]
+    fn test_subsample() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.fasta");
+        let contents: String = (1..=20).map(|i| format!(">seq_{} info\nACGT\n", i)).collect();
+        std::fs::write(&path, contents).unwrap();
+        let names = |alignment: &Alignment| {
+            let mut names = Vec::new();
+            alignment.for_each_record(|header, _| {
+                names.push(seq_name(header).to_string());
+                Ok(())
+            }).unwrap();
+            names
+        };
+
+        // The same seed chooses the same sequences, whether or not the alignment is in memory.
+        let load = |max_memory| Alignment::load(&path, max_memory, None, false).unwrap();
+        let in_memory = subsample(load(1000), 5, 7).unwrap();
+        let on_disk = subsample(load(0), 5, 7).unwrap();
+        assert_eq!(names(&in_memory).len(), 5);
+        assert_eq!(names(&in_memory), names(&on_disk));
+        let again = subsample(load(1000), 5, 7).unwrap();
+        assert_eq!(names(&in_memory), names(&again));
+
+        // Asking for too many sequences keeps them all.
+        let all = subsample(load(1000), 20, 7).unwrap();
+        assert_eq!(names(&all).len(), 20);
+    }
+
+    #[test]
+    fn test_skip_empty() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.fasta");
+        std::fs::write(&path, ">seq_1\nACGT\n>seq_2\n>seq_3 info\nAC-T\n>seq_4\n\n").unwrap();
+        for max_memory in [0, 1000] {
+            let alignment = Alignment::load(&path, max_memory, None, false).unwrap();
+            let (alignment, empty) = skip_empty(alignment).unwrap();
+            assert_eq!(empty, vec!["seq_2".to_string(), "seq_4".to_string()]);
+            let mut names = Vec::new();
+            alignment.for_each_record(|header, _| {
+                names.push(header.to_string());
+                Ok(())
+            }).unwrap();
+            assert_eq!(names, vec!["seq_1".to_string(), "seq_3 info".to_string()]);
+        }
+
+        std::fs::write(&path, ">seq_1\n>seq_2\n").unwrap();
+        assert!(matches!(skip_empty(Alignment::load(&path, 1000, None, false).unwrap()),
+                         Err(Error::EmptyInput(_))));
+    }
+}
@@ -0,0 +1,129 @@
+// Copyright 2023 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Core-SNP-filter
+
+// This file is part of Core-SNP-filter. Core-SNP-filter is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any later version.
+// Core-SNP-filter is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU General Public License for more details. You should have received a copy of the GNU
+// General Public License along with Core-SNP-filter. If not, see <http://www.gnu.org/licenses/>.
+
+use serde::Serialize;
+use std::io;
+use std::path::Path;
+
+use crate::alignment::Alignment;
+use crate::error::Error;
+use crate::filter::{bitvectors_and_counts, is_pars_informative};
+use crate::progress::Progress;
+use crate::samples::seq_name;
+
+
+/// A description of an alignment, made without filtering it.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct AlignmentStats {
+    /// Number of sequences in the alignment
+    pub seq_count: usize,
+    /// Length of the alignment
+    pub alignment_length: usize,
+    /// Parsimony-informative sites in the alignment
+    pub pars_informative: usize,
+    /// Number of sites in each core-fraction bin: 0-10%, 10-20%, ..., 90-100% (each including its
+    /// lower bound but not its upper bound) and lastly exactly 100%
+    pub core_fraction_bins: [usize; 11],
+    /// The name of each sequence and the fraction of its sites with a canonical base, in order
+    pub aligned_fractions: Vec<(String, f64)>,
+}
+
+
+/// Reads the alignment and describes it: its dimensions, how core its sites are, how many of its
+/// sites are parsimony-informative and how much of each sequence is aligned (i.e. has a base).
+pub fn alignment_stats(filename: &Path, max_memory: usize) -> Result<AlignmentStats, Error> {
+    let alignment = Alignment::load(filename, max_memory, None, false)?;
+    let alignment_length = alignment.first_seq_length()?;
+    let counts = bitvectors_and_counts(&alignment, alignment_length, false, None, None, false,
+                                       false, "-.", &Progress::hidden())?;
+
+    let mut core_fraction_bins = [0; 11];
+    for &count in &counts.acgt_counts {
+        let bin = (count * 10) / counts.seq_count;
+        core_fraction_bins[bin] += 1;
+    }
+    let pars_informative = counts.base_counts.iter().filter(|c| is_pars_informative(*c)).count();
+
+    let mut aligned_fractions = Vec::with_capacity(counts.seq_count);
+    alignment.for_each_record(|header, seq| {
+        let bases = seq.iter().filter(|b| b"ACGTacgt".contains(b)).count();
+        aligned_fractions.push((seq_name(header).to_string(),
+                                bases as f64 / alignment_length as f64));
+        Ok(())
+    })?;
+
+    Ok(AlignmentStats { seq_count: counts.seq_count, alignment_length, pars_informative,
+                        core_fraction_bins, aligned_fractions })
+}
+
+
+/// Writes the stats as a series of tab-delimited tables.
+pub fn write_alignment_stats(stats: &AlignmentStats, out: &mut dyn io::Write) -> io::Result<()> {
+    writeln!(out, "sequences\t{}", stats.seq_count)?;
+    writeln!(out, "length\t{}", stats.alignment_length)?;
+    writeln!(out, "parsimony-informative sites\t{}", stats.pars_informative)?;
+    writeln!(out)?;
+    writeln!(out, "core fraction\tsites")?;
+    for (i, count) in stats.core_fraction_bins.iter().enumerate() {
+        if i < 10 {
+            writeln!(out, "{}-{}%\t{}", i * 10, (i + 1) * 10, count)?;
+        } else {
+            writeln!(out, "100%\t{}", count)?;
+        }
+    }
+    writeln!(out)?;
+    writeln!(out, "sample\taligned")?;
+    for (name, fraction) in &stats.aligned_fractions {
+        writeln!(out, "{}\t{:.2}%", name, fraction * 100.0)?;
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use tempfile::{TempDir,tempdir};
+    use super::*;
+
+    fn make_test_file(contents: &str) -> (PathBuf, TempDir) {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("test.fasta");
+        let mut file = File::create(&file_path).unwrap();
+        write!(file, "{}", contents).unwrap();
+        (file_path, dir)
+    }
+
+    #[test]
+    fn test_alignment_stats() {
+        let (path, _dir) = make_test_file(">seq_1 info\nACGATCAG\n\
+                                           >seq_2\nAC----GA\n\
+                                           >seq_3\nAGGATCAG\n\
+                                           >seq_4\nAGGNTCAA\n");
+        let stats = alignment_stats(&path, 1000).unwrap();
+        assert_eq!(stats.seq_count, 4);
+        assert_eq!(stats.alignment_length, 8);
+        assert_eq!(stats.pars_informative, 2);
+        assert_eq!(stats.core_fraction_bins, [0, 0, 0, 0, 0, 1, 0, 3, 0, 0, 4]);
+        assert_eq!(stats.aligned_fractions,
+                   vec![("seq_1".to_string(), 1.0), ("seq_2".to_string(), 0.5),
+                        ("seq_3".to_string(), 1.0), ("seq_4".to_string(), 0.875)]);
+
+        let mut out = Vec::new();
+        write_alignment_stats(&stats, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("sequences\t4\nlength\t8\nparsimony-informative sites\t2\n"));
+        assert!(out.contains("\n70-80%\t3\n80-90%\t0\n90-100%\t0\n100%\t4\n"));
+        assert!(out.ends_with("\nseq_4\t87.50%\n"));
+    }
+}